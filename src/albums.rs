@@ -0,0 +1,212 @@
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A user-defined collection of images, referenced by content hash so moves and renames don't break it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Album {
+    pub id: String,
+    pub name: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    /// Content hashes in the order they were added.
+    pub images: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AlbumSummary {
+    pub id: String,
+    pub name: String,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub image_count: usize,
+    pub cover_hash: Option<String>,
+}
+
+impl From<&Album> for AlbumSummary {
+    fn from(album: &Album) -> Self {
+        Self {
+            id: album.id.clone(),
+            name: album.name.clone(),
+            created_at: album.created_at,
+            updated_at: album.updated_at,
+            image_count: album.images.len(),
+            cover_hash: album.images.first().cloned(),
+        }
+    }
+}
+
+/// Albums kept in memory and written back to a single JSON file after every change.
+pub struct AlbumStore {
+    path: PathBuf,
+    albums: Mutex<BTreeMap<String, Album>>,
+    next_id: AtomicU64,
+}
+
+impl AlbumStore {
+    pub fn new(path: &str) -> Result<Self, AppError> {
+        let path = PathBuf::from(path);
+        let albums = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice::<Vec<Album>>(&bytes)?
+                .into_iter()
+                .map(|album| (album.id.clone(), album))
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(AppError::Io(e)),
+        };
+        log::info!("Loaded {} albums from {:?}", albums.len(), path);
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        Ok(Self {
+            path,
+            albums: Mutex::new(albums),
+            next_id: AtomicU64::new(seed),
+        })
+    }
+
+    /// Lists every album, sorted by name.
+    pub fn list(&self) -> Vec<AlbumSummary> {
+        let albums = self.albums.lock().unwrap();
+        let mut summaries: Vec<AlbumSummary> = albums.values().map(AlbumSummary::from).collect();
+        summaries.sort_by_key(|a| a.name.to_lowercase());
+        summaries
+    }
+
+    pub fn get(&self, id: &str) -> Result<Album, AppError> {
+        self.albums
+            .lock()
+            .unwrap()
+            .get(id)
+            .cloned()
+            .ok_or_else(|| not_found(id))
+    }
+
+    /// Looks an album up by ID, or else by case-insensitive name.
+    pub fn find(&self, id_or_name: &str) -> Option<Album> {
+        let albums = self.albums.lock().unwrap();
+        albums.get(id_or_name).cloned().or_else(|| {
+            albums
+                .values()
+                .find(|a| a.name.eq_ignore_ascii_case(id_or_name))
+                .cloned()
+        })
+    }
+
+    pub fn create(&self, name: &str) -> Result<Album, AppError> {
+        let mut albums = self.albums.lock().unwrap();
+        let name = validate_name(&albums, name, None)?;
+        let now = now();
+        let album = Album {
+            id: format!("{:x}", self.next_id.fetch_add(1, Ordering::Relaxed)),
+            name,
+            created_at: now,
+            updated_at: now,
+            images: Vec::new(),
+        };
+        albums.insert(album.id.clone(), album.clone());
+        self.save(&albums)?;
+        log::info!("Created album {} ({})", album.name, album.id);
+        Ok(album)
+    }
+
+    pub fn rename(&self, id: &str, name: &str) -> Result<Album, AppError> {
+        self.update(id, |albums| validate_name(albums, name, Some(id)), |album, name| {
+            album.name = name;
+        })
+    }
+
+    pub fn delete(&self, id: &str) -> Result<Album, AppError> {
+        let mut albums = self.albums.lock().unwrap();
+        let album = albums.remove(id).ok_or_else(|| not_found(id))?;
+        self.save(&albums)?;
+        log::info!("Deleted album {} ({})", album.name, album.id);
+        Ok(album)
+    }
+
+    /// Appends the hashes that are not in the album yet. Returns the album and the hashes added.
+    pub fn add_images(&self, id: &str, hashes: &[String]) -> Result<(Album, Vec<String>), AppError> {
+        let mut added = Vec::new();
+        let album = self.update(id, |_| Ok(()), |album, _| {
+            for hash in hashes {
+                if !album.images.contains(hash) && !added.contains(hash) {
+                    album.images.push(hash.clone());
+                    added.push(hash.clone());
+                }
+            }
+        })?;
+        Ok((album, added))
+    }
+
+    /// Removes the given hashes. Returns the album and the hashes that were actually in it.
+    pub fn remove_images(&self, id: &str, hashes: &[String]) -> Result<(Album, Vec<String>), AppError> {
+        let mut removed = Vec::new();
+        let album = self.update(id, |_| Ok(()), |album, _| {
+            album.images.retain(|hash| {
+                let remove = hashes.contains(hash);
+                if remove {
+                    removed.push(hash.clone());
+                }
+                !remove
+            });
+        })?;
+        Ok((album, removed))
+    }
+
+    /// Validates under the lock, applies `change`, bumps `updated_at` and persists.
+    fn update<T>(
+        &self,
+        id: &str,
+        validate: impl FnOnce(&BTreeMap<String, Album>) -> Result<T, AppError>,
+        change: impl FnOnce(&mut Album, T),
+    ) -> Result<Album, AppError> {
+        let mut albums = self.albums.lock().unwrap();
+        if !albums.contains_key(id) {
+            return Err(not_found(id));
+        }
+        let value = validate(&albums)?;
+        let album = albums.get_mut(id).unwrap();
+        change(album, value);
+        album.updated_at = now();
+        let album = album.clone();
+        self.save(&albums)?;
+        Ok(album)
+    }
+
+    /// Writes the whole store to a temporary file and renames it into place.
+    fn save(&self, albums: &BTreeMap<String, Album>) -> Result<(), AppError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        let list: Vec<&Album> = albums.values().collect();
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(&list)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Trims the name and rejects empty names and names used by another album.
+fn validate_name(albums: &BTreeMap<String, Album>, name: &str, id: Option<&str>) -> Result<String, AppError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(AppError::BadRequest("Album name must not be empty".to_string()));
+    }
+    if albums
+        .values()
+        .any(|a| a.name.eq_ignore_ascii_case(name) && Some(a.id.as_str()) != id)
+    {
+        return Err(AppError::BadRequest(format!("An album named {} already exists", name)));
+    }
+    Ok(name.to_string())
+}
+
+fn not_found(id: &str) -> AppError {
+    AppError::NotFound(format!("Album {} not found", id))
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
@@ -0,0 +1,553 @@
+use crate::colors::ColorInfo;
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::iptc;
+use crate::metadata::ImageMetadata;
+use crate::perceptual;
+use crate::places::Gazetteer;
+use crate::processor;
+use crate::quality::QualityMetrics;
+use crate::similarity;
+use crate::thumbnails;
+use crate::xmp;
+use exif::{Exif, Reader};
+use image::DynamicImage;
+use serde::Serialize;
+use std::cell::OnceCell;
+use std::collections::BTreeSet;
+use std::io::Cursor;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+pub const HASH: &str = "hash";
+
+/// One processing stage: extracts some of an image's metadata from its file.
+///
+/// Analyzers only write the fields they own, so any one of them can be re-run on an
+/// already indexed image without touching what the others extracted.
+pub trait Analyzer: Send + Sync {
+    /// Stable name used in the configuration and the API.
+    fn name(&self) -> &'static str;
+    fn description(&self) -> &'static str;
+    /// Bump whenever the analyzer's output changes, so reprocessing picks up the images
+    /// analyzed by an older version.
+    fn version(&self) -> u32 {
+        1
+    }
+    /// Analyzers whose output this one reads from the metadata. It is re-run whenever they are.
+    fn inputs(&self) -> &'static [&'static str] {
+        &[]
+    }
+    /// Required analyzers cannot be disabled, and an image they fail on is not indexed.
+    fn required(&self) -> bool {
+        false
+    }
+    fn analyze(&self, input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError>;
+}
+
+/// The file being analyzed. Its content, EXIF data and decoded image are read on first
+/// use and shared by every analyzer.
+pub struct AnalysisInput<'a> {
+    pub path: &'a Path,
+    pub config: &'a AppConfig,
+    bytes: OnceCell<Vec<u8>>,
+    exif: OnceCell<Option<Exif>>,
+    image: OnceCell<Result<DynamicImage, String>>,
+    thumbnail: OnceCell<DynamicImage>,
+}
+
+impl<'a> AnalysisInput<'a> {
+    pub fn new(config: &'a AppConfig, path: &'a Path) -> Self {
+        Self {
+            path,
+            config,
+            bytes: OnceCell::new(),
+            exif: OnceCell::new(),
+            image: OnceCell::new(),
+            thumbnail: OnceCell::new(),
+        }
+    }
+
+    pub fn bytes(&self) -> Result<&[u8], AppError> {
+        if let Some(bytes) = self.bytes.get() {
+            return Ok(bytes);
+        }
+        let bytes = std::fs::read(self.path)?;
+        Ok(self.bytes.get_or_init(|| bytes))
+    }
+
+    pub fn exif(&self) -> Result<Option<&Exif>, AppError> {
+        if let Some(exif) = self.exif.get() {
+            return Ok(exif.as_ref());
+        }
+        let exif = Reader::new().read_from_container(&mut Cursor::new(self.bytes()?)).ok();
+        Ok(self.exif.get_or_init(|| exif).as_ref())
+    }
+
+    /// The full decoded image. A decoding failure is remembered, so it is only attempted once.
+    pub fn image(&self) -> Result<&DynamicImage, AppError> {
+        let image = match self.image.get() {
+            Some(image) => image,
+            None => {
+                let image = thumbnails::open_image(self.path).map_err(|e| e.to_string());
+                self.image.get_or_init(|| image)
+            }
+        };
+        image
+            .as_ref()
+            .map_err(|e| AppError::Generic(format!("Could not decode {:?}: {}", self.path, e)))
+    }
+
+    pub fn thumbnail(&self) -> Result<&DynamicImage, AppError> {
+        if let Some(thumbnail) = self.thumbnail.get() {
+            return Ok(thumbnail);
+        }
+        let thumbnail = thumbnails::make_thumbnail(self.image()?);
+        Ok(self.thumbnail.get_or_init(|| thumbnail))
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct AnalyzerStats {
+    pub runs: u64,
+    pub failures: u64,
+    pub total_ms: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AnalyzerInfo {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub version: u32,
+    pub required: bool,
+    pub enabled: bool,
+    #[serde(flatten)]
+    pub stats: AnalyzerStats,
+}
+
+struct Stage {
+    analyzer: Box<dyn Analyzer>,
+    enabled: bool,
+    stats: Mutex<AnalyzerStats>,
+}
+
+/// Every analyzer in the order they run, with the ones listed in `disabled_analyzers` switched off.
+pub struct AnalyzerRegistry {
+    stages: Vec<Stage>,
+}
+
+impl AnalyzerRegistry {
+    pub fn new(config: &AppConfig, gazetteer: Arc<Gazetteer>) -> Result<Self, AppError> {
+        let analyzers: Vec<Box<dyn Analyzer>> = vec![
+            Box::new(HashAnalyzer),
+            Box::new(FileAnalyzer),
+            Box::new(ExifAnalyzer),
+            Box::new(PlaceAnalyzer { gazetteer }),
+            Box::new(XmpAnalyzer),
+            Box::new(IptcAnalyzer),
+            Box::new(ThumbnailAnalyzer),
+            Box::new(QualityAnalyzer),
+            Box::new(ColorsAnalyzer),
+            Box::new(SimilarityAnalyzer),
+        ];
+        for name in &config.disabled_analyzers {
+            match analyzers.iter().find(|a| a.name() == name) {
+                None => return Err(AppError::Generic(format!("Unknown analyzer: {}", name))),
+                Some(a) if a.required() => {
+                    return Err(AppError::Generic(format!("Analyzer {} cannot be disabled", name)))
+                }
+                Some(_) => {}
+            }
+        }
+        let stages = analyzers
+            .into_iter()
+            .map(|analyzer| Stage {
+                enabled: !config.disabled_analyzers.contains(analyzer.name()),
+                analyzer,
+                stats: Mutex::new(AnalyzerStats::default()),
+            })
+            .collect();
+        Ok(Self { stages })
+    }
+
+    pub fn list(&self) -> Vec<AnalyzerInfo> {
+        self.stages
+            .iter()
+            .map(|stage| AnalyzerInfo {
+                name: stage.analyzer.name(),
+                description: stage.analyzer.description(),
+                version: stage.analyzer.version(),
+                required: stage.analyzer.required(),
+                enabled: stage.enabled,
+                stats: stage.stats.lock().unwrap().clone(),
+            })
+            .collect()
+    }
+
+    /// Fails unless every name is an analyzer that can be re-run on indexed images.
+    pub fn check_rerunnable<'n>(&self, names: impl IntoIterator<Item = &'n String>) -> Result<(), AppError> {
+        for name in names {
+            if name == HASH {
+                return Err(AppError::BadRequest("The hash identifies an image and cannot be re-computed".to_string()));
+            }
+            if !self.stages.iter().any(|s| s.analyzer.name() == name) {
+                return Err(AppError::NotFound(format!("Unknown analyzer: {}", name)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Enabled analyzers that never ran successfully on the image or ran in an older version.
+    pub fn outdated(&self, metadata: &ImageMetadata) -> BTreeSet<String> {
+        self.stages
+            .iter()
+            .filter(|s| s.enabled && s.analyzer.name() != HASH)
+            .filter(|s| metadata.analyzer_versions.get(s.analyzer.name()) != Some(&s.analyzer.version()))
+            .map(|s| s.analyzer.name().to_string())
+            .collect()
+    }
+
+    /// Runs every enabled analyzer in order, stopping and returning false as soon as `skip`
+    /// returns true, e.g. once the hash shows the image is already indexed. Failures of
+    /// optional analyzers are logged and leave their fields unset.
+    pub fn analyze(
+        &self,
+        input: &AnalysisInput,
+        metadata: &mut ImageMetadata,
+        skip: impl Fn(&ImageMetadata) -> bool,
+    ) -> Result<bool, AppError> {
+        for stage in self.stages.iter().filter(|s| s.enabled) {
+            if let Err(e) = self.run_stage(stage, input, metadata) {
+                if stage.analyzer.required() {
+                    return Err(e);
+                }
+                log::warn!("Analyzer {} failed on {:?}: {}", stage.analyzer.name(), input.path, e);
+            }
+            if skip(metadata) {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Re-runs the named analyzers, enabled or not, followed by the enabled analyzers that read
+    /// their output. Stops at the first failure, leaving `metadata` partly updated.
+    pub fn reanalyze(
+        &self,
+        names: &BTreeSet<String>,
+        input: &AnalysisInput,
+        metadata: &mut ImageMetadata,
+    ) -> Result<(), AppError> {
+        let mut ran: Vec<&str> = Vec::new();
+        for stage in &self.stages {
+            let name = stage.analyzer.name();
+            let dependent = stage.enabled && stage.analyzer.inputs().iter().any(|i| ran.contains(i));
+            if names.contains(name) || dependent {
+                self.run_stage(stage, input, metadata)
+                    .map_err(|e| AppError::Generic(format!("Analyzer {} failed: {}", name, e)))?;
+                ran.push(name);
+            }
+        }
+        Ok(())
+    }
+
+    fn run_stage(&self, stage: &Stage, input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError> {
+        let started = Instant::now();
+        let result = stage.analyzer.analyze(input, metadata);
+        let elapsed = started.elapsed();
+        log::trace!("Analyzer {} took {:?} on {:?}", stage.analyzer.name(), elapsed, input.path);
+
+        let mut stats = stage.stats.lock().unwrap();
+        stats.runs += 1;
+        stats.failures += result.is_err() as u64;
+        stats.total_ms += elapsed.as_secs_f64() * 1000.0;
+        if result.is_ok() {
+            metadata
+                .analyzer_versions
+                .insert(stage.analyzer.name().to_string(), stage.analyzer.version());
+        }
+        result
+    }
+}
+
+struct HashAnalyzer;
+
+impl Analyzer for HashAnalyzer {
+    fn name(&self) -> &'static str {
+        HASH
+    }
+
+    fn description(&self) -> &'static str {
+        "SHA-256 digest of the file content, which identifies the image"
+    }
+
+    fn required(&self) -> bool {
+        true
+    }
+
+    fn analyze(&self, input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError> {
+        // Streamed, so images skipped as already indexed are never read into memory whole.
+        metadata.file_hash = processor::hash_file(input.path)?;
+        Ok(())
+    }
+}
+
+struct FileAnalyzer;
+
+impl Analyzer for FileAnalyzer {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    fn description(&self) -> &'static str {
+        "Dimensions, size, timestamps, extension and MIME type"
+    }
+
+    fn required(&self) -> bool {
+        true
+    }
+
+    fn analyze(&self, input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError> {
+        let (width, height) = image::image_dimensions(input.path)?;
+        let file_info = std::fs::metadata(input.path)?;
+        metadata.width = width;
+        metadata.height = height;
+        metadata.file_size = file_info.len();
+        metadata.modified_at = file_info.modified().ok().map(unix_seconds);
+        metadata.created_at = created_at(&file_info);
+        metadata.extension = input
+            .path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        metadata.mime_type = mime_guess::from_path(input.path).first_or_octet_stream().to_string();
+        Ok(())
+    }
+}
+
+struct ExifAnalyzer;
+
+impl Analyzer for ExifAnalyzer {
+    fn name(&self) -> &'static str {
+        "exif"
+    }
+
+    fn description(&self) -> &'static str {
+        "Camera make and model, capture time and GPS position from EXIF"
+    }
+
+    fn analyze(&self, input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError> {
+        let exif = input.exif()?;
+        let text = |tag| exif?.get_field(tag, exif::In::PRIMARY).map(|f| f.display_value().to_string());
+        metadata.has_exif = exif.is_some();
+        metadata.camera_make = text(exif::Tag::Make);
+        metadata.camera_model = text(exif::Tag::Model);
+        metadata.date_taken = text(exif::Tag::DateTimeOriginal);
+        metadata.gps_latitude =
+            exif.and_then(|e| gps_coordinate(e, exif::Tag::GPSLatitude, exif::Tag::GPSLatitudeRef, b'S'));
+        metadata.gps_longitude =
+            exif.and_then(|e| gps_coordinate(e, exif::Tag::GPSLongitude, exif::Tag::GPSLongitudeRef, b'W'));
+        Ok(())
+    }
+}
+
+struct PlaceAnalyzer {
+    gazetteer: Arc<Gazetteer>,
+}
+
+impl Analyzer for PlaceAnalyzer {
+    fn name(&self) -> &'static str {
+        "place"
+    }
+
+    fn description(&self) -> &'static str {
+        "Country, region and city nearest to the GPS position"
+    }
+
+    fn inputs(&self) -> &'static [&'static str] {
+        &["exif"]
+    }
+
+    fn analyze(&self, _input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError> {
+        metadata.place = match (metadata.gps_latitude, metadata.gps_longitude) {
+            (Some(latitude), Some(longitude)) => self.gazetteer.lookup(latitude, longitude),
+            _ => None,
+        };
+        Ok(())
+    }
+}
+
+struct XmpAnalyzer;
+
+impl Analyzer for XmpAnalyzer {
+    fn name(&self) -> &'static str {
+        "xmp"
+    }
+
+    fn description(&self) -> &'static str {
+        "Keywords, rating, caption and regions from embedded XMP and sidecars"
+    }
+
+    fn analyze(&self, input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError> {
+        metadata.xmp = xmp::read_for_image(input.path);
+        Ok(())
+    }
+}
+
+struct IptcAnalyzer;
+
+impl Analyzer for IptcAnalyzer {
+    fn name(&self) -> &'static str {
+        "iptc"
+    }
+
+    fn description(&self) -> &'static str {
+        "Caption, keywords, credits and location from IPTC-IIM records"
+    }
+
+    fn analyze(&self, input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError> {
+        metadata.iptc = iptc::read_for_image(input.path)?;
+        Ok(())
+    }
+}
+
+struct ThumbnailAnalyzer;
+
+impl Analyzer for ThumbnailAnalyzer {
+    fn name(&self) -> &'static str {
+        "thumbnail"
+    }
+
+    fn description(&self) -> &'static str {
+        "Writes the JPEG thumbnail"
+    }
+
+    fn analyze(&self, input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError> {
+        let thumbnail_dir = Path::new(&input.config.thumbnail_directory);
+        let thumbnail_path = thumbnails::thumbnail_path_for(thumbnail_dir, &metadata.file_hash);
+        thumbnails::save_thumbnail(input.thumbnail()?, &thumbnail_path)?;
+        metadata.thumbnail_path = thumbnail_path.to_string_lossy().to_string();
+        Ok(())
+    }
+}
+
+struct QualityAnalyzer;
+
+impl Analyzer for QualityAnalyzer {
+    fn name(&self) -> &'static str {
+        "quality"
+    }
+
+    fn description(&self) -> &'static str {
+        "Sharpness, noise and exposure scores of the full image"
+    }
+
+    fn analyze(&self, input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError> {
+        metadata.quality = QualityMetrics::analyze(input.image()?);
+        Ok(())
+    }
+}
+
+struct ColorsAnalyzer;
+
+impl Analyzer for ColorsAnalyzer {
+    fn name(&self) -> &'static str {
+        "colors"
+    }
+
+    fn description(&self) -> &'static str {
+        "Average and dominant colors of the thumbnail"
+    }
+
+    fn analyze(&self, input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError> {
+        // The thumbnail is plenty for a palette and much cheaper than the full image.
+        metadata.colors = ColorInfo::analyze(input.thumbnail()?);
+        Ok(())
+    }
+}
+
+struct SimilarityAnalyzer;
+
+impl Analyzer for SimilarityAnalyzer {
+    fn name(&self) -> &'static str {
+        "similarity"
+    }
+
+    fn description(&self) -> &'static str {
+        "Perceptual hash and color histogram for burst grouping and similar images"
+    }
+
+    fn analyze(&self, input: &AnalysisInput, metadata: &mut ImageMetadata) -> Result<(), AppError> {
+        let thumbnail = input.thumbnail()?;
+        metadata.perceptual_hash = Some(perceptual::to_hex(perceptual::dhash(thumbnail)));
+        metadata.color_histogram = Some(similarity::color_histogram(thumbnail));
+        Ok(())
+    }
+}
+
+/// Converts degrees, minutes and seconds to signed decimal degrees, negative towards `negative_ref`.
+fn gps_coordinate(exif: &Exif, tag: exif::Tag, ref_tag: exif::Tag, negative_ref: u8) -> Option<f64> {
+    let field = exif.get_field(tag, exif::In::PRIMARY)?;
+    let exif::Value::Rational(dms) = &field.value else {
+        return None;
+    };
+    let degrees = dms
+        .iter()
+        .zip([1.0, 60.0, 3600.0])
+        .map(|(value, divisor)| value.to_f64() / divisor)
+        .sum::<f64>();
+    let negative = matches!(
+        exif.get_field(ref_tag, exif::In::PRIMARY).map(|f| &f.value),
+        Some(exif::Value::Ascii(values)) if values.first().and_then(|v| v.first()) == Some(&negative_ref)
+    );
+    Some(if negative { -degrees } else { degrees })
+}
+
+/// Moves everything the analyzers write from `analyzed` onto `target`, leaving its annotations,
+/// copies, burst group and the rest as they are.
+pub fn copy_analyzed_fields(analyzed: ImageMetadata, target: &mut ImageMetadata) {
+    target.width = analyzed.width;
+    target.height = analyzed.height;
+    target.file_size = analyzed.file_size;
+    target.modified_at = analyzed.modified_at;
+    target.created_at = analyzed.created_at;
+    target.extension = analyzed.extension;
+    target.mime_type = analyzed.mime_type;
+    target.has_exif = analyzed.has_exif;
+    target.camera_make = analyzed.camera_make;
+    target.camera_model = analyzed.camera_model;
+    target.date_taken = analyzed.date_taken;
+    target.gps_latitude = analyzed.gps_latitude;
+    target.gps_longitude = analyzed.gps_longitude;
+    target.place = analyzed.place;
+    target.xmp = analyzed.xmp;
+    target.iptc = analyzed.iptc;
+    target.thumbnail_path = analyzed.thumbnail_path;
+    target.quality = analyzed.quality;
+    target.colors = analyzed.colors;
+    target.perceptual_hash = analyzed.perceptual_hash;
+    target.color_histogram = analyzed.color_histogram;
+    target.analyzer_versions = analyzed.analyzer_versions;
+}
+
+pub fn unix_seconds(time: std::time::SystemTime) -> i64 {
+    match time.duration_since(std::time::UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(e) => -(e.duration().as_secs() as i64),
+    }
+}
+
+/// Birth time where the filesystem records it, otherwise the inode change time.
+fn created_at(file_info: &std::fs::Metadata) -> Option<i64> {
+    if let Ok(created) = file_info.created() {
+        return Some(unix_seconds(created));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        Some(file_info.ctime())
+    }
+    #[cfg(not(unix))]
+    None
+}
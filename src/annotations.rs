@@ -0,0 +1,223 @@
+use crate::error::AppError;
+use crate::metadata::ImageMetadata;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const MAX_RATING: u8 = 5;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorLabel {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl ColorLabel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorLabel::Red => "red",
+            ColorLabel::Yellow => "yellow",
+            ColorLabel::Green => "green",
+            ColorLabel::Blue => "blue",
+            ColorLabel::Purple => "purple",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        serde_json::from_value(serde_json::Value::String(value.to_lowercase())).ok()
+    }
+}
+
+/// What users attach to an image by hand. Kept outside the index so it survives re-indexing.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Annotations {
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// 0 to 5 stars; 0 means unrated.
+    #[serde(default)]
+    pub rating: u8,
+    #[serde(default)]
+    pub color_label: Option<ColorLabel>,
+    #[serde(default)]
+    pub favorite: bool,
+}
+
+impl Annotations {
+    pub fn is_empty(&self) -> bool {
+        *self == Annotations::default()
+    }
+}
+
+/// A partial update. Fields that are left out keep their current value; `color_label: null` clears it.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct AnnotationPatch {
+    /// Replaces all tags.
+    pub tags: Option<Vec<String>>,
+    #[serde(default)]
+    pub add_tags: Vec<String>,
+    #[serde(default)]
+    pub remove_tags: Vec<String>,
+    pub rating: Option<u8>,
+    #[serde(default, deserialize_with = "present")]
+    pub color_label: Option<Option<ColorLabel>>,
+    pub favorite: Option<bool>,
+}
+
+/// Tells a field set to `null` apart from a missing one.
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+impl AnnotationPatch {
+    pub fn validate(&self) -> Result<(), AppError> {
+        match self.rating {
+            Some(rating) if rating > MAX_RATING => Err(AppError::BadRequest(format!(
+                "rating must be between 0 and {}",
+                MAX_RATING
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn apply(&self, annotations: &mut Annotations) {
+        if let Some(tags) = &self.tags {
+            annotations.tags.clear();
+            add_tags(&mut annotations.tags, tags);
+        }
+        add_tags(&mut annotations.tags, &self.add_tags);
+        annotations
+            .tags
+            .retain(|tag| !self.remove_tags.iter().any(|r| r.trim() == tag));
+        if let Some(rating) = self.rating {
+            annotations.rating = rating;
+        }
+        if let Some(color_label) = self.color_label {
+            annotations.color_label = color_label;
+        }
+        if let Some(favorite) = self.favorite {
+            annotations.favorite = favorite;
+        }
+    }
+}
+
+/// Adds trimmed, non-empty tags that are not present yet.
+fn add_tags(tags: &mut Vec<String>, new_tags: &[String]) {
+    for tag in new_tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty()) {
+        if !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+}
+
+/// Annotation, keyword and IPTC credit and location counts over a set of images.
+#[derive(Serialize, Debug, Default)]
+pub struct Facets {
+    pub total: u64,
+    pub tags: BTreeMap<String, u64>,
+    pub ratings: BTreeMap<u8, u64>,
+    pub color_labels: BTreeMap<String, u64>,
+    pub favorites: u64,
+    /// XMP and IPTC keywords.
+    pub keywords: BTreeMap<String, u64>,
+    pub bylines: BTreeMap<String, u64>,
+    pub cities: BTreeMap<String, u64>,
+    pub countries: BTreeMap<String, u64>,
+}
+
+impl Facets {
+    pub fn add(&mut self, metadata: &ImageMetadata) {
+        let annotations = &metadata.annotations;
+        self.total += 1;
+        for tag in &annotations.tags {
+            *self.tags.entry(tag.clone()).or_default() += 1;
+        }
+        *self.ratings.entry(annotations.rating).or_default() += 1;
+        if let Some(color_label) = annotations.color_label {
+            *self.color_labels.entry(color_label.as_str().to_string()).or_default() += 1;
+        }
+        if annotations.favorite {
+            self.favorites += 1;
+        }
+        for keyword in metadata.keywords() {
+            *self.keywords.entry(keyword.to_string()).or_default() += 1;
+        }
+        let iptc = &metadata.iptc;
+        let mut bylines: Vec<&String> = iptc.bylines.iter().collect();
+        bylines.sort();
+        bylines.dedup();
+        for byline in bylines {
+            *self.bylines.entry(byline.clone()).or_default() += 1;
+        }
+        if let Some(city) = &iptc.city {
+            *self.cities.entry(city.clone()).or_default() += 1;
+        }
+        if let Some(country) = &iptc.country {
+            *self.countries.entry(country.clone()).or_default() += 1;
+        }
+    }
+}
+
+/// Annotations by content hash, written back to a single JSON file after every change.
+pub struct AnnotationStore {
+    path: PathBuf,
+    annotations: Mutex<BTreeMap<String, Annotations>>,
+}
+
+impl AnnotationStore {
+    pub fn new(path: &str) -> Result<Self, AppError> {
+        let path = PathBuf::from(path);
+        let annotations: BTreeMap<String, Annotations> = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(AppError::Io(e)),
+        };
+        log::info!("Loaded annotations for {} images from {:?}", annotations.len(), path);
+        Ok(Self {
+            path,
+            annotations: Mutex::new(annotations),
+        })
+    }
+
+    pub fn get(&self, hash: &str) -> Annotations {
+        self.annotations.lock().unwrap().get(hash).cloned().unwrap_or_default()
+    }
+
+    /// Applies `patch` to every hash and persists once. Returns the new annotations per hash.
+    pub fn update(&self, hashes: &[String], patch: &AnnotationPatch) -> Result<Vec<Annotations>, AppError> {
+        patch.validate()?;
+        let mut annotations = self.annotations.lock().unwrap();
+        let mut updated = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            let mut current = annotations.get(hash).cloned().unwrap_or_default();
+            patch.apply(&mut current);
+            if current.is_empty() {
+                annotations.remove(hash);
+            } else {
+                annotations.insert(hash.clone(), current.clone());
+            }
+            updated.push(current);
+        }
+        self.save(&annotations)?;
+        Ok(updated)
+    }
+
+    /// Writes the whole store to a temporary file and renames it into place.
+    fn save(&self, annotations: &BTreeMap<String, Annotations>) -> Result<(), AppError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = self.path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, serde_json::to_vec_pretty(annotations)?)?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
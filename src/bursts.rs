@@ -0,0 +1,226 @@
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::jobs::JobRegistry;
+use crate::perceptual;
+use crate::quality::QualityMetrics;
+use crate::search::Searcher;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+#[derive(Serialize, Debug, Default)]
+pub struct GroupReport {
+    /// Images with a capture time, camera and perceptual hash.
+    pub candidates: u64,
+    pub groups: u64,
+    pub grouped_images: u64,
+    /// Documents whose group or best pick changed.
+    pub updated: u64,
+}
+
+/// The fields of a document that grouping reads, so regrouping never loads whole documents.
+#[derive(Deserialize, Debug, Default)]
+pub struct BurstFields {
+    pub file_hash: String,
+    #[serde(default)]
+    pub camera_make: Option<String>,
+    #[serde(default)]
+    pub camera_model: Option<String>,
+    #[serde(default)]
+    pub date_taken: Option<String>,
+    #[serde(default)]
+    pub perceptual_hash: Option<String>,
+    #[serde(default)]
+    pub group_id: Option<String>,
+    #[serde(default)]
+    pub best_pick: bool,
+    #[serde(flatten)]
+    pub quality: QualityMetrics,
+}
+
+impl BurstFields {
+    /// The stored fields `BurstFields` is read from.
+    pub const SOURCE_FIELDS: [&'static str; 12] = [
+        "file_hash",
+        "camera_make",
+        "camera_model",
+        "date_taken",
+        "perceptual_hash",
+        "group_id",
+        "best_pick",
+        "sharpness",
+        "noise",
+        "mean_luminance",
+        "clipped_highlights",
+        "clipped_shadows",
+    ];
+}
+
+struct Candidate {
+    index: usize,
+    camera: (Option<String>, Option<String>),
+    taken_at: i64,
+    hash: u64,
+}
+
+/// Recomputes every burst group and best pick over the whole library and updates the documents that changed.
+///
+/// Shots join a group when they come from the same camera make and model, were taken at most
+/// `burst_interval_seconds` after the previous shot in the group, and their perceptual hashes
+/// differ from it in at most `burst_max_hash_distance` bits. The sharpest, least clipped shot is
+/// the group's best pick.
+pub async fn regroup(
+    searcher: &Arc<dyn Searcher>,
+    config: &AppConfig,
+    jobs: &JobRegistry,
+    job_id: &str,
+) -> Result<GroupReport, AppError> {
+    let mut images = searcher.get_burst_fields().await?;
+    let mut candidates: Vec<Candidate> = images
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| m.camera_make.is_some() || m.camera_model.is_some())
+        .filter_map(|(index, m)| {
+            Some(Candidate {
+                index,
+                camera: (m.camera_make.clone(), m.camera_model.clone()),
+                taken_at: parse_date_taken(m.date_taken.as_deref()?)?,
+                hash: perceptual::from_hex(m.perceptual_hash.as_deref()?)?,
+            })
+        })
+        .collect();
+    candidates.sort_by(|a, b| {
+        (&a.camera, a.taken_at, &images[a.index].file_hash).cmp(&(&b.camera, b.taken_at, &images[b.index].file_hash))
+    });
+
+    let mut report = GroupReport {
+        candidates: candidates.len() as u64,
+        ..Default::default()
+    };
+    let mut assignments: Vec<(Option<String>, bool)> = vec![(None, false); images.len()];
+    let mut group: Vec<&Candidate> = Vec::new();
+    for candidate in candidates.iter().map(Some).chain([None]) {
+        let joins = match (candidate, group.last()) {
+            (Some(c), Some(previous)) => {
+                c.camera == previous.camera
+                    && c.taken_at - previous.taken_at <= config.burst_interval_seconds as i64
+                    && perceptual::distance(c.hash, previous.hash) <= config.burst_max_hash_distance
+            }
+            _ => false,
+        };
+        if !joins {
+            if group.len() > 1 {
+                let group_id = images[group[0].index].file_hash.clone();
+                // Ties go to the earliest shot.
+                let score = |c: &Candidate| images[c.index].quality.score();
+                let best = group
+                    .iter()
+                    .enumerate()
+                    .max_by(|(i, a), (j, b)| score(a).total_cmp(&score(b)).then(j.cmp(i)))
+                    .map(|(_, c)| c.index)
+                    .unwrap();
+                for member in &group {
+                    assignments[member.index] = (Some(group_id.clone()), member.index == best);
+                }
+                report.groups += 1;
+                report.grouped_images += group.len() as u64;
+            }
+            group.clear();
+        }
+        group.extend(candidate);
+    }
+
+    let changed: Vec<(String, (Option<String>, bool))> = images
+        .drain(..)
+        .zip(assignments)
+        .filter(|(m, (group_id, best_pick))| m.group_id != *group_id || m.best_pick != *best_pick)
+        .map(|(m, assignment)| (m.file_hash, assignment))
+        .collect();
+    let total = changed.len() as u64;
+    for (done, (hash, (group_id, best_pick))) in changed.into_iter().enumerate() {
+        jobs.set_progress(job_id, done as u64, total);
+        // Re-read the document so edits made while grouping ran are not overwritten.
+        let Some(mut metadata) = searcher.get_by_hash(&hash).await? else { continue };
+        metadata.group_id = group_id;
+        metadata.best_pick = best_pick;
+        searcher.update_document(metadata).await?;
+        report.updated += 1;
+    }
+    jobs.set_progress(job_id, total, total);
+    log::info!(
+        "Grouped {} of {} candidate images into {} bursts; updated {} documents",
+        report.grouped_images,
+        report.candidates,
+        report.groups,
+        report.updated
+    );
+    Ok(report)
+}
+
+/// Seconds since the epoch for an EXIF `YYYY:MM:DD HH:MM:SS` or `YYYY-MM-DD HH:MM:SS` time, ignoring the
+/// unknown time zone, which is the same for every shot of a burst.
+fn parse_date_taken(value: &str) -> Option<i64> {
+    let numbers: Vec<i64> = value
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|n| !n.is_empty())
+        .take(6)
+        .map(|n| n.parse().ok())
+        .collect::<Option<_>>()?;
+    let [year, month, day, hour, minute, second] = numbers[..] else {
+        return None;
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    // Days since 1970-01-01 in the proleptic Gregorian calendar.
+    let (y, m) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = y.div_euclid(400);
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * m + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    Some(days * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_both_exif_date_separators() {
+        assert_eq!(parse_date_taken("2016:05:04 03:02:01"), Some(1462330921));
+        assert_eq!(parse_date_taken("2016-05-04 03:02:01"), Some(1462330921));
+        assert_eq!(parse_date_taken("1970:01:01 00:00:00"), Some(0));
+        assert_eq!(parse_date_taken("1969:12:31 23:59:59"), Some(-1));
+    }
+
+    #[test]
+    fn counts_leap_days_and_year_boundaries() {
+        assert_eq!(parse_date_taken("2020:02:29 12:00:00"), Some(1582977600));
+        assert_eq!(parse_date_taken("2000:03:01 00:00:00"), Some(951868800));
+        let new_year = parse_date_taken("2020:01:01 00:00:00").unwrap();
+        assert_eq!(parse_date_taken("2019:12:31 23:59:59"), Some(new_year - 1));
+    }
+
+    #[test]
+    fn ignores_subseconds_and_offsets() {
+        let plain = parse_date_taken("2016:05:04 03:02:01");
+        assert_eq!(parse_date_taken("2016:05:04 03:02:01.25"), plain);
+        assert_eq!(parse_date_taken("2016:05:04 03:02:01+02:00"), plain);
+        assert_eq!(parse_date_taken("  2016:05:04 03:02:01\0"), plain);
+    }
+
+    #[test]
+    fn rejects_placeholder_and_incomplete_dates() {
+        for value in [
+            "0000:00:00 00:00:00",
+            "    :  :     :  :  ",
+            "",
+            "2016:05:04",
+            "2016:13:04 03:02:01",
+            "2016:05:32 03:02:01",
+            "2016:05:04 03:02:xx",
+        ] {
+            assert_eq!(parse_date_taken(value), None, "{:?} should not parse", value);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::error::AppError;
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+
+/// At most this many colors are kept per image.
+const PALETTE_SIZE: usize = 5;
+/// Histogram bins closer than this (CIE76 ΔE) to a palette color are folded into it.
+const MERGE_DISTANCE: f64 = 15.0;
+/// Palette colors covering less of the image than this are ignored when searching by color.
+pub const MIN_SEARCH_SHARE: f64 = 0.05;
+/// The default `color_tolerance`, as a CIE76 ΔE.
+pub const DEFAULT_TOLERANCE: f64 = 30.0;
+
+/// A color in CIELAB: lightness from 0 to 100, then the a* and b* axes.
+pub type Lab = [f64; 3];
+
+/// The average color and the dominant colors of an image, as `#rrggbb`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ColorInfo {
+    pub average: String,
+    /// Largest share first.
+    pub palette: Vec<PaletteColor>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PaletteColor {
+    pub color: String,
+    /// The fraction of the image's pixels closest to this color.
+    pub share: f64,
+}
+
+impl ColorInfo {
+    /// Builds the palette from a coarse 512-bin RGB histogram: the most common bins that are
+    /// perceptually distinct become palette colors, and every bin is then averaged into its nearest one.
+    pub fn analyze(image: &DynamicImage) -> Option<Self> {
+        let rgb = image.to_rgb8();
+        let mut bins = vec![(0u64, [0u64; 3]); 512];
+        let mut total = [0u64; 3];
+        for pixel in rgb.pixels() {
+            let [r, g, b] = pixel.0;
+            let bin = &mut bins[((r as usize >> 5) << 6) | ((g as usize >> 5) << 3) | (b as usize >> 5)];
+            bin.0 += 1;
+            for (channel, value) in [r, g, b].into_iter().enumerate() {
+                bin.1[channel] += value as u64;
+                total[channel] += value as u64;
+            }
+        }
+        let pixels = rgb.pixels().len() as u64;
+        if pixels == 0 {
+            return None;
+        }
+
+        let mut bins: Vec<(u64, [f64; 3])> = bins
+            .into_iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, sum)| (count, sum.map(|s| s as f64 / count as f64)))
+            .collect();
+        bins.sort_by_key(|(count, _)| Reverse(*count));
+
+        let mut centers: Vec<Lab> = Vec::new();
+        for (_, color) in &bins {
+            let lab = rgb_to_lab(*color);
+            if centers.len() < PALETTE_SIZE && centers.iter().all(|c| delta_e(*c, lab) >= MERGE_DISTANCE) {
+                centers.push(lab);
+            }
+        }
+        let mut clusters = vec![(0u64, [0f64; 3]); centers.len()];
+        for (count, color) in &bins {
+            let lab = rgb_to_lab(*color);
+            let nearest = (0..centers.len())
+                .min_by(|a, b| delta_e(centers[*a], lab).total_cmp(&delta_e(centers[*b], lab)))
+                .unwrap_or(0);
+            clusters[nearest].0 += count;
+            for (sum, value) in clusters[nearest].1.iter_mut().zip(color) {
+                *sum += value * *count as f64;
+            }
+        }
+
+        let mut palette: Vec<PaletteColor> = clusters
+            .into_iter()
+            .filter(|(count, _)| *count > 0)
+            .map(|(count, sum)| PaletteColor {
+                color: to_hex(sum.map(|s| s / count as f64)),
+                share: (count as f64 / pixels as f64 * 1000.0).round() / 1000.0,
+            })
+            .collect();
+        palette.sort_by(|a, b| b.share.total_cmp(&a.share));
+        Some(Self {
+            average: to_hex(total.map(|s| s as f64 / pixels as f64)),
+            palette,
+        })
+    }
+
+    /// Each palette color in CIELAB with its share.
+    pub fn palette_lab(&self) -> Vec<(Lab, f64)> {
+        self.palette
+            .iter()
+            .filter_map(|p| Some((hex_to_lab(&p.color)?, p.share)))
+            .collect()
+    }
+}
+
+/// The distance from `target` to the closest palette color that covers a noticeable part of the image.
+pub fn palette_distance(palette: impl IntoIterator<Item = (Lab, f64)>, target: Lab) -> Option<f64> {
+    palette
+        .into_iter()
+        .filter(|(_, share)| *share >= MIN_SEARCH_SHARE)
+        .map(|(lab, _)| delta_e(lab, target))
+        .min_by(|a, b| a.total_cmp(b))
+}
+
+/// Packs a palette color into one integer for numeric index fields: 10 bits of lightness,
+/// 12 bits each for a* and b* (offset by 128), all in tenths, and the share in thousandths.
+pub fn pack(lab: Lab, share: f64) -> u64 {
+    let tenths = |value: f64, offset: f64, max: u64| (((value + offset) * 10.0).round().max(0.0) as u64).min(max);
+    (tenths(lab[0], 0.0, 1023) << 34)
+        | (tenths(lab[1], 128.0, 4095) << 22)
+        | (tenths(lab[2], 128.0, 4095) << 10)
+        | ((share * 1000.0).round() as u64).min(1023)
+}
+
+pub fn unpack(value: u64) -> (Lab, f64) {
+    let lab = [
+        ((value >> 34) & 1023) as f64 / 10.0,
+        ((value >> 22) & 4095) as f64 / 10.0 - 128.0,
+        ((value >> 10) & 4095) as f64 / 10.0 - 128.0,
+    ];
+    (lab, (value & 1023) as f64 / 1000.0)
+}
+
+/// Parses `#rrggbb`, `rrggbb`, `#rgb` or one of a few color names into CIELAB.
+pub fn parse_color(value: &str) -> Result<Lab, AppError> {
+    let named = match value.trim().to_lowercase().as_str() {
+        // Mid tones rather than pure primaries, which hardly occur in photos.
+        "red" => Some("#c0392b"),
+        "orange" => Some("#e67e22"),
+        "yellow" => Some("#f1c40f"),
+        "green" => Some("#3f8f3f"),
+        "blue" => Some("#2f6fb7"),
+        "purple" => Some("#7d4698"),
+        "pink" => Some("#e88fb0"),
+        "brown" => Some("#7b5234"),
+        "black" => Some("#141414"),
+        "white" => Some("#f5f5f5"),
+        "gray" | "grey" => Some("#808080"),
+        _ => None,
+    };
+    hex_to_lab(named.unwrap_or(value.trim()))
+        .ok_or_else(|| AppError::BadRequest(format!("Invalid color {}; expected #rrggbb or a color name", value)))
+}
+
+fn hex_to_lab(value: &str) -> Option<Lab> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+    let hex = match hex.len() {
+        3 => hex.chars().flat_map(|c| [c, c]).collect(),
+        6 => hex.to_string(),
+        _ => return None,
+    };
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok().map(|v| v as f64);
+    Some(rgb_to_lab([channel(0)?, channel(2)?, channel(4)?]))
+}
+
+fn to_hex(rgb: [f64; 3]) -> String {
+    let [r, g, b] = rgb.map(|c| c.round().clamp(0.0, 255.0) as u8);
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+/// sRGB (0 to 255) to CIELAB under a D65 white point.
+fn rgb_to_lab(rgb: [f64; 3]) -> Lab {
+    let [r, g, b] = rgb.map(|c| {
+        let c = c / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    });
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f64| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// CIE76 color difference: the Euclidean distance in CIELAB.
+pub fn delta_e(a: Lab, b: Lab) -> f64 {
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Lab, b: Lab, tolerance: f64) {
+        assert!(delta_e(a, b) <= tolerance, "{:?} is not within {} of {:?}", a, tolerance, b);
+    }
+
+    #[test]
+    fn pack_round_trips_to_a_tenth() {
+        for lab in [[0.0, 0.0, 0.0], [100.0, -128.0, 127.9], [53.24, 80.09, 67.2], [32.3, -0.04, -107.86]] {
+            for share in [0.0, 0.05, 0.333, 1.0] {
+                let (unpacked, unpacked_share) = unpack(pack(lab, share));
+                assert_close(unpacked, lab, 0.09);
+                assert!((unpacked_share - share).abs() <= 0.0005);
+            }
+        }
+    }
+
+    #[test]
+    fn pack_clamps_out_of_range_values_without_touching_neighbouring_fields() {
+        let (lab, share) = unpack(pack([150.0, -200.0, 300.0], 2.0));
+        assert_eq!(lab, [102.3, -128.0, 281.5]);
+        assert_eq!(share, 1.023);
+
+        let (lab, share) = unpack(pack([-5.0, 0.0, 0.0], -1.0));
+        assert_eq!(lab[0], 0.0);
+        assert_close(lab, [0.0, 0.0, 0.0], 0.01);
+        assert_eq!(share, 0.0);
+    }
+
+    #[test]
+    fn parses_hex_colors_in_every_form() {
+        let white = parse_color("#ffffff").unwrap();
+        assert_close(white, [100.0, 0.0, 0.0], 0.1);
+        assert_eq!(parse_color("FFF").unwrap(), white);
+        assert_eq!(parse_color(" #fff ").unwrap(), white);
+        assert_close(parse_color("#000").unwrap(), [0.0, 0.0, 0.0], 0.01);
+        // Pure sRGB red, as published for D65.
+        assert_close(parse_color("#ff0000").unwrap(), [53.24, 80.09, 67.2], 0.1);
+    }
+
+    #[test]
+    fn parses_color_names() {
+        assert_eq!(parse_color("Grey").unwrap(), parse_color("gray").unwrap());
+        assert_eq!(parse_color("red").unwrap(), parse_color("#c0392b").unwrap());
+    }
+
+    #[test]
+    fn rejects_malformed_colors() {
+        for value in ["", "#", "#ff", "#12345", "#1234567", "#gggggg", "teal"] {
+            assert!(parse_color(value).is_err(), "{:?} should not parse", value);
+        }
+    }
+
+    #[test]
+    fn palette_colors_survive_hex_and_packing() {
+        let info = ColorInfo {
+            average: "#808080".to_string(),
+            palette: vec![PaletteColor {
+                color: to_hex([200.0, 120.0, 40.0]),
+                share: 0.42,
+            }],
+        };
+        let [(lab, share)] = info.palette_lab()[..] else { panic!("expected one palette color") };
+        let (unpacked, unpacked_share) = unpack(pack(lab, share));
+        assert_close(unpacked, parse_color("#c87828").unwrap(), 0.09);
+        assert_eq!(unpacked_share, 0.42);
+    }
+}
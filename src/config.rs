@@ -12,7 +12,7 @@ pub struct CliConfig {
     pub engine: Option<String>,
     #[clap(long)]
     pub elasticsearch_url: Option<String>,
-    #[clap(long, short)]
+    #[clap(long, short = 'i')]
     pub tantivy_index_path: Option<String>,
     #[clap(long, short)]
     pub thumbnail_directory: Option<String>,
@@ -20,10 +20,38 @@ pub struct CliConfig {
     pub allowed_extensions: Option<Vec<String>>,
     #[clap(long, short)]
     pub num_workers: Option<usize>,
-    #[clap(long, short)]
+    #[clap(long, short = 'p')]
     pub web_port: Option<u16>,
     #[clap(long)]
     pub log_level: Option<String>,
+    #[clap(long)]
+    pub thumbnail_regeneration_limit: Option<usize>,
+    #[clap(long)]
+    pub render_cache_directory: Option<String>,
+    #[clap(long)]
+    pub render_cache_max_bytes: Option<u64>,
+    #[clap(long)]
+    pub render_concurrency_limit: Option<usize>,
+    #[clap(long)]
+    pub trash_directory: Option<String>,
+    #[clap(long)]
+    pub album_store_path: Option<String>,
+    #[clap(long)]
+    pub annotation_store_path: Option<String>,
+    #[clap(long)]
+    pub xmp_write_back: Option<bool>,
+    #[clap(long)]
+    pub gazetteer_path: Option<String>,
+    #[clap(long)]
+    pub similarity_index_path: Option<String>,
+    #[clap(long)]
+    pub burst_interval_seconds: Option<u64>,
+    #[clap(long)]
+    pub burst_max_hash_distance: Option<u32>,
+    #[clap(long)]
+    pub disabled_analyzers: Option<Vec<String>>,
+    #[clap(long)]
+    pub recreate_index: bool,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -37,6 +65,20 @@ pub struct AppConfig {
     pub num_workers: usize,
     pub web_port: u16,
     pub log_level: String,
+    pub thumbnail_regeneration_limit: usize,
+    pub render_cache_directory: String,
+    pub render_cache_max_bytes: u64,
+    pub render_concurrency_limit: usize,
+    pub trash_directory: String,
+    pub album_store_path: String,
+    pub annotation_store_path: String,
+    pub xmp_write_back: bool,
+    pub gazetteer_path: String,
+    pub similarity_index_path: String,
+    pub burst_interval_seconds: u64,
+    pub burst_max_hash_distance: u32,
+    pub disabled_analyzers: HashSet<String>,
+    pub recreate_index: bool,
 }
 
 impl AppConfig {
@@ -80,6 +122,48 @@ impl AppConfig {
         if let Some(log_level) = cli_config.log_level {
             config.log_level = log_level;
         }
+        if let Some(thumbnail_regeneration_limit) = cli_config.thumbnail_regeneration_limit {
+            config.thumbnail_regeneration_limit = thumbnail_regeneration_limit;
+        }
+        if let Some(render_cache_directory) = cli_config.render_cache_directory {
+            config.render_cache_directory = render_cache_directory;
+        }
+        if let Some(render_cache_max_bytes) = cli_config.render_cache_max_bytes {
+            config.render_cache_max_bytes = render_cache_max_bytes;
+        }
+        if let Some(render_concurrency_limit) = cli_config.render_concurrency_limit {
+            config.render_concurrency_limit = render_concurrency_limit;
+        }
+        if let Some(trash_directory) = cli_config.trash_directory {
+            config.trash_directory = trash_directory;
+        }
+        if let Some(album_store_path) = cli_config.album_store_path {
+            config.album_store_path = album_store_path;
+        }
+        if let Some(annotation_store_path) = cli_config.annotation_store_path {
+            config.annotation_store_path = annotation_store_path;
+        }
+        if let Some(xmp_write_back) = cli_config.xmp_write_back {
+            config.xmp_write_back = xmp_write_back;
+        }
+        if let Some(gazetteer_path) = cli_config.gazetteer_path {
+            config.gazetteer_path = gazetteer_path;
+        }
+        if let Some(similarity_index_path) = cli_config.similarity_index_path {
+            config.similarity_index_path = similarity_index_path;
+        }
+        if let Some(burst_interval_seconds) = cli_config.burst_interval_seconds {
+            config.burst_interval_seconds = burst_interval_seconds;
+        }
+        if let Some(burst_max_hash_distance) = cli_config.burst_max_hash_distance {
+            config.burst_max_hash_distance = burst_max_hash_distance;
+        }
+        if let Some(disabled_analyzers) = cli_config.disabled_analyzers {
+            config.disabled_analyzers = disabled_analyzers.into_iter().collect();
+        }
+        if cli_config.recreate_index {
+            config.recreate_index = true;
+        }
 
         Ok(config)
     }
@@ -0,0 +1,559 @@
+use crate::error::AppError;
+use crate::jobs::JobRegistry;
+use crate::links::{self, LinkMethod};
+use crate::metadata::ImageMetadata;
+use crate::processor;
+use crate::search::Searcher;
+use crate::thumbnails;
+use crate::trash::{Trash, TrashEntry};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+/// Decides which copy of a duplicate group survives a `keep-one` deletion.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(tag = "policy", rename_all = "kebab-case")]
+pub enum KeepPolicy {
+    /// Keep the copy recorded as `file_path`, i.e. the first one the processor saw.
+    #[default]
+    Primary,
+    /// Keep the first copy below `root`.
+    PreferRoot { root: String },
+    /// Keep the copy with the shortest path.
+    ShortestPath,
+    /// Keep the copy with the oldest modification time.
+    OldestMtime,
+    /// Keep the copy with the most EXIF fields. Copies share a hash when indexed, but may
+    /// have been edited in place since.
+    RichestExif,
+    /// Keep exactly this path, which must be one of the copies.
+    Explicit { path: String },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DedupPlan {
+    pub file_hash: String,
+    pub keep: Option<String>,
+    pub delete: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct FailedPath {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DedupOutcome {
+    pub trashed: Vec<TrashEntry>,
+    pub failed: Vec<FailedPath>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct LinkRequest {
+    #[serde(default)]
+    pub keep: KeepPolicy,
+    #[serde(default)]
+    pub method: LinkMethod,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LinkedPath {
+    pub path: String,
+    pub method: LinkMethod,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LinkOutcome {
+    pub linked: Vec<LinkedPath>,
+    pub failed: Vec<FailedPath>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DuplicateGroup {
+    pub file_hash: String,
+    pub file_path: String,
+    pub duplicate_paths: Vec<String>,
+    pub copies: u64,
+    pub linked: u64,
+    pub file_size: u64,
+    pub wasted_bytes: u64,
+}
+
+impl DuplicateGroup {
+    pub fn from_metadata(metadata: ImageMetadata) -> Self {
+        let file_size = match metadata.file_size {
+            0 => copies(&metadata)
+                .iter()
+                .find_map(|p| std::fs::metadata(p).ok())
+                .map(|m| m.len())
+                .unwrap_or(0),
+            size => size,
+        };
+        let duplicate_paths = metadata.duplicate_paths();
+        let copies = 1 + duplicate_paths.len() as u64;
+        let linked = metadata.linked_paths.len() as u64;
+        Self {
+            file_hash: metadata.file_hash,
+            file_path: metadata.file_path,
+            duplicate_paths,
+            copies,
+            linked,
+            file_size,
+            wasted_bytes: file_size * (copies - 1).saturating_sub(linked),
+        }
+    }
+}
+
+/// Selects duplicate groups for bulk operations.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct DuplicateFilter {
+    /// Only groups with at least one copy below this directory.
+    pub path_prefix: Option<String>,
+}
+
+impl DuplicateFilter {
+    pub fn matches_path(&self, path: &str) -> bool {
+        match &self.path_prefix {
+            Some(prefix) => Path::new(path).starts_with(prefix),
+            None => true,
+        }
+    }
+
+    pub fn matches(&self, metadata: &ImageMetadata) -> bool {
+        copies(metadata).iter().any(|p| self.matches_path(p))
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ResolveRequest {
+    #[serde(default)]
+    pub filter: DuplicateFilter,
+    #[serde(default)]
+    pub keep: KeepPolicy,
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Only trash copies that match the filter, leaving copies elsewhere alone.
+    #[serde(default = "default_restrict_to_filter")]
+    pub restrict_to_filter: bool,
+}
+
+fn default_restrict_to_filter() -> bool {
+    true
+}
+
+#[derive(Serialize, Debug)]
+pub struct GroupResolution {
+    pub plan: DedupPlan,
+    pub trashed: Vec<String>,
+    pub failed: Vec<FailedPath>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ResolveReport {
+    pub dry_run: bool,
+    pub groups_matched: u64,
+    pub files_trashed: u64,
+    pub bytes_reclaimed: u64,
+    pub failures: u64,
+    pub groups: Vec<GroupResolution>,
+}
+
+fn copies(metadata: &ImageMetadata) -> Vec<String> {
+    metadata.paths().map(|p| p.to_string()).collect()
+}
+
+/// Plans the removal of every copy.
+pub fn plan_delete_all(metadata: &ImageMetadata) -> DedupPlan {
+    DedupPlan {
+        file_hash: metadata.file_hash.clone(),
+        keep: None,
+        delete: copies(metadata),
+    }
+}
+
+/// Plans the removal of every copy except the one chosen by `policy`.
+///
+/// Only copies still on disk can be kept, so a group whose chosen copy has already gone is
+/// rejected before anything is moved.
+pub fn plan_keep_one(metadata: &ImageMetadata, policy: &KeepPolicy) -> Result<DedupPlan, AppError> {
+    let copies = copies(metadata);
+    let existing: Vec<&String> = copies.iter().filter(|p| Path::new(p).exists()).collect();
+    let keep = match choose_keeper(&copies, &existing, policy)? {
+        Some(keep) => keep,
+        None => existing
+            .iter()
+            .find(|p| **p == &metadata.file_path)
+            .or_else(|| existing.first())
+            .map(|p| p.to_string())
+            .ok_or_else(|| {
+                AppError::NotFound(format!("No copy of image {} is left on disk to keep", metadata.file_hash))
+            })?,
+    };
+    log::debug!("Keep policy {:?} chose {} for hash {}", policy, keep, metadata.file_hash);
+
+    Ok(DedupPlan {
+        file_hash: metadata.file_hash.clone(),
+        delete: copies.into_iter().filter(|p| *p != keep).collect(),
+        keep: Some(keep),
+    })
+}
+
+/// Returns the existing copy to keep, or `None` when the policy has no preference among them.
+fn choose_keeper(copies: &[String], existing: &[&String], policy: &KeepPolicy) -> Result<Option<String>, AppError> {
+    let existing = || existing.iter().copied();
+
+    let keeper = match policy {
+        KeepPolicy::Primary => None,
+        KeepPolicy::PreferRoot { root } => existing().find(|p| Path::new(p).starts_with(root)).cloned(),
+        KeepPolicy::ShortestPath => existing().min_by_key(|p| p.len()).cloned(),
+        KeepPolicy::OldestMtime => existing().min_by_key(|p| modified(p)).cloned(),
+        // max_by_key returns the last maximum, so reverse to prefer earlier copies on ties.
+        KeepPolicy::RichestExif => existing().rev().max_by_key(|p| exif_field_count(p)).cloned(),
+        KeepPolicy::Explicit { path } => {
+            if !copies.contains(path) {
+                return Err(AppError::BadRequest(format!("{} is not a copy of this image", path)));
+            }
+            if !existing().any(|p| p == path) {
+                return Err(AppError::NotFound(format!("{} is no longer on disk and cannot be kept", path)));
+            }
+            Some(path.clone())
+        }
+    };
+    Ok(keeper)
+}
+
+fn modified(path: &str) -> SystemTime {
+    std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .unwrap_or(SystemTime::now())
+}
+
+fn exif_field_count(path: &str) -> usize {
+    File::open(path)
+        .ok()
+        .and_then(|file| exif::Reader::new().read_from_container(&mut BufReader::new(file)).ok())
+        .map(|exif| exif.fields().count())
+        .unwrap_or(0)
+}
+
+/// Moves the planned files to the trash and updates the index for the ones that were moved.
+pub async fn apply_plan(
+    searcher: &Arc<dyn Searcher>,
+    trash: &Arc<Trash>,
+    thumbnail_dir: &Path,
+    mut metadata: ImageMetadata,
+    plan: &DedupPlan,
+) -> Result<DedupOutcome, AppError> {
+    let (trash, paths, hash) = (trash.clone(), plan.delete.clone(), plan.file_hash.clone());
+    let keep = plan.keep.clone();
+    let (trashed, failed) = tokio::task::spawn_blocking(move || {
+        let mut trashed = Vec::new();
+        let mut failed = Vec::new();
+        // A kept copy that changed since indexing would leave no copy of the content at all.
+        let kept = match keep.as_deref() {
+            Some(keep) => verify_hash(Path::new(keep), &hash).map_err(|e| e.to_string()),
+            None => Ok(()),
+        };
+        for file_path in paths {
+            let result = kept
+                .clone()
+                .map_err(|e| AppError::Generic(format!("Kept copy cannot be trusted: {}", e)))
+                .and_then(|_| verify_hash(Path::new(&file_path), &hash))
+                .and_then(|_| trash.move_to_trash(&file_path, &hash));
+            match result {
+                Ok(entry) => trashed.push(entry),
+                Err(e) => {
+                    log::error!("Failed to move file {} to trash: {}", file_path, e);
+                    failed.push(FailedPath {
+                        path: file_path,
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        (trashed, failed)
+    })
+    .await?;
+
+    // Forget only the paths that were actually moved, and make the kept copy the primary one.
+    let moved: HashSet<&str> = trashed.iter().map(|entry| entry.original_path.as_str()).collect();
+    let mut remaining: Vec<String> = plan
+        .keep
+        .iter()
+        .cloned()
+        .chain(copies(&metadata).into_iter().filter(|p| Some(p) != plan.keep.as_ref()))
+        .filter(|p| !moved.contains(p.as_str()))
+        .collect();
+
+    if remaining.is_empty() {
+        searcher.delete_document(&plan.file_hash).await?;
+        log::info!("Deleted document from index for hash: {}", &plan.file_hash);
+        thumbnails::remove_thumbnail(thumbnail_dir, &plan.file_hash)?;
+    } else if !moved.is_empty() {
+        metadata.linked_paths.retain(|p| !moved.contains(p.as_str()));
+        metadata.copies.retain(|c| !moved.contains(c.path.as_str()));
+        metadata.file_path = remaining.remove(0);
+        searcher.update_document(metadata).await?;
+        log::info!("Updated document in index for hash: {}", &plan.file_hash);
+    }
+
+    Ok(DedupOutcome { trashed, failed })
+}
+
+/// Re-hashes `path` so a file edited or replaced since it was indexed is never trashed as a duplicate.
+fn verify_hash(path: &Path, expected_hash: &str) -> Result<(), AppError> {
+    let actual = processor::hash_file(path)?;
+    if actual != expected_hash {
+        return Err(AppError::Generic(format!(
+            "{:?} no longer matches hash {} (found {})",
+            path, expected_hash, actual
+        )));
+    }
+    Ok(())
+}
+
+/// Replaces the planned copies with links to the kept one instead of deleting them.
+/// Every path stays in the index; the linked ones are recorded in `linked_paths`.
+pub async fn apply_link_plan(
+    searcher: &Arc<dyn Searcher>,
+    mut metadata: ImageMetadata,
+    plan: &DedupPlan,
+    method: LinkMethod,
+) -> Result<LinkOutcome, AppError> {
+    let keep = plan
+        .keep
+        .clone()
+        .ok_or_else(|| AppError::BadRequest("Linking needs a copy to keep".to_string()))?;
+
+    let mut linked = Vec::new();
+    let mut failed = Vec::new();
+    for path in &plan.delete {
+        let (keep_path, duplicate, hash) = (keep.clone(), path.clone(), plan.file_hash.clone());
+        let result = tokio::task::spawn_blocking(move || {
+            links::replace_with_link(Path::new(&keep_path), Path::new(&duplicate), &hash, method)
+        })
+        .await?;
+        match result {
+            Ok(used) => linked.push(LinkedPath {
+                path: path.clone(),
+                method: used,
+            }),
+            Err(e) => {
+                log::error!("Failed to link {} to {}: {}", path, keep, e);
+                failed.push(FailedPath {
+                    path: path.clone(),
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    if !linked.is_empty() {
+        let mut linked_paths: Vec<String> = metadata.linked_paths.iter().filter(|p| **p != keep).cloned().collect();
+        for entry in &linked {
+            if !linked_paths.contains(&entry.path) {
+                linked_paths.push(entry.path.clone());
+            }
+        }
+        metadata.file_path = keep;
+        metadata.linked_paths = linked_paths;
+        searcher.update_document(metadata).await?;
+        log::info!("Recorded {} linked copies for hash: {}", linked.len(), &plan.file_hash);
+    }
+
+    Ok(LinkOutcome { linked, failed })
+}
+
+/// Applies `request.keep` to every duplicate group matching `request.filter`, reporting progress to `jobs`.
+pub async fn resolve_all(
+    searcher: &Arc<dyn Searcher>,
+    trash: &Arc<Trash>,
+    thumbnail_dir: &Path,
+    jobs: &JobRegistry,
+    job_id: &str,
+    request: &ResolveRequest,
+) -> Result<ResolveReport, AppError> {
+    let groups: Vec<ImageMetadata> = searcher
+        .get_duplicate_groups()
+        .await?
+        .into_iter()
+        .filter(|m| request.filter.matches(m))
+        .collect();
+    let total = groups.len() as u64;
+    log::info!("Resolving {} duplicate groups with policy {:?}", total, request.keep);
+
+    let mut report = ResolveReport {
+        dry_run: request.dry_run,
+        groups_matched: total,
+        ..Default::default()
+    };
+
+    for (done, metadata) in groups.into_iter().enumerate() {
+        jobs.set_progress(job_id, done as u64, total);
+
+        // Planning checks which copies exist and may read their EXIF data.
+        let (candidate, keep) = (metadata.clone(), request.keep.clone());
+        let mut plan = match tokio::task::spawn_blocking(move || plan_keep_one(&candidate, &keep)).await? {
+            Ok(plan) => plan,
+            Err(e) => {
+                report.failures += 1;
+                report.groups.push(GroupResolution {
+                    plan: plan_delete_all(&metadata),
+                    trashed: Vec::new(),
+                    failed: Vec::new(),
+                    error: Some(e.to_string()),
+                });
+                continue;
+            }
+        };
+        if request.restrict_to_filter {
+            plan.delete.retain(|p| request.filter.matches_path(p));
+        }
+        if request.dry_run || plan.delete.is_empty() {
+            report.groups.push(GroupResolution {
+                plan,
+                trashed: Vec::new(),
+                failed: Vec::new(),
+                error: None,
+            });
+            continue;
+        }
+
+        let resolution = match apply_plan(searcher, trash, thumbnail_dir, metadata, &plan).await {
+            Ok(outcome) => {
+                report.files_trashed += outcome.trashed.len() as u64;
+                report.bytes_reclaimed += outcome.trashed.iter().map(|entry| entry.size).sum::<u64>();
+                report.failures += outcome.failed.len() as u64;
+                GroupResolution {
+                    plan,
+                    trashed: outcome.trashed.into_iter().map(|entry| entry.original_path).collect(),
+                    failed: outcome.failed,
+                    error: None,
+                }
+            }
+            Err(e) => {
+                report.failures += 1;
+                GroupResolution {
+                    plan,
+                    trashed: Vec::new(),
+                    failed: Vec::new(),
+                    error: Some(e.to_string()),
+                }
+            }
+        };
+        report.groups.push(resolution);
+    }
+
+    jobs.set_progress(job_id, total, total);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::FileCopy;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    /// Writes each of `names` below a fresh directory and returns an image with them as copies, the first one primary.
+    fn image_with_copies(test: &str, names: &[&str]) -> (PathBuf, ImageMetadata) {
+        let dir = std::env::temp_dir().join(format!("dedup-test-{}-{}", std::process::id(), test));
+        let _ = std::fs::remove_dir_all(&dir);
+        let paths: Vec<String> = names.iter().map(|name| dir.join(name).to_string_lossy().to_string()).collect();
+        for path in &paths {
+            std::fs::create_dir_all(Path::new(path).parent().unwrap()).unwrap();
+            std::fs::write(path, b"same content").unwrap();
+        }
+        let metadata = ImageMetadata {
+            file_path: paths[0].clone(),
+            file_hash: "hash".to_string(),
+            copies: paths
+                .iter()
+                .map(|path| FileCopy {
+                    path: path.clone(),
+                    size: 12,
+                    modified_at: None,
+                    library: String::new(),
+                    first_seen: 0,
+                })
+                .collect(),
+            ..ImageMetadata::default()
+        };
+        (dir, metadata)
+    }
+
+    fn kept(metadata: &ImageMetadata, policy: KeepPolicy) -> String {
+        plan_keep_one(metadata, &policy).unwrap().keep.unwrap()
+    }
+
+    fn set_modified(path: &Path, seconds_ago: u64) {
+        let file = File::options().append(true).open(path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(seconds_ago)).unwrap();
+    }
+
+    #[test]
+    fn shortest_path_ties_go_to_the_earlier_copy() {
+        let (dir, metadata) = image_with_copies("shortest", &["long/a.jpg", "b/b.jpg", "c/c.jpg"]);
+        assert_eq!(kept(&metadata, KeepPolicy::ShortestPath), dir.join("b/b.jpg").to_string_lossy());
+    }
+
+    #[test]
+    fn oldest_mtime_ties_go_to_the_earlier_copy() {
+        let (dir, metadata) = image_with_copies("oldest", &["a.jpg", "b.jpg", "c.jpg"]);
+        set_modified(&dir.join("a.jpg"), 10);
+        set_modified(&dir.join("b.jpg"), 3600);
+        set_modified(&dir.join("c.jpg"), 3600);
+        assert_eq!(kept(&metadata, KeepPolicy::OldestMtime), dir.join("b.jpg").to_string_lossy());
+    }
+
+    #[test]
+    fn richest_exif_ties_go_to_the_earlier_copy() {
+        let (dir, metadata) = image_with_copies("exif", &["a.jpg", "b.jpg"]);
+        assert_eq!(kept(&metadata, KeepPolicy::RichestExif), dir.join("a.jpg").to_string_lossy());
+    }
+
+    #[test]
+    fn prefer_root_falls_back_to_the_primary_copy() {
+        let (dir, metadata) = image_with_copies("root", &["inbox/a.jpg", "archive/b.jpg", "archive/c.jpg"]);
+        let prefer = |root: &str| KeepPolicy::PreferRoot {
+            root: dir.join(root).to_string_lossy().to_string(),
+        };
+        assert_eq!(kept(&metadata, prefer("archive")), dir.join("archive/b.jpg").to_string_lossy());
+        assert_eq!(kept(&metadata, prefer("elsewhere")), dir.join("inbox/a.jpg").to_string_lossy());
+        // A root is matched by whole path components, not as a string prefix.
+        assert_eq!(kept(&metadata, prefer("arch")), dir.join("inbox/a.jpg").to_string_lossy());
+    }
+
+    #[test]
+    fn a_missing_primary_is_replaced_by_the_first_copy_on_disk() {
+        let (dir, metadata) = image_with_copies("missing", &["a.jpg", "b.jpg", "c.jpg"]);
+        std::fs::remove_file(dir.join("a.jpg")).unwrap();
+        std::fs::remove_file(dir.join("b.jpg")).unwrap();
+        let plan = plan_keep_one(&metadata, &KeepPolicy::Primary).unwrap();
+        assert_eq!(plan.keep.unwrap(), dir.join("c.jpg").to_string_lossy());
+        assert_eq!(plan.delete.len(), 2);
+
+        std::fs::remove_file(dir.join("c.jpg")).unwrap();
+        assert!(matches!(plan_keep_one(&metadata, &KeepPolicy::ShortestPath), Err(AppError::NotFound(_))));
+    }
+
+    #[test]
+    fn explicit_paths_must_be_existing_copies() {
+        let (dir, metadata) = image_with_copies("explicit", &["a.jpg", "b.jpg"]);
+        let explicit = |name: &str| KeepPolicy::Explicit {
+            path: dir.join(name).to_string_lossy().to_string(),
+        };
+        assert_eq!(kept(&metadata, explicit("b.jpg")), dir.join("b.jpg").to_string_lossy());
+        assert!(matches!(plan_keep_one(&metadata, &explicit("other.jpg")), Err(AppError::BadRequest(_))));
+        std::fs::remove_file(dir.join("b.jpg")).unwrap();
+        assert!(matches!(plan_keep_one(&metadata, &explicit("b.jpg")), Err(AppError::NotFound(_))));
+    }
+}
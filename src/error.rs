@@ -49,6 +49,9 @@ pub enum AppError {
 
     #[error("Not found: {0}")]
     NotFound(String),
+
+    #[error("Bad request: {0}")]
+    BadRequest(String),
 }
 
 impl ResponseError for AppError {
@@ -75,6 +78,7 @@ impl ResponseError for AppError {
             AppError::RecvError => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Generic(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
         }
     }
 }
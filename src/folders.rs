@@ -0,0 +1,195 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Strips trailing slashes so `/photos/` and `/photos` name the same folder.
+pub fn normalize_folder(folder: &str) -> String {
+    let trimmed = folder.trim_end_matches('/');
+    if trimmed.is_empty() && folder.starts_with('/') {
+        "/".to_string()
+    } else {
+        trimmed.to_string()
+    }
+}
+
+/// The folder a path is stored in.
+pub fn parent_folder(path: &str) -> Option<String> {
+    Path::new(path).parent().map(|p| p.to_string_lossy().to_string())
+}
+
+/// Every folder above a path, innermost first.
+pub fn ancestor_folders(path: &str) -> Vec<String> {
+    Path::new(path)
+        .ancestors()
+        .skip(1)
+        .map(|p| p.to_string_lossy().to_string())
+        .filter(|p| !p.is_empty())
+        .collect()
+}
+
+#[derive(Serialize, Debug)]
+pub struct FolderNode {
+    pub path: String,
+    pub name: String,
+    /// Images with a copy directly in this folder.
+    pub image_count: u64,
+    /// The sum of `image_count` over this folder and all of its subfolders.
+    pub total_count: u64,
+    pub children: Vec<FolderNode>,
+}
+
+/// One level of the tree, as listed by the browse endpoint.
+#[derive(Serialize, Debug)]
+pub struct FolderSummary {
+    pub path: String,
+    pub name: String,
+    pub image_count: u64,
+    pub total_count: u64,
+    /// An image from this folder or below it, to show as its cover.
+    pub thumbnail_hash: Option<String>,
+}
+
+/// Per-folder image counts, built from the indexed parent folders.
+#[derive(Debug, Default)]
+pub struct FolderCounts {
+    direct: BTreeMap<String, u64>,
+    /// The hash of an image directly in each folder, keyed by folder.
+    representatives: BTreeMap<String, String>,
+}
+
+/// Subtree totals and the child folders of every folder.
+struct Layout {
+    totals: BTreeMap<String, u64>,
+    children: BTreeMap<String, Vec<String>>,
+}
+
+impl FolderCounts {
+    /// Adds `count` images with a copy directly in `folder`, one of which is `sample`.
+    pub fn add_folder(&mut self, folder: &str, count: u64, sample: Option<&str>) {
+        if count == 0 {
+            return;
+        }
+        *self.direct.entry(folder.to_string()).or_default() += count;
+        if let Some(hash) = sample {
+            self.representatives
+                .entry(folder.to_string())
+                .or_insert_with(|| hash.to_string());
+        }
+    }
+
+    /// Whether a cover image is already known for `folder`.
+    pub fn has_representative(&self, folder: &str) -> bool {
+        self.representatives.contains_key(folder)
+    }
+
+    fn layout(&self) -> Layout {
+        let mut totals: BTreeMap<String, u64> = BTreeMap::new();
+        for (folder, count) in &self.direct {
+            *totals.entry(folder.clone()).or_default() += count;
+            for ancestor in ancestor_folders(folder) {
+                *totals.entry(ancestor).or_default() += count;
+            }
+        }
+        let mut children: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for folder in totals.keys() {
+            if let Some(parent) = parent_folder(folder).filter(|p| p != folder) {
+                children.entry(parent).or_default().push(folder.clone());
+            }
+        }
+        Layout { totals, children }
+    }
+
+    /// The deepest folder that contains every image.
+    pub fn root(&self) -> Option<String> {
+        self.root_of(&self.layout())
+    }
+
+    fn root_of(&self, layout: &Layout) -> Option<String> {
+        // Skip down from the top through folders that hold nothing but a single subfolder.
+        let mut root = layout.totals.keys().find(|f| parent_folder(f).is_none())?.clone();
+        while !self.direct.contains_key(&root) {
+            match layout.children.get(&root).map(|c| c.as_slice()) {
+                Some([only]) => root = only.clone(),
+                _ => break,
+            }
+        }
+        Some(root)
+    }
+
+    /// The tree below `root`, or below the deepest folder containing every image when `root` is `None`.
+    pub fn tree(&self, root: Option<&str>) -> Option<FolderNode> {
+        let layout = self.layout();
+        let root = match root {
+            Some(root) => normalize_folder(root),
+            None => self.root_of(&layout)?,
+        };
+        if !layout.totals.contains_key(&root) {
+            return None;
+        }
+        Some(self.node(root, &layout))
+    }
+
+    fn node(&self, folder: String, layout: &Layout) -> FolderNode {
+        FolderNode {
+            name: folder_name(&folder),
+            image_count: self.direct.get(&folder).copied().unwrap_or(0),
+            total_count: layout.totals.get(&folder).copied().unwrap_or(0),
+            children: layout
+                .children
+                .get(&folder)
+                .into_iter()
+                .flatten()
+                .map(|child| self.node(child.clone(), layout))
+                .collect(),
+            path: folder,
+        }
+    }
+
+    /// The immediate subfolders of `folder`, each with a cover image from its subtree.
+    pub fn subfolders(&self, folder: &str) -> Vec<FolderSummary> {
+        let layout = self.layout();
+        layout
+            .children
+            .get(&normalize_folder(folder))
+            .into_iter()
+            .flatten()
+            .map(|child| FolderSummary {
+                path: child.clone(),
+                name: folder_name(child),
+                image_count: self.direct.get(child).copied().unwrap_or(0),
+                total_count: layout.totals.get(child).copied().unwrap_or(0),
+                thumbnail_hash: self.representative(child),
+            })
+            .collect()
+    }
+
+    /// The folder's own cover image, or else the one of the first folder below it.
+    fn representative(&self, folder: &str) -> Option<String> {
+        if let Some(hash) = self.representatives.get(folder) {
+            return Some(hash.clone());
+        }
+        let prefix = format!("{}/", folder.trim_end_matches('/'));
+        self.representatives
+            .range(prefix.clone()..)
+            .take_while(|(candidate, _)| candidate.starts_with(&prefix))
+            .map(|(_, hash)| hash.clone())
+            .next()
+    }
+
+    /// The number of images with a copy directly in `folder`.
+    pub fn image_count(&self, folder: &str) -> u64 {
+        self.direct.get(&normalize_folder(folder)).copied().unwrap_or(0)
+    }
+
+    /// Whether any image sits in or below `folder`.
+    pub fn contains(&self, folder: &str) -> bool {
+        self.layout().totals.contains_key(&normalize_folder(folder))
+    }
+}
+
+fn folder_name(folder: &str) -> String {
+    Path::new(folder)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| folder.to_string())
+}
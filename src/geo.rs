@@ -0,0 +1,204 @@
+use crate::error::AppError;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+const GEOHASH_ALPHABET: &[u8] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+const MAX_GEOHASH_PRECISION: usize = 12;
+
+/// A map viewport in degrees. `west` is greater than `east` when it spans the antimeridian.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingBox {
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+impl BoundingBox {
+    pub const WORLD: BoundingBox = BoundingBox {
+        west: -180.0,
+        south: -90.0,
+        east: 180.0,
+        north: 90.0,
+    };
+
+    /// Parses `west,south,east,north`, the order used by most map libraries.
+    pub fn parse(value: &str) -> Result<Self, AppError> {
+        let invalid = || AppError::BadRequest(format!("Invalid bbox {}; expected west,south,east,north", value));
+        let numbers: Vec<f64> = value
+            .split(',')
+            .map(|n| n.trim().parse::<f64>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        let [west, south, east, north] = numbers[..] else {
+            return Err(invalid());
+        };
+        let longitudes = -180.0..=180.0;
+        let latitudes = -90.0..=90.0;
+        if ![west, east].iter().all(|lon| longitudes.contains(lon))
+            || ![south, north].iter().all(|lat| latitudes.contains(lat))
+            || south > north
+        {
+            return Err(invalid());
+        }
+        Ok(Self { west, south, east, north })
+    }
+
+    /// The longitude ranges covered, split in two when the box crosses the antimeridian.
+    pub fn longitude_ranges(&self) -> Vec<(f64, f64)> {
+        if self.west <= self.east {
+            vec![(self.west, self.east)]
+        } else {
+            vec![(self.west, 180.0), (-180.0, self.east)]
+        }
+    }
+}
+
+/// Picks a geohash length that gives a few dozen cells across a web map at `zoom` (0 to 20+).
+pub fn precision_for_zoom(zoom: u32) -> usize {
+    (zoom.div_ceil(2) as usize).clamp(1, MAX_GEOHASH_PRECISION)
+}
+
+/// Encodes a position as a geohash of `precision` characters.
+pub fn geohash(latitude: f64, longitude: f64, precision: usize) -> String {
+    let (mut lat_range, mut lon_range) = ((-90.0, 90.0), (-180.0, 180.0));
+    let mut hash = String::with_capacity(precision);
+    let mut even = true;
+    let (mut bits, mut value) = (0, 0usize);
+    while hash.len() < precision {
+        let (range, coordinate) = if even {
+            (&mut lon_range, longitude)
+        } else {
+            (&mut lat_range, latitude)
+        };
+        let middle = (range.0 + range.1) / 2.0;
+        value <<= 1;
+        if coordinate >= middle {
+            value |= 1;
+            range.0 = middle;
+        } else {
+            range.1 = middle;
+        }
+        even = !even;
+        bits += 1;
+        if bits == 5 {
+            hash.push(GEOHASH_ALPHABET[value] as char);
+            bits = 0;
+            value = 0;
+        }
+    }
+    hash
+}
+
+/// A geohash cell with the images inside it.
+#[derive(Serialize, Debug)]
+pub struct GeoCluster {
+    pub geohash: String,
+    pub count: u64,
+    /// The mean position of the images in the cell.
+    pub latitude: f64,
+    pub longitude: f64,
+    pub sample_hash: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct GeoClusters {
+    pub precision: usize,
+    pub total: u64,
+    pub clusters: Vec<GeoCluster>,
+}
+
+/// Running sums per geohash cell, for backends that cluster positions themselves.
+#[derive(Debug)]
+pub struct ClusterCounts<T> {
+    precision: usize,
+    cells: BTreeMap<String, (u64, f64, f64, T)>,
+}
+
+impl<T> ClusterCounts<T> {
+    pub fn new(precision: usize) -> Self {
+        Self {
+            precision,
+            cells: BTreeMap::new(),
+        }
+    }
+
+    /// Counts a position; `sample` is kept for the first position seen in each cell.
+    pub fn add(&mut self, latitude: f64, longitude: f64, sample: impl FnOnce() -> T) {
+        let cell = self
+            .cells
+            .entry(geohash(latitude, longitude, self.precision))
+            .or_insert_with(|| (0, 0.0, 0.0, sample()));
+        cell.0 += 1;
+        cell.1 += latitude;
+        cell.2 += longitude;
+    }
+
+    /// The clusters, largest first, with each sample turned into an image hash by `resolve`.
+    pub fn into_clusters(self, mut resolve: impl FnMut(T) -> Option<String>) -> GeoClusters {
+        let mut clusters: Vec<GeoCluster> = self
+            .cells
+            .into_iter()
+            .map(|(geohash, (count, latitude_sum, longitude_sum, sample))| GeoCluster {
+                geohash,
+                count,
+                latitude: latitude_sum / count as f64,
+                longitude: longitude_sum / count as f64,
+                sample_hash: resolve(sample),
+            })
+            .collect();
+        clusters.sort_by_key(|c| std::cmp::Reverse(c.count));
+        GeoClusters {
+            precision: self.precision,
+            total: clusters.iter().map(|c| c.count).sum(),
+            clusters,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_reference_geohashes() {
+        assert_eq!(geohash(57.64911, 10.40744, 11), "u4pruydqqvj");
+        assert_eq!(geohash(42.6, -5.6, 5), "ezs42");
+    }
+
+    #[test]
+    fn positions_either_side_of_the_antimeridian_fall_in_separate_cells() {
+        let east = geohash(10.0, 179.99, 3);
+        let west = geohash(10.0, -179.99, 3);
+        assert_eq!(east, "xcz");
+        assert_eq!(west, "81b");
+        // The edges themselves land in the outermost cells rather than wrapping around.
+        assert_eq!(geohash(0.0, 180.0, 2), "xb");
+        assert_eq!(geohash(0.0, -180.0, 2), "80");
+        assert_eq!(geohash(90.0, 180.0, 2), "zz");
+        assert_eq!(geohash(-90.0, -180.0, 2), "00");
+    }
+
+    #[test]
+    fn a_bounding_box_across_the_antimeridian_is_split_in_two() {
+        let bbox = BoundingBox::parse("170,-10,-170,10").unwrap();
+        assert_eq!(bbox.longitude_ranges(), [(170.0, 180.0), (-180.0, -170.0)]);
+        let in_range = |lon: f64| bbox.longitude_ranges().iter().any(|(w, e)| (*w..=*e).contains(&lon));
+        assert!(in_range(179.99) && in_range(-179.99));
+        assert!(!in_range(0.0));
+
+        assert_eq!(BoundingBox::WORLD.longitude_ranges(), [(-180.0, 180.0)]);
+        assert!(BoundingBox::parse("170,10,-170,-10").is_err());
+        assert!(BoundingBox::parse("190,-10,-170,10").is_err());
+    }
+
+    #[test]
+    fn precision_grows_with_zoom_within_geohash_limits() {
+        assert_eq!(precision_for_zoom(0), 1);
+        assert_eq!(precision_for_zoom(2), 1);
+        assert_eq!(precision_for_zoom(3), 2);
+        assert_eq!(precision_for_zoom(10), 5);
+        assert_eq!(precision_for_zoom(24), MAX_GEOHASH_PRECISION);
+        assert_eq!(precision_for_zoom(u32::MAX), MAX_GEOHASH_PRECISION);
+    }
+}
@@ -1,9 +1,13 @@
+use crate::annotations::AnnotationStore;
 use crate::error::AppError;
 use crate::metadata::ImageMetadata;
 use crate::search::Searcher;
+use crate::similarity::SimilarityIndex;
 
 pub fn start_indexing(
     searcher: std::sync::Arc<dyn Searcher>,
+    annotations: std::sync::Arc<AnnotationStore>,
+    similarity: std::sync::Arc<SimilarityIndex>,
     metadata_rx: crossbeam_channel::Receiver<ImageMetadata>,
 ) -> Result<(), AppError> {
     log::info!("Starting metadata indexing");
@@ -11,7 +15,10 @@ pub fn start_indexing(
     // Ensure the index exists and has the correct mapping
     futures::executor::block_on(searcher.ensure_index_exists())?;
 
-    for metadata in metadata_rx {
+    for mut metadata in metadata_rx {
+        // Annotations live outside the index, so a rebuilt index gets them back here.
+        metadata.annotations = annotations.get(&metadata.file_hash);
+        similarity.insert(&metadata);
         futures::executor::block_on(searcher.index_metadata(metadata))?;
     }
 
@@ -0,0 +1,344 @@
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Photoshop image resource holding the IPTC-IIM records of a JPEG.
+const IPTC_RESOURCE_ID: u16 = 0x0404;
+/// TIFF tag holding the IPTC-IIM records of a TIFF.
+const IPTC_TIFF_TAG: u16 = 33723;
+/// `ESC % G`, the coded character set declaring UTF-8.
+const UTF8_CHARSET: &[u8] = b"\x1b%G";
+
+/// Captions, keywords, credits and location from the IPTC-IIM application record.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct IptcMetadata {
+    /// 2:05 Object Name
+    #[serde(default)]
+    pub title: Option<String>,
+    /// 2:105 Headline
+    #[serde(default)]
+    pub headline: Option<String>,
+    /// 2:120 Caption/Abstract
+    #[serde(default)]
+    pub caption: Option<String>,
+    /// 2:25 Keywords
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// 2:80 By-line
+    #[serde(default)]
+    pub bylines: Vec<String>,
+    /// 2:110 Credit
+    #[serde(default)]
+    pub credit: Option<String>,
+    /// 2:115 Source
+    #[serde(default)]
+    pub source: Option<String>,
+    /// 2:116 Copyright Notice
+    #[serde(default)]
+    pub copyright: Option<String>,
+    /// 2:90 City
+    #[serde(default)]
+    pub city: Option<String>,
+    /// 2:92 Sub-location
+    #[serde(default)]
+    pub sublocation: Option<String>,
+    /// 2:95 Province/State
+    #[serde(default)]
+    pub province_state: Option<String>,
+    /// 2:101 Country/Primary Location Name
+    #[serde(default)]
+    pub country: Option<String>,
+    /// 2:100 Country/Primary Location Code
+    #[serde(default)]
+    pub country_code: Option<String>,
+}
+
+impl IptcMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == IptcMetadata::default()
+    }
+
+    /// Every free text value, for full text search.
+    pub fn text_values(&self) -> impl Iterator<Item = &String> {
+        [
+            &self.title,
+            &self.headline,
+            &self.caption,
+            &self.credit,
+            &self.source,
+            &self.copyright,
+            &self.city,
+            &self.sublocation,
+            &self.province_state,
+            &self.country,
+        ]
+        .into_iter()
+        .flatten()
+        .chain(self.bylines.iter())
+    }
+}
+
+/// Reads the IPTC records of a JPEG or TIFF. Other formats and files without IPTC give an empty result.
+pub fn read_for_image(image: &Path) -> Result<IptcMetadata, AppError> {
+    let mut reader = BufReader::new(File::open(image)?);
+    let mut magic = [0u8; 4];
+    if reader.read_exact(&mut magic).is_err() {
+        return Ok(IptcMetadata::default());
+    }
+    reader.seek(SeekFrom::Start(0))?;
+    let records = match magic {
+        [0xFF, 0xD8, ..] => jpeg_records(&mut reader)?,
+        [b'I', b'I', 42, 0] | [b'M', b'M', 0, 42] => tiff_records(&mut reader)?,
+        _ => None,
+    };
+    Ok(records.map(|r| parse_records(&r)).unwrap_or_default())
+}
+
+/// Walks the JPEG markers up to the image data and returns the IPTC block of the first APP13 segment carrying one.
+fn jpeg_records<R: Read + Seek>(reader: &mut R) -> Result<Option<Vec<u8>>, AppError> {
+    reader.seek(SeekFrom::Start(2))?;
+    loop {
+        let mut marker = [0u8; 2];
+        if reader.read_exact(&mut marker).is_err() || marker[0] != 0xFF {
+            return Ok(None);
+        }
+        match marker[1] {
+            // Fill bytes before a marker.
+            0xFF => {
+                reader.seek(SeekFrom::Current(-1))?;
+                continue;
+            }
+            // Markers without a length.
+            0x01 | 0xD0..=0xD7 => continue,
+            // Start of scan or end of image: no metadata follows.
+            0xDA | 0xD9 => return Ok(None),
+            _ => {}
+        }
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        let length = u16::from_be_bytes(length) as usize;
+        if length < 2 {
+            return Ok(None);
+        }
+        if marker[1] != 0xED {
+            reader.seek(SeekFrom::Current(length as i64 - 2))?;
+            continue;
+        }
+        let mut segment = vec![0u8; length - 2];
+        reader.read_exact(&mut segment)?;
+        if let Some(records) = segment
+            .strip_prefix(b"Photoshop 3.0\0")
+            .and_then(photoshop_iptc_resource)
+        {
+            return Ok(Some(records.to_vec()));
+        }
+    }
+}
+
+/// Finds the IPTC resource among the `8BIM` image resource blocks of an APP13 segment.
+fn photoshop_iptc_resource(mut blocks: &[u8]) -> Option<&[u8]> {
+    while blocks.len() >= 12 && blocks.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([blocks[4], blocks[5]]);
+        // A Pascal string name, padded to an even length including its length byte.
+        let name_length = blocks[6] as usize;
+        let mut pos = 7 + name_length;
+        pos += pos % 2;
+        let size_bytes = blocks.get(pos..pos + 4)?;
+        let size = u32::from_be_bytes(size_bytes.try_into().ok()?) as usize;
+        pos += 4;
+        let data = blocks.get(pos..pos + size)?;
+        if id == IPTC_RESOURCE_ID {
+            return Some(data);
+        }
+        pos += size + size % 2;
+        blocks = blocks.get(pos..)?;
+    }
+    None
+}
+
+/// Looks up the IPTC tag in the first IFD of a TIFF and returns its raw bytes.
+fn tiff_records<R: Read + Seek>(reader: &mut R) -> Result<Option<Vec<u8>>, AppError> {
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    let little_endian = header[0] == b'I';
+    let u16_at = |b: &[u8]| {
+        let b = [b[0], b[1]];
+        if little_endian { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) }
+    };
+    let u32_at = |b: &[u8]| {
+        let b = [b[0], b[1], b[2], b[3]];
+        if little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) }
+    };
+
+    reader.seek(SeekFrom::Start(u32_at(&header[4..]) as u64))?;
+    let mut count = [0u8; 2];
+    reader.read_exact(&mut count)?;
+    for _ in 0..u16_at(&count) {
+        let mut entry = [0u8; 12];
+        reader.read_exact(&mut entry)?;
+        if u16_at(&entry[0..]) != IPTC_TIFF_TAG {
+            continue;
+        }
+        // Written as BYTE, UNDEFINED or LONG depending on the software; the bytes are the same.
+        let unit = match u16_at(&entry[2..]) {
+            1 | 2 | 7 => 1,
+            3 => 2,
+            4 => 4,
+            _ => return Ok(None),
+        };
+        let length = u32_at(&entry[4..]) as u64 * unit;
+        if length <= 4 {
+            return Ok(Some(entry[8..8 + length as usize].to_vec()));
+        }
+        reader.seek(SeekFrom::Start(u32_at(&entry[8..]) as u64))?;
+        // The length comes from the file, so only read what is actually there instead of trusting it.
+        let mut records = Vec::new();
+        reader.by_ref().take(length).read_to_end(&mut records)?;
+        if (records.len() as u64) < length {
+            return Err(AppError::Io(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "IPTC data runs past the end of the file",
+            )));
+        }
+        return Ok(Some(records));
+    }
+    Ok(None)
+}
+
+/// Decodes the IIM datasets of the envelope (1) and application (2) records.
+fn parse_records(mut data: &[u8]) -> IptcMetadata {
+    let mut datasets = Vec::new();
+    let mut utf8 = false;
+    while data.len() >= 5 && data[0] == 0x1C {
+        let (record, dataset) = (data[1], data[2]);
+        let mut length = u16::from_be_bytes([data[3], data[4]]) as usize;
+        let mut pos = 5;
+        // Extended datasets store the number of length bytes that follow.
+        if length & 0x8000 != 0 {
+            let length_bytes = length & 0x7FFF;
+            let Some(bytes) = data.get(pos..pos + length_bytes) else { break };
+            length = bytes.iter().fold(0usize, |acc, b| (acc << 8) | *b as usize);
+            pos += length_bytes;
+        }
+        let Some(value) = pos.checked_add(length).and_then(|end| data.get(pos..end)) else { break };
+        match (record, dataset) {
+            (1, 90) => utf8 = value == UTF8_CHARSET,
+            (2, _) => datasets.push((dataset, value)),
+            _ => {}
+        }
+        data = &data[pos + length..];
+    }
+
+    let mut iptc = IptcMetadata::default();
+    for (dataset, value) in datasets {
+        let Some(value) = decode(value, utf8) else { continue };
+        let field = match dataset {
+            5 => &mut iptc.title,
+            105 => &mut iptc.headline,
+            120 => &mut iptc.caption,
+            110 => &mut iptc.credit,
+            115 => &mut iptc.source,
+            116 => &mut iptc.copyright,
+            90 => &mut iptc.city,
+            92 => &mut iptc.sublocation,
+            95 => &mut iptc.province_state,
+            101 => &mut iptc.country,
+            100 => &mut iptc.country_code,
+            25 => {
+                if !iptc.keywords.contains(&value) {
+                    iptc.keywords.push(value);
+                }
+                continue;
+            }
+            80 => {
+                iptc.bylines.push(value);
+                continue;
+            }
+            _ => continue,
+        };
+        field.get_or_insert(value);
+    }
+    iptc
+}
+
+/// Text is UTF-8 when declared so, and otherwise taken as UTF-8 if valid and Latin-1 if not.
+fn decode(value: &[u8], utf8: bool) -> Option<String> {
+    let text = match std::str::from_utf8(value) {
+        Ok(text) => text.to_string(),
+        Err(_) if utf8 => String::from_utf8_lossy(value).to_string(),
+        Err(_) => value.iter().map(|b| *b as char).collect(),
+    };
+    let text = text.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A standard dataset, with its length in the two bytes after the tag.
+    fn dataset(record: u8, dataset: u8, value: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0x1C, record, dataset];
+        bytes.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    /// An extended dataset, whose length follows in `length_bytes` big-endian bytes.
+    fn extended_dataset(record: u8, dataset: u8, length_bytes: usize, value: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0x1C, record, dataset];
+        bytes.extend_from_slice(&(0x8000 | length_bytes as u16).to_be_bytes());
+        bytes.extend_from_slice(&(value.len() as u64).to_be_bytes()[8 - length_bytes..]);
+        bytes.extend_from_slice(value);
+        bytes
+    }
+
+    #[test]
+    fn reads_a_caption_longer_than_a_standard_dataset() {
+        let caption = "a".repeat(40_000);
+        let mut data = extended_dataset(2, 120, 4, caption.as_bytes());
+        data.extend(dataset(2, 25, b"beach"));
+
+        let iptc = parse_records(&data);
+        assert_eq!(iptc.caption.as_deref(), Some(caption.as_str()));
+        assert_eq!(iptc.keywords, ["beach"]);
+    }
+
+    #[test]
+    fn reads_a_short_value_in_an_extended_dataset() {
+        let mut data = dataset(1, 90, UTF8_CHARSET);
+        data.extend(extended_dataset(2, 90, 2, "Zürich".as_bytes()));
+        data.extend(dataset(2, 5, b"Title"));
+
+        let iptc = parse_records(&data);
+        assert_eq!(iptc.city.as_deref(), Some("Zürich"));
+        assert_eq!(iptc.title.as_deref(), Some("Title"));
+    }
+
+    #[test]
+    fn stops_at_a_truncated_extended_dataset() {
+        let mut data = dataset(2, 25, b"kept");
+        let mut truncated = extended_dataset(2, 120, 4, &[b'x'; 100]);
+        truncated.truncate(50);
+        data.extend(truncated);
+
+        assert_eq!(parse_records(&data).keywords, ["kept"]);
+        assert_eq!(parse_records(&data).caption, None);
+        // Length bytes cut off before the value starts.
+        assert_eq!(parse_records(&data[..data.len() - 45]).keywords, ["kept"]);
+    }
+
+    #[test]
+    fn ignores_an_extended_length_beyond_the_address_space() {
+        let mut data = dataset(2, 25, b"kept");
+        data.extend([0x1C, 2, 120, 0x80, 8]);
+        data.extend([0xFF; 8]);
+        data.extend(b"caption");
+
+        let iptc = parse_records(&data);
+        assert_eq!(iptc.keywords, ["kept"]);
+        assert_eq!(iptc.caption, None);
+    }
+}
@@ -0,0 +1,119 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many finished jobs are remembered. The oldest ones are forgotten when a new job starts.
+const MAX_FINISHED_JOBS: usize = 100;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum JobState {
+    Running,
+    Completed,
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: String,
+    pub state: JobState,
+    pub started_at: u64,
+    pub finished_at: Option<u64>,
+    pub done: u64,
+    pub total: u64,
+    pub error: Option<String>,
+    #[serde(skip)]
+    pub report: Option<serde_json::Value>,
+}
+
+/// Keeps track of long running background jobs so clients can poll their progress.
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, JobInfo>>,
+    next_id: AtomicU64,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a new running job and returns its ID.
+    pub fn start(&self, kind: &str) -> String {
+        let id = format!("{}-{}", kind, self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let job = JobInfo {
+            id: id.clone(),
+            kind: kind.to_string(),
+            state: JobState::Running,
+            started_at: now(),
+            finished_at: None,
+            done: 0,
+            total: 0,
+            error: None,
+            report: None,
+        };
+        let mut jobs = self.jobs.lock().unwrap();
+        jobs.insert(id.clone(), job);
+        evict_finished(&mut jobs);
+        drop(jobs);
+        log::info!("Started job {}", id);
+        id
+    }
+
+    pub fn set_progress(&self, id: &str, done: u64, total: u64) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.done = done;
+            job.total = total;
+        }
+    }
+
+    pub fn complete(&self, id: &str, report: serde_json::Value) {
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.state = JobState::Completed;
+            job.finished_at = Some(now());
+            job.report = Some(report);
+        }
+        log::info!("Job {} completed", id);
+    }
+
+    pub fn fail(&self, id: &str, error: String) {
+        log::error!("Job {} failed: {}", id, error);
+        if let Some(job) = self.jobs.lock().unwrap().get_mut(id) {
+            job.state = JobState::Failed;
+            job.finished_at = Some(now());
+            job.error = Some(error);
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<JobInfo> {
+        self.jobs.lock().unwrap().get(id).cloned()
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        let mut jobs: Vec<JobInfo> = self.jobs.lock().unwrap().values().cloned().collect();
+        jobs.sort_by(|a, b| b.started_at.cmp(&a.started_at).then_with(|| b.id.cmp(&a.id)));
+        jobs
+    }
+}
+
+/// Forgets the earliest finished jobs and their reports once more than `MAX_FINISHED_JOBS` have finished.
+fn evict_finished(jobs: &mut HashMap<String, JobInfo>) {
+    let mut finished: Vec<(u64, String)> = jobs
+        .values()
+        .filter_map(|job| Some((job.finished_at?, job.id.clone())))
+        .collect();
+    if finished.len() <= MAX_FINISHED_JOBS {
+        return;
+    }
+    finished.sort();
+    for (_, id) in &finished[..finished.len() - MAX_FINISHED_JOBS] {
+        jobs.remove(id);
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
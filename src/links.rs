@@ -0,0 +1,109 @@
+use crate::error::AppError;
+use crate::processor;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkMethod {
+    Hardlink,
+    Reflink,
+    /// Try a reflink clone first and fall back to a hardlink.
+    #[default]
+    Auto,
+}
+
+/// Replaces `duplicate` with a link to `keep`, after checking both still hash to `expected_hash`.
+///
+/// The link is created next to `duplicate` and renamed over it, so the path is never missing.
+/// Returns the method that was actually used.
+pub fn replace_with_link(
+    keep: &Path,
+    duplicate: &Path,
+    expected_hash: &str,
+    method: LinkMethod,
+) -> Result<LinkMethod, AppError> {
+    for path in [keep, duplicate] {
+        let actual = processor::hash_file(path)?;
+        if actual != expected_hash {
+            return Err(AppError::Generic(format!(
+                "{:?} no longer matches hash {} (found {})",
+                path, expected_hash, actual
+            )));
+        }
+    }
+
+    if is_same_file(keep, duplicate)? {
+        log::debug!("{:?} is already hardlinked to {:?}", duplicate, keep);
+        return Ok(LinkMethod::Hardlink);
+    }
+
+    let tmp_path = temporary_path(duplicate);
+    let used = match method {
+        LinkMethod::Hardlink => hardlink(keep, &tmp_path).map(|_| LinkMethod::Hardlink),
+        LinkMethod::Reflink => reflink(keep, &tmp_path).map(|_| LinkMethod::Reflink),
+        LinkMethod::Auto => match reflink(keep, &tmp_path) {
+            Ok(_) => Ok(LinkMethod::Reflink),
+            Err(e) => {
+                log::debug!("Reflink of {:?} failed ({}), falling back to hardlink", keep, e);
+                hardlink(keep, &tmp_path).map(|_| LinkMethod::Hardlink)
+            }
+        },
+    };
+    let used = match used {
+        Ok(used) => used,
+        Err(e) => {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+    };
+
+    if let Err(e) = std::fs::rename(&tmp_path, duplicate) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(AppError::Io(e));
+    }
+    log::info!("Replaced {:?} with a {:?} to {:?}", duplicate, used, keep);
+    Ok(used)
+}
+
+fn temporary_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    path.with_file_name(format!(".{}.link-tmp", name))
+}
+
+fn hardlink(keep: &Path, target: &Path) -> Result<(), AppError> {
+    let _ = std::fs::remove_file(target);
+    std::fs::hard_link(keep, target)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_same_file(a: &Path, b: &Path) -> Result<bool, AppError> {
+    use std::os::unix::fs::MetadataExt;
+    let (a, b) = (std::fs::metadata(a)?, std::fs::metadata(b)?);
+    Ok(a.dev() == b.dev() && a.ino() == b.ino())
+}
+
+#[cfg(not(unix))]
+fn is_same_file(_a: &Path, _b: &Path) -> Result<bool, AppError> {
+    Ok(false)
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(keep: &Path, target: &Path) -> Result<(), AppError> {
+    use std::os::unix::io::AsRawFd;
+
+    let source = std::fs::File::open(keep)?;
+    let dest = std::fs::File::create(target)?;
+    // SAFETY: both descriptors are open for the duration of the call.
+    let result = unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE, source.as_raw_fd()) };
+    if result != 0 {
+        return Err(AppError::Io(std::io::Error::last_os_error()));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_keep: &Path, _target: &Path) -> Result<(), AppError> {
+    Err(AppError::Generic("Reflinks are only supported on Linux".to_string()))
+}
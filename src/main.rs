@@ -1,21 +1,54 @@
+mod albums;
+mod analyzers;
+mod annotations;
+mod bursts;
+mod colors;
 mod config;
+mod dedup;
 mod error;
+mod folders;
+mod geo;
 mod indexer;
+mod iptc;
+mod jobs;
+mod links;
 mod metadata;
+mod perceptual;
+mod places;
 mod processor;
+mod quality;
+mod render;
+mod reprocess;
 mod search;
 mod search_clients;
+mod similarity;
+mod stats;
+mod thumbnails;
+mod trash;
 mod walker;
 mod web_server;
+mod xmp;
 
+use crate::analyzers::AnalyzerRegistry;
+use crate::annotations::AnnotationStore;
 use crate::config::AppConfig;
+use crate::jobs::JobRegistry;
 use crate::search::Searcher;
+use crate::similarity::SimilarityIndex;
 use crate::search_clients::{elasticsearch::ElasticsearchSearcher, tantivy::TantivySearcher};
 use anyhow::Result;
 use log::info;
 use std::sync::Arc;
 
-pub fn start_indexing_job(config: AppConfig, searcher: Arc<dyn Searcher>) {
+pub fn start_indexing_job(
+    config: AppConfig,
+    searcher: Arc<dyn Searcher>,
+    annotations: Arc<AnnotationStore>,
+    analyzers: Arc<AnalyzerRegistry>,
+    jobs: Arc<JobRegistry>,
+    similarity: Arc<SimilarityIndex>,
+) {
+    let grouping_config = config.clone();
     tokio::spawn(async move {
         let result = async {
             searcher.ensure_index_exists().await?;
@@ -26,6 +59,7 @@ pub fn start_indexing_job(config: AppConfig, searcher: Arc<dyn Searcher>) {
             let (metadata_tx, metadata_rx) = crossbeam_channel::unbounded();
 
             let searcher_clone_for_indexer = searcher.clone();
+            let similarity_for_indexer = similarity.clone();
             let config_for_processor = config.clone();
 
             // Run indexing in the background
@@ -36,13 +70,13 @@ pub fn start_indexing_job(config: AppConfig, searcher: Arc<dyn Searcher>) {
             });
 
             let processor_handle = tokio::task::spawn_blocking(move || {
-                if let Err(e) = processor::start_processing(config_for_processor, paths_rx, metadata_tx, existing_hashes) {
+                if let Err(e) = processor::start_processing(config_for_processor, paths_rx, metadata_tx, existing_hashes, &analyzers) {
                     log::error!("Processor error: {}", e);
                 }
             });
 
             let indexer_handle = tokio::task::spawn_blocking(move || {
-                if let Err(e) = indexer::start_indexing(searcher_clone_for_indexer, metadata_rx) {
+                if let Err(e) = indexer::start_indexing(searcher_clone_for_indexer, annotations, similarity_for_indexer, metadata_rx) {
                     log::error!("Indexer error: {}", e);
                 }
             });
@@ -51,6 +85,13 @@ pub fn start_indexing_job(config: AppConfig, searcher: Arc<dyn Searcher>) {
             tokio::try_join!(walker_handle, processor_handle, indexer_handle)?;
 
             log::info!("All indexing tasks have completed.");
+            similarity.save()?;
+
+            let job_id = jobs.start("group-bursts");
+            match bursts::regroup(&searcher, &grouping_config, &jobs, &job_id).await {
+                Ok(report) => jobs.complete(&job_id, serde_json::to_value(&report)?),
+                Err(e) => jobs.fail(&job_id, e.to_string()),
+            }
 
             Ok::<(), anyhow::Error>(())
         }.await;
@@ -72,11 +113,15 @@ async fn main() -> Result<()> {
 
     info!("Starting image-indexer");
 
+    thumbnails::migrate_flat_layout(std::path::Path::new(&config.thumbnail_directory))?;
+
     let searcher: Arc<dyn Searcher> = if config.engine == "elasticsearch" {
         Arc::new(ElasticsearchSearcher::new(&config)?)
     } else {
         Arc::new(TantivySearcher::new(&config)?)
     };
+    // Brings an index created by an older version up to date before anything reads from it
+    searcher.ensure_index_exists().await?;
 
     // Run the web server in the foreground
     if let Err(e) = web_server::start_web_server(Arc::new(config), searcher).await {
@@ -1,8 +1,15 @@
 // src/metadata.rs
 
+use crate::annotations::Annotations;
+use crate::colors::ColorInfo;
+use crate::iptc::IptcMetadata;
+use crate::places::Place;
+use crate::quality::QualityMetrics;
+use crate::xmp::XmpMetadata;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct ImageMetadata {
     pub file_path: String,
     pub file_hash: String,
@@ -13,6 +20,110 @@ pub struct ImageMetadata {
     pub date_taken: Option<String>,
     pub gps_latitude: Option<f64>,
     pub gps_longitude: Option<f64>,
+    #[serde(default)]
+    pub has_exif: bool,
+    #[serde(default)]
+    pub file_size: u64,
+    /// Filesystem timestamps in Unix seconds.
+    #[serde(default)]
+    pub modified_at: Option<i64>,
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    /// Lowercase, without the leading dot.
+    #[serde(default)]
+    pub extension: String,
+    #[serde(default)]
+    pub mime_type: String,
     pub thumbnail_path: String,
-    pub duplicate_paths: Vec<String>,
+    /// Every known copy of the content, including the one at `file_path`.
+    #[serde(default)]
+    pub copies: Vec<FileCopy>,
+    /// Duplicate paths that have been replaced by a hardlink or reflink to `file_path`.
+    #[serde(default)]
+    pub linked_paths: Vec<String>,
+    /// Keywords, rating, caption and regions from embedded XMP and `.xmp` sidecars.
+    #[serde(default)]
+    pub xmp: XmpMetadata,
+    /// Caption, keywords, credits and location from IPTC-IIM records.
+    #[serde(default)]
+    pub iptc: IptcMetadata,
+    /// Country, region and city nearest to the GPS position.
+    #[serde(default)]
+    pub place: Option<Place>,
+    /// Average and dominant colors, computed from the thumbnail.
+    #[serde(default)]
+    pub colors: Option<ColorInfo>,
+    /// 64-bit difference hash of the thumbnail, as hex.
+    #[serde(default)]
+    pub perceptual_hash: Option<String>,
+    /// Fraction of thumbnail pixels per color bin, for finding similar images.
+    #[serde(default)]
+    pub color_histogram: Option<Vec<f32>>,
+    /// The burst or bracketed series the image belongs to, and whether it is the best shot of it.
+    #[serde(default)]
+    pub group_id: Option<String>,
+    #[serde(default)]
+    pub best_pick: bool,
+    /// Version of each analyzer that last ran successfully on the image, to find outdated documents.
+    #[serde(default)]
+    pub analyzer_versions: BTreeMap<String, u32>,
+    /// Sharpness, noise and exposure scores.
+    #[serde(flatten)]
+    pub quality: QualityMetrics,
+    /// Tags, rating, colour label and favorite flag set by users.
+    #[serde(flatten)]
+    pub annotations: Annotations,
+}
+
+/// One physical copy of an image's content.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct FileCopy {
+    pub path: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub modified_at: Option<i64>,
+    /// The scan directory the copy was found under.
+    #[serde(default)]
+    pub library: String,
+    /// Unix seconds when the copy was first indexed.
+    #[serde(default)]
+    pub first_seen: i64,
+}
+
+impl ImageMetadata {
+    /// All copy paths, starting with `file_path`.
+    pub fn paths(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.file_path.as_str()).chain(
+            self.copies
+                .iter()
+                .map(|c| c.path.as_str())
+                .filter(move |p| *p != self.file_path),
+        )
+    }
+
+    /// Paths of every copy other than `file_path`.
+    pub fn duplicate_paths(&self) -> Vec<String> {
+        self.paths().skip(1).map(|p| p.to_string()).collect()
+    }
+
+    /// XMP and IPTC keywords without repeats.
+    pub fn keywords(&self) -> Vec<&str> {
+        let mut keywords: Vec<&str> = Vec::new();
+        for keyword in self.xmp.keywords.iter().chain(&self.iptc.keywords) {
+            if !keywords.contains(&keyword.as_str()) {
+                keywords.push(keyword);
+            }
+        }
+        keywords
+    }
+
+    /// Records `copy` unless its path is already known. Returns whether it was added.
+    pub fn add_copy(&mut self, copy: FileCopy) -> bool {
+        if self.copies.iter().any(|c| c.path == copy.path) {
+            return false;
+        }
+        self.copies.push(copy);
+        true
+    }
 }
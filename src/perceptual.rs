@@ -0,0 +1,29 @@
+use image::imageops::FilterType;
+use image::DynamicImage;
+
+/// A 64-bit difference hash: the image is shrunk to 9x8 grey pixels and each bit records
+/// whether a pixel is brighter than its right neighbour. Near-identical shots differ in few bits.
+pub fn dhash(image: &DynamicImage) -> u64 {
+    let small = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+    let mut hash = 0u64;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash = (hash << 1) | (small.get_pixel(x, y).0[0] > small.get_pixel(x + 1, y).0[0]) as u64;
+        }
+    }
+    hash
+}
+
+/// Hashes are stored as 16 hex digits, since not every backend has unsigned 64-bit integers.
+pub fn to_hex(hash: u64) -> String {
+    format!("{:016x}", hash)
+}
+
+pub fn from_hex(hash: &str) -> Option<u64> {
+    u64::from_str_radix(hash, 16).ok()
+}
+
+/// The number of differing bits.
+pub fn distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
@@ -0,0 +1,269 @@
+use crate::error::AppError;
+use crate::metadata::ImageMetadata;
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Images further than this from every known city are left without a place.
+const MAX_DISTANCE_KM: f64 = 100.0;
+const EARTH_RADIUS_KM: f64 = 6371.0;
+const KM_PER_DEGREE: f64 = 111.2;
+
+/// Where an image was taken, from the nearest gazetteer city.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Place {
+    /// ISO 3166 alpha-2 code.
+    pub country_code: String,
+    pub country: String,
+    /// First-level administrative division, such as a state or canton.
+    pub region: Option<String>,
+    pub city: String,
+}
+
+struct City {
+    name: String,
+    latitude: f64,
+    longitude: f64,
+    country_code: String,
+    admin1_code: String,
+}
+
+/// An offline reverse geocoder over a GeoNames `cities*.txt` dump.
+///
+/// Country and region names are read from `countryInfo.txt` and `admin1CodesASCII.txt` when they
+/// sit next to the cities file; otherwise the codes are used as names.
+#[derive(Default)]
+pub struct Gazetteer {
+    cities: Vec<City>,
+    /// City indexes by whole-degree cell.
+    grid: HashMap<(i32, i32), Vec<usize>>,
+    countries: HashMap<String, String>,
+    regions: HashMap<String, String>,
+}
+
+impl Gazetteer {
+    /// Loads the cities file at `path`, or returns an empty gazetteer if `path` is empty.
+    pub fn load(path: &str) -> Result<Self, AppError> {
+        if path.is_empty() {
+            log::info!("No gazetteer configured; images will not be reverse geocoded.");
+            return Ok(Self::default());
+        }
+        let path = Path::new(path);
+        let mut gazetteer = Self::default();
+        for line in std::fs::read_to_string(path)?.lines() {
+            let columns: Vec<&str> = line.split('\t').collect();
+            if columns.len() < 11 {
+                continue;
+            }
+            let (Ok(latitude), Ok(longitude)) = (columns[4].parse::<f64>(), columns[5].parse::<f64>()) else {
+                continue;
+            };
+            gazetteer
+                .grid
+                .entry(cell(latitude, longitude))
+                .or_default()
+                .push(gazetteer.cities.len());
+            gazetteer.cities.push(City {
+                name: columns[1].to_string(),
+                latitude,
+                longitude,
+                country_code: columns[8].to_string(),
+                admin1_code: columns[10].to_string(),
+            });
+        }
+
+        let directory = path.parent().unwrap_or(Path::new("."));
+        // ISO, ISO3, ISO-Numeric, fips, Country, ...
+        gazetteer.countries = read_names(&directory.join("countryInfo.txt"), 4);
+        // CC.ADM1, name, asciiname, geonameid
+        gazetteer.regions = read_names(&directory.join("admin1CodesASCII.txt"), 1);
+        log::info!(
+            "Loaded {} cities, {} country and {} region names from {:?}",
+            gazetteer.cities.len(),
+            gazetteer.countries.len(),
+            gazetteer.regions.len(),
+            path
+        );
+        Ok(gazetteer)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cities.is_empty()
+    }
+
+    /// The place of the nearest city within `MAX_DISTANCE_KM`.
+    pub fn lookup(&self, latitude: f64, longitude: f64) -> Option<Place> {
+        if self.is_empty() || !(-90.0..=90.0).contains(&latitude) || !(-180.0..=180.0).contains(&longitude) {
+            return None;
+        }
+        let lat_cells = (MAX_DISTANCE_KM / KM_PER_DEGREE).ceil() as i32;
+        // Degrees of longitude shrink towards the poles, so more cells are needed to cover the same distance.
+        let lon_cells = (MAX_DISTANCE_KM / (KM_PER_DEGREE * latitude.to_radians().cos().max(0.01)))
+            .ceil()
+            .min(180.0) as i32;
+        let (lat_cell, lon_cell) = cell(latitude, longitude);
+
+        let mut nearest: Option<(f64, &City)> = None;
+        for dlat in -lat_cells..=lat_cells {
+            for dlon in -lon_cells..=lon_cells {
+                // Wrap around the antimeridian.
+                let lon = (lon_cell + dlon + 180).rem_euclid(360) - 180;
+                let Some(indexes) = self.grid.get(&(lat_cell + dlat, lon)) else { continue };
+                for &index in indexes {
+                    let city = &self.cities[index];
+                    let distance = haversine_km(latitude, longitude, city.latitude, city.longitude);
+                    if distance <= MAX_DISTANCE_KM && nearest.is_none_or(|(best, _)| distance < best) {
+                        nearest = Some((distance, city));
+                    }
+                }
+            }
+        }
+
+        nearest.map(|(_, city)| Place {
+            country: self
+                .countries
+                .get(&city.country_code)
+                .cloned()
+                .unwrap_or_else(|| city.country_code.clone()),
+            region: self
+                .regions
+                .get(&format!("{}.{}", city.country_code, city.admin1_code))
+                .cloned()
+                .or_else(|| (!city.admin1_code.is_empty()).then(|| city.admin1_code.clone())),
+            country_code: city.country_code.clone(),
+            city: city.name.clone(),
+        })
+    }
+}
+
+fn cell(latitude: f64, longitude: f64) -> (i32, i32) {
+    (latitude.floor() as i32, longitude.floor() as i32)
+}
+
+fn haversine_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (dlat, dlon) = ((lat2 - lat1).to_radians(), (lon2 - lon1).to_radians());
+    let a = (dlat / 2.0).sin().powi(2) + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Reads a tab-separated GeoNames code list into code -> the name in `name_column`. Missing files are fine.
+fn read_names(path: &Path, name_column: usize) -> HashMap<String, String> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return HashMap::new();
+    };
+    contents
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split('\t').collect();
+            Some((columns.first()?.to_string(), columns.get(name_column)?.to_string()))
+        })
+        .collect()
+}
+
+#[derive(Serialize, Debug)]
+pub struct CountryNode {
+    pub country_code: String,
+    pub country: String,
+    pub image_count: u64,
+    pub regions: Vec<RegionNode>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RegionNode {
+    pub region: Option<String>,
+    pub image_count: u64,
+    pub cities: Vec<CityNode>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CityNode {
+    pub city: String,
+    pub image_count: u64,
+    /// An image taken there, to show as its cover.
+    pub thumbnail_hash: Option<String>,
+}
+
+/// Country code, region and city.
+type CityKey = (String, Option<String>, String);
+
+#[derive(Debug)]
+struct CityCount {
+    country: String,
+    image_count: u64,
+    thumbnail_hash: Option<String>,
+}
+
+/// Image counts per country, region and city, built one image or aggregation bucket at a time.
+#[derive(Debug, Default)]
+pub struct PlaceCounts {
+    cities: BTreeMap<CityKey, CityCount>,
+}
+
+impl PlaceCounts {
+    pub fn add(&mut self, metadata: &ImageMetadata) {
+        if let Some(place) = &metadata.place {
+            self.add_count(place, 1, Some(&metadata.file_hash));
+        }
+    }
+
+    pub fn add_count(&mut self, place: &Place, count: u64, sample_hash: Option<&str>) {
+        let key = (place.country_code.clone(), place.region.clone(), place.city.clone());
+        let entry = self.cities.entry(key).or_insert_with(|| CityCount {
+            country: place.country.clone(),
+            image_count: 0,
+            thumbnail_hash: None,
+        });
+        entry.image_count += count;
+        if entry.thumbnail_hash.is_none() {
+            entry.thumbnail_hash = sample_hash.map(|h| h.to_string());
+        }
+    }
+
+    /// Countries, regions and cities, each level ordered by descending image count.
+    pub fn tree(self) -> Vec<CountryNode> {
+        let mut countries: BTreeMap<String, CountryNode> = BTreeMap::new();
+        for ((country_code, region, city), count) in self.cities {
+            let CityCount {
+                country,
+                image_count,
+                thumbnail_hash,
+            } = count;
+            let country_node = countries.entry(country_code.clone()).or_insert_with(|| CountryNode {
+                country_code,
+                country,
+                image_count: 0,
+                regions: Vec::new(),
+            });
+            country_node.image_count += image_count;
+            let region_node = match country_node.regions.iter_mut().position(|r| r.region == region) {
+                Some(position) => &mut country_node.regions[position],
+                None => {
+                    country_node.regions.push(RegionNode {
+                        region,
+                        image_count: 0,
+                        cities: Vec::new(),
+                    });
+                    country_node.regions.last_mut().unwrap()
+                }
+            };
+            region_node.image_count += image_count;
+            region_node.cities.push(CityNode {
+                city,
+                image_count,
+                thumbnail_hash,
+            });
+        }
+
+        let mut countries: Vec<CountryNode> = countries.into_values().collect();
+        for country in &mut countries {
+            for region in &mut country.regions {
+                region.cities.sort_by_key(|c| Reverse(c.image_count));
+            }
+            country.regions.sort_by_key(|r| Reverse(r.image_count));
+        }
+        countries.sort_by_key(|c| Reverse(c.image_count));
+        countries
+    }
+}
@@ -1,19 +1,20 @@
+use crate::analyzers::{self, AnalysisInput, AnalyzerRegistry};
 use crate::config::AppConfig;
 use crate::error::AppError;
-use crate::metadata::ImageMetadata;
-use exif::Reader;
+use crate::metadata::{FileCopy, ImageMetadata};
 use rayon::prelude::*;
 use sha2::{Digest, Sha256};
 use std::collections::HashSet;
 use std::fs::File;
-use std::io::{BufReader, Read};
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 
 pub fn start_processing(
     config: AppConfig,
     paths_rx: crossbeam_channel::Receiver<PathBuf>,
     metadata_tx: crossbeam_channel::Sender<ImageMetadata>,
     existing_hashes: HashSet<String>,
+    analyzers: &AnalyzerRegistry,
 ) -> Result<(), AppError> {
     log::info!("Starting image processing with {} workers", config.num_workers);
     log::debug!("Processor will use thumbnail directory: {}", config.thumbnail_directory);
@@ -24,7 +25,7 @@ pub fn start_processing(
 
     paths.into_par_iter().try_for_each(|path| {
         log::info!("Processing image started for: {:?}", path); // Log when processing starts for a specific image
-        match process_image(&config, &path, &existing_hashes) {
+        match process_image(&config, analyzers, &path, &existing_hashes) {
             Ok(Some(metadata)) => {
                 log::trace!("Extracted metadata for {:?}: {:?}", path, metadata);
                 metadata_tx.send(metadata)?;
@@ -47,8 +48,8 @@ pub fn start_processing(
     Ok(())
 }
 
-fn process_image(config: &AppConfig, path: &PathBuf, existing_hashes: &HashSet<String>) -> Result<Option<ImageMetadata>, AppError> {
-    log::trace!("Calculating hash for image: {:?}", path);
+/// Returns the hex SHA-256 digest of the file's content.
+pub fn hash_file(path: &Path) -> Result<String, AppError> {
     let mut file = File::open(path)?;
     let mut hasher = Sha256::new();
     let mut buffer = [0; 1024];
@@ -59,94 +60,33 @@ fn process_image(config: &AppConfig, path: &PathBuf, existing_hashes: &HashSet<S
         }
         hasher.update(&buffer[..n]);
     }
-    let hash = format!("{:x}", hasher.finalize());
-    log::debug!("Calculated hash for {:?}: {}", path, hash);
-
-    if existing_hashes.contains(&hash) {
-        return Ok(None);
-    }
-
-    log::trace!("Extracting EXIF data for image: {:?}", path);
-    let file_for_exif = File::open(path)?; // Reopen file for EXIF
-    let mut buf_reader = BufReader::new(file_for_exif);
-    let exif_reader = Reader::new();
-    let exif = exif_reader.read_from_container(&mut buf_reader).ok();
-
-    log::trace!("Getting image dimensions for image: {:?}", path);
-    let (width, height) = image::image_dimensions(path).map_err(|e| {
-        log::warn!("Could not get dimensions for {:?}: {}", path, e);
-        e
-    })?;
-    log::debug!("Dimensions for {:?}: {}x{}", path, width, height);
+    Ok(format!("{:x}", hasher.finalize()))
+}
 
+/// Runs every enabled analyzer on the image at `path`. Returns `None` without analyzing
+/// further when its hash is in `existing_hashes`.
+pub fn process_image(
+    config: &AppConfig,
+    analyzers: &AnalyzerRegistry,
+    path: &PathBuf,
+    existing_hashes: &HashSet<String>,
+) -> Result<Option<ImageMetadata>, AppError> {
+    let input = AnalysisInput::new(config, path);
     let mut metadata = ImageMetadata {
         file_path: path.to_string_lossy().to_string(),
-        file_hash: hash,
-        width,
-        height,
-        camera_make: None,
-        camera_model: None,
-        date_taken: None,
-        gps_latitude: None,
-        gps_longitude: None,
-        thumbnail_path: "".to_string(),
-        duplicate_paths: vec![],
+        ..Default::default()
     };
-
-    if let Some(exif) = exif {
-        log::trace!("EXIF data found for {:?}", path);
-        if let Some(field) = exif.get_field(exif::Tag::Make, exif::In::PRIMARY) {
-            metadata.camera_make = Some(field.display_value().to_string());
-            log::trace!("Camera make: {}", metadata.camera_make.as_ref().unwrap());
-        }
-        if let Some(field) = exif.get_field(exif::Tag::Model, exif::In::PRIMARY) {
-            metadata.camera_model = Some(field.display_value().to_string());
-            log::trace!("Camera model: {}", metadata.camera_model.as_ref().unwrap());
-        }
-        if let Some(field) =
-            exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)
-        {
-            metadata.date_taken = Some(field.display_value().to_string());
-            log::trace!("Date taken: {}", metadata.date_taken.as_ref().unwrap());
-        }
-        if let Some(field) =
-            exif.get_field(exif::Tag::GPSLatitude, exif::In::PRIMARY)
-        {
-            if let exif::Value::Rational(gps) = &field.value {
-                metadata.gps_latitude = Some(gps.iter().map(|v| v.to_f64()).sum());
-                log::trace!("GPS Latitude: {}", metadata.gps_latitude.unwrap());
-            }
-        }
-        if let Some(field) =
-            exif.get_field(exif::Tag::GPSLongitude, exif::In::PRIMARY)
-        {
-            if let exif::Value::Rational(gps) = &field.value {
-                metadata.gps_longitude = Some(gps.iter().map(|v| v.to_f64()).sum());
-                log::trace!("GPS Longitude: {}", metadata.gps_longitude.unwrap());
-            }
-        }
-    } else {
-        log::debug!("No EXIF data found for {:?}", path);
-    }
-
-    log::trace!("Generating thumbnail for image: {:?}", path);
-    let thumbnail_dir = std::path::Path::new(&config.thumbnail_directory);
-    if !thumbnail_dir.exists() {
-        std::fs::create_dir_all(thumbnail_dir)?;
-        log::debug!("Created thumbnail directory: {:?}", thumbnail_dir);
+    if !analyzers.analyze(&input, &mut metadata, |m| existing_hashes.contains(&m.file_hash))? {
+        return Ok(None);
     }
-    let thumbnail_path = thumbnail_dir
-        .join(format!("{}.jpg", metadata.file_hash));
-    
-    log::trace!("Opening image for thumbnail generation: {:?}", path);
-    let image = image::open(path).map_err(|e| {
-        log::warn!("Could not open image for thumbnail generation {:?}: {}", path, e);
-        e
-    })?;
-    let thumbnail = image.thumbnail(256, 256);
-    thumbnail.save(&thumbnail_path)?;
-    metadata.thumbnail_path = thumbnail_path.to_string_lossy().to_string();
-    log::debug!("Thumbnail saved to: {:?}", thumbnail_path);
+    log::debug!("Analyzed {:?}: {}x{}, hash {}", path, metadata.width, metadata.height, metadata.file_hash);
 
+    metadata.copies = vec![FileCopy {
+        path: metadata.file_path.clone(),
+        size: metadata.file_size,
+        modified_at: metadata.modified_at,
+        library: config.scan_directory.clone(),
+        first_seen: analyzers::unix_seconds(std::time::SystemTime::now()),
+    }];
     Ok(Some(metadata))
 }
@@ -0,0 +1,105 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, GrayImage};
+use serde::{Deserialize, Serialize};
+
+/// Images are scaled down to this many pixels on the long side first, so scores of
+/// images of different resolutions are comparable and large files stay cheap to analyze.
+const ANALYSIS_SIZE: u32 = 1024;
+/// Luma at or above this counts as a clipped highlight, and at or below `SHADOW_CLIP` as a clipped shadow.
+const HIGHLIGHT_CLIP: u8 = 250;
+const SHADOW_CLIP: u8 = 5;
+
+/// Sharpness, noise and exposure scores, computed from the full image.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct QualityMetrics {
+    /// Variance of the Laplacian of the luma. Low values mean a blurry or featureless image.
+    #[serde(default)]
+    pub sharpness: Option<f64>,
+    /// Estimated standard deviation of the noise, in luma levels (0 to 255).
+    #[serde(default)]
+    pub noise: Option<f64>,
+    /// Mean luma from 0 (black) to 1 (white).
+    #[serde(default)]
+    pub mean_luminance: Option<f64>,
+    /// Fractions of pixels that are blown out or crushed to black.
+    #[serde(default)]
+    pub clipped_highlights: Option<f64>,
+    #[serde(default)]
+    pub clipped_shadows: Option<f64>,
+}
+
+impl QualityMetrics {
+    pub fn analyze(image: &DynamicImage) -> Self {
+        let luma = if image.width().max(image.height()) > ANALYSIS_SIZE {
+            image.resize(ANALYSIS_SIZE, ANALYSIS_SIZE, FilterType::Triangle).to_luma8()
+        } else {
+            image.to_luma8()
+        };
+        let pixels = luma.pixels().len();
+        if pixels == 0 {
+            return Self::default();
+        }
+
+        let (mut sum, mut highlights, mut shadows) = (0u64, 0usize, 0usize);
+        for pixel in luma.pixels() {
+            let value = pixel.0[0];
+            sum += value as u64;
+            highlights += (value >= HIGHLIGHT_CLIP) as usize;
+            shadows += (value <= SHADOW_CLIP) as usize;
+        }
+        Self {
+            sharpness: laplacian_variance(&luma),
+            noise: noise_sigma(&luma),
+            mean_luminance: Some(round(sum as f64 / pixels as f64 / 255.0)),
+            clipped_highlights: Some(round(highlights as f64 / pixels as f64)),
+            clipped_shadows: Some(round(shadows as f64 / pixels as f64)),
+        }
+    }
+
+    /// Sharpness scaled down by the clipped fraction of the image, for picking the best of similar shots.
+    /// Unanalyzed images score 0.
+    pub fn score(&self) -> f64 {
+        let clipped = self.clipped_highlights.unwrap_or(0.0) + self.clipped_shadows.unwrap_or(0.0);
+        self.sharpness.unwrap_or(0.0) * (1.0 - clipped).max(0.0)
+    }
+}
+
+/// Applies a 3x3 kernel to every interior pixel.
+fn convolve(luma: &GrayImage, kernel: [[i32; 3]; 3]) -> Option<Vec<f64>> {
+    let (width, height) = luma.dimensions();
+    if width < 3 || height < 3 {
+        return None;
+    }
+    let mut values = Vec::with_capacity(((width - 2) * (height - 2)) as usize);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let mut value = 0;
+            for (dy, row) in kernel.iter().enumerate() {
+                for (dx, weight) in row.iter().enumerate() {
+                    value += weight * luma.get_pixel(x + dx as u32 - 1, y + dy as u32 - 1).0[0] as i32;
+                }
+            }
+            values.push(value as f64);
+        }
+    }
+    Some(values)
+}
+
+fn laplacian_variance(luma: &GrayImage) -> Option<f64> {
+    let values = convolve(luma, [[0, 1, 0], [1, -4, 1], [0, 1, 0]])?;
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    let variance = values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64;
+    Some(round(variance))
+}
+
+/// Immerkær's fast noise estimate: a kernel that cancels out edges and smooth gradients
+/// leaves mostly noise, whose mean absolute response is proportional to its deviation.
+fn noise_sigma(luma: &GrayImage) -> Option<f64> {
+    let values = convolve(luma, [[1, -2, 1], [-2, 4, -2], [1, -2, 1]])?;
+    let mean_abs = values.iter().map(|v| v.abs()).sum::<f64>() / values.len() as f64;
+    Some(round(mean_abs * (std::f64::consts::PI / 2.0).sqrt() / 6.0))
+}
+
+fn round(value: f64) -> f64 {
+    (value * 1000.0).round() / 1000.0
+}
@@ -0,0 +1,335 @@
+use crate::error::AppError;
+use image::codecs::jpeg::JpegEncoder;
+use image::imageops::FilterType;
+use image::{DynamicImage, GenericImageView, ImageOutputFormat};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::{BufReader, Cursor};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::Semaphore;
+use walkdir::WalkDir;
+
+const MAX_RENDER_DIMENSION: u32 = 8192;
+const DEFAULT_QUALITY: u8 = 85;
+
+/// Makes the temporary file of every render unique, so concurrent renders never share one.
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Fit {
+    /// Scale down to fit inside the box, keeping the aspect ratio.
+    #[default]
+    Contain,
+    /// Scale and crop so the box is completely covered.
+    Cover,
+    /// Stretch to exactly the requested size.
+    Fill,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderFormat {
+    #[default]
+    Jpeg,
+    Png,
+}
+
+impl RenderFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            RenderFormat::Jpeg => "jpg",
+            RenderFormat::Png => "png",
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            RenderFormat::Jpeg => "image/jpeg",
+            RenderFormat::Png => "image/png",
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct RenderParams {
+    pub w: Option<u32>,
+    pub h: Option<u32>,
+    #[serde(default)]
+    pub fit: Fit,
+    #[serde(default)]
+    pub format: RenderFormat,
+    pub quality: Option<u8>,
+}
+
+impl RenderParams {
+    pub fn validate(&self) -> Result<(), AppError> {
+        for dimension in [self.w, self.h].into_iter().flatten() {
+            if dimension == 0 || dimension > MAX_RENDER_DIMENSION {
+                return Err(AppError::BadRequest(format!(
+                    "Width and height must be between 1 and {}",
+                    MAX_RENDER_DIMENSION
+                )));
+            }
+        }
+        if let Some(quality) = self.quality {
+            if !(1..=100).contains(&quality) {
+                return Err(AppError::BadRequest("Quality must be between 1 and 100".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    /// A file name that uniquely identifies this rendition of `hash`.
+    pub fn cache_key(&self, hash: &str) -> String {
+        let dimension = |d: Option<u32>| d.map(|d| d.to_string()).unwrap_or_else(|| "auto".to_string());
+        let quality = match self.format {
+            RenderFormat::Jpeg => format!("-q{}", self.quality.unwrap_or(DEFAULT_QUALITY)),
+            RenderFormat::Png => String::new(),
+        };
+        format!(
+            "{}-{}x{}-{:?}{}.{}",
+            hash,
+            dimension(self.w),
+            dimension(self.h),
+            self.fit,
+            quality,
+            self.format.extension()
+        )
+        .to_lowercase()
+    }
+}
+
+/// A rendition ready to serve.
+pub enum Rendition {
+    /// A file in the cache.
+    Cached(PathBuf),
+    /// A freshly rendered image that could not be stored in the cache.
+    Uncached(Vec<u8>),
+}
+
+/// Produces resized renditions of originals and keeps them in a size-bounded on-disk cache.
+pub struct Renderer {
+    cache: Arc<RenderCache>,
+    limit: Semaphore,
+}
+
+/// The on-disk renditions. Its methods do blocking I/O and run on the blocking thread pool.
+struct RenderCache {
+    directory: PathBuf,
+    max_bytes: u64,
+    current_bytes: Mutex<u64>,
+}
+
+impl Renderer {
+    pub fn new(cache_directory: &str, max_bytes: u64, concurrency: usize) -> Result<Self, AppError> {
+        let cache_directory = PathBuf::from(cache_directory);
+        std::fs::create_dir_all(&cache_directory)?;
+        let current_bytes = WalkDir::new(&cache_directory)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| e.metadata().ok())
+            .map(|m| m.len())
+            .sum();
+        log::debug!("Render cache at {:?} holds {} bytes", cache_directory, current_bytes);
+
+        Ok(Self {
+            cache: Arc::new(RenderCache {
+                directory: cache_directory,
+                max_bytes,
+                current_bytes: Mutex::new(current_bytes),
+            }),
+            limit: Semaphore::new(concurrency.max(1)),
+        })
+    }
+
+    fn cache_path(&self, hash: &str, key: &str) -> PathBuf {
+        let shard = hash.get(0..2).unwrap_or("__");
+        self.cache.directory.join(shard).join(key)
+    }
+
+    /// Returns the cached rendition, rendering it from `source` first if needed.
+    pub async fn render(&self, hash: &str, source: PathBuf, params: RenderParams) -> Result<Rendition, AppError> {
+        let cache_path = self.cache_path(hash, &params.cache_key(hash));
+        if touch_blocking(&cache_path).await? {
+            log::trace!("Render cache hit: {:?}", cache_path);
+            return Ok(Rendition::Cached(cache_path));
+        }
+
+        let _permit = self
+            .limit
+            .acquire()
+            .await
+            .map_err(|e| AppError::Generic(e.to_string()))?;
+        // Another request may have rendered it while we were waiting for a permit.
+        if touch_blocking(&cache_path).await? {
+            return Ok(Rendition::Cached(cache_path));
+        }
+
+        log::debug!("Rendering {:?} with {:?}", source, params);
+        let cache = self.cache.clone();
+        tokio::task::spawn_blocking(move || {
+            let bytes = render_image(&source, &params)?;
+            match cache.insert(&cache_path, &bytes) {
+                Ok(()) => Ok(Rendition::Cached(cache_path)),
+                Err(e) => {
+                    log::warn!("Failed to cache rendition {:?}: {}", cache_path, e);
+                    Ok(Rendition::Uncached(bytes))
+                }
+            }
+        })
+        .await?
+    }
+}
+
+impl RenderCache {
+    fn insert(&self, cache_path: &Path, bytes: &[u8]) -> Result<(), AppError> {
+        if let Some(parent) = cache_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = cache_path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        if let Err(e) = std::fs::write(&tmp_path, bytes) {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+
+        // Renaming under the lock keeps the size of a rendition that replaced an identical one
+        // from another render from being counted twice.
+        let mut current_bytes = self.current_bytes.lock().unwrap();
+        let replaced = std::fs::metadata(cache_path).map(|m| m.len()).unwrap_or(0);
+        if let Err(e) = std::fs::rename(&tmp_path, cache_path) {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(e.into());
+        }
+        *current_bytes = current_bytes.saturating_sub(replaced) + bytes.len() as u64;
+        if *current_bytes > self.max_bytes {
+            *current_bytes = self.evict(cache_path);
+        }
+        Ok(())
+    }
+
+    /// Deletes the least recently used renditions until the cache is back under its limit.
+    /// Returns the new cache size.
+    fn evict(&self, keep: &Path) -> u64 {
+        let mut entries: Vec<(SystemTime, u64, PathBuf)> = WalkDir::new(&self.directory)
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_file())
+            .filter_map(|e| {
+                let metadata = e.metadata().ok()?;
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                Some((modified, metadata.len(), e.into_path()))
+            })
+            .collect();
+        entries.sort_by_key(|(modified, _, _)| *modified);
+
+        let mut total: u64 = entries.iter().map(|(_, size, _)| size).sum();
+        for (_, size, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+            match std::fs::remove_file(&path) {
+                Ok(_) => {
+                    log::trace!("Evicted rendition: {:?}", path);
+                    total -= size;
+                }
+                Err(e) => log::warn!("Failed to evict rendition {:?}: {}", path, e),
+            }
+        }
+        log::debug!("Render cache trimmed to {} bytes", total);
+        total
+    }
+}
+
+/// `touch` on the blocking thread pool.
+async fn touch_blocking(path: &Path) -> Result<bool, AppError> {
+    let path = path.to_path_buf();
+    Ok(tokio::task::spawn_blocking(move || touch(&path)).await?)
+}
+
+/// Marks a cached rendition as recently used. Returns false if it does not exist.
+fn touch(path: &Path) -> bool {
+    match File::options().append(true).open(path) {
+        Ok(file) => {
+            let _ = file.set_modified(SystemTime::now());
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Decodes `source`, applies its EXIF orientation, resizes and re-encodes it.
+pub fn render_image(source: &Path, params: &RenderParams) -> Result<Vec<u8>, AppError> {
+    let image = image::open(source)?;
+    let image = apply_orientation(image, read_orientation(source));
+    let image = resize(image, params);
+
+    let mut bytes = Vec::new();
+    match params.format {
+        RenderFormat::Jpeg => {
+            let quality = params.quality.unwrap_or(DEFAULT_QUALITY);
+            let rgb = DynamicImage::ImageRgb8(image.to_rgb8());
+            JpegEncoder::new_with_quality(&mut bytes, quality).encode_image(&rgb)?;
+        }
+        RenderFormat::Png => {
+            image.write_to(&mut Cursor::new(&mut bytes), ImageOutputFormat::Png)?;
+        }
+    }
+    Ok(bytes)
+}
+
+fn read_orientation(source: &Path) -> u32 {
+    let exif = File::open(source)
+        .ok()
+        .and_then(|file| exif::Reader::new().read_from_container(&mut BufReader::new(file)).ok());
+    exif.and_then(|exif| {
+        exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+    })
+    .unwrap_or(1)
+}
+
+fn apply_orientation(image: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => image.fliph(),
+        3 => image.rotate180(),
+        4 => image.flipv(),
+        5 => image.rotate90().fliph(),
+        6 => image.rotate90(),
+        7 => image.rotate270().fliph(),
+        8 => image.rotate270(),
+        _ => image,
+    }
+}
+
+fn resize(image: DynamicImage, params: &RenderParams) -> DynamicImage {
+    let (width, height) = image.dimensions();
+    let (target_w, target_h) = match (params.w, params.h) {
+        (None, None) => return image,
+        (Some(w), Some(h)) => (w, h),
+        (Some(w), None) => (w, scale(height, w, width)),
+        (None, Some(h)) => (scale(width, h, height), h),
+    };
+
+    match params.fit {
+        Fit::Contain if target_w >= width && target_h >= height => image,
+        Fit::Contain => image.resize(target_w, target_h, FilterType::CatmullRom),
+        Fit::Cover => image.resize_to_fill(target_w, target_h, FilterType::CatmullRom),
+        Fit::Fill => image.resize_exact(target_w, target_h, FilterType::CatmullRom),
+    }
+}
+
+fn scale(value: u32, numerator: u32, denominator: u32) -> u32 {
+    ((value as u64 * numerator as u64) / denominator.max(1) as u64).max(1) as u32
+}
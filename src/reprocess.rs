@@ -0,0 +1,130 @@
+use crate::analyzers::{self, AnalysisInput, AnalyzerRegistry};
+use crate::bursts;
+use crate::config::AppConfig;
+use crate::error::AppError;
+use crate::jobs::JobRegistry;
+use crate::metadata::ImageMetadata;
+use crate::processor;
+use crate::search::{SearchRequest, Searcher};
+use crate::similarity::SimilarityIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ReprocessRequest {
+    /// Analyzers to re-run on every selected image whatever their version. When empty, each
+    /// image gets its outdated analyzers re-run.
+    #[serde(default)]
+    pub analyzers: BTreeSet<String>,
+    /// Only reprocess images matching these `/api/images` filters.
+    #[serde(default)]
+    pub filter: Option<SearchRequest>,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ReprocessReport {
+    pub images: u64,
+    /// Images with no analyzer to re-run.
+    pub current: u64,
+    /// Documents whose metadata changed and were rewritten.
+    pub updated: u64,
+    pub unchanged: u64,
+    /// Images whose analyzers failed or whose copies on disk no longer match their hash.
+    pub failed: u64,
+    /// Images without any copy left on disk.
+    pub missing: u64,
+}
+
+/// Re-extracts the metadata of every indexed image, or of the ones matching the filter, reading
+/// the first copy on disk whose content still matches, and updates the documents that changed in
+/// place. Bursts are regrouped afterwards if anything changed.
+pub async fn reprocess(
+    searcher: &Arc<dyn Searcher>,
+    config: &Arc<AppConfig>,
+    analyzers: &Arc<AnalyzerRegistry>,
+    similarity: &SimilarityIndex,
+    jobs: &JobRegistry,
+    job_id: &str,
+    request: ReprocessRequest,
+) -> Result<ReprocessReport, AppError> {
+    let images = match &request.filter {
+        Some(filter) => searcher.search_all(filter).await?,
+        None => searcher.get_all_images().await?,
+    };
+    let total = images.len() as u64;
+    let mut report = ReprocessReport {
+        images: total,
+        ..Default::default()
+    };
+    for (done, metadata) in images.into_iter().enumerate() {
+        jobs.set_progress(job_id, done as u64, total);
+        let names = if request.analyzers.is_empty() {
+            analyzers.outdated(&metadata)
+        } else {
+            request.analyzers.clone()
+        };
+        if names.is_empty() {
+            report.current += 1;
+            continue;
+        }
+        let hash = metadata.file_hash.clone();
+        let (config, analyzers) = (config.clone(), analyzers.clone());
+        let analyzed = tokio::task::spawn_blocking(move || -> Result<Option<(ImageMetadata, ImageMetadata)>, AppError> {
+            let existing: Vec<PathBuf> = metadata.paths().map(PathBuf::from).filter(|p| p.exists()).collect();
+            if existing.is_empty() {
+                return Ok(None);
+            }
+            // A copy edited since it was indexed would attach the wrong metadata to this hash.
+            let path = existing
+                .into_iter()
+                .find(|p| processor::hash_file(p).is_ok_and(|actual| actual == metadata.file_hash))
+                .ok_or_else(|| AppError::Generic("no copy on disk still matches its hash".to_string()))?;
+            let input = AnalysisInput::new(&config, &path);
+            let mut updated = metadata.clone();
+            analyzers.reanalyze(&names, &input, &mut updated)?;
+            Ok(Some((metadata, updated)))
+        })
+        .await?;
+        match analyzed {
+            Ok(Some((before, after))) if serde_json::to_value(&before)? != serde_json::to_value(&after)? => {
+                // Re-read the document so annotations and other edits made meanwhile are kept.
+                let Some(mut current) = searcher.get_by_hash(&hash).await? else {
+                    report.missing += 1;
+                    continue;
+                };
+                analyzers::copy_analyzed_fields(after, &mut current);
+                similarity.insert(&current);
+                searcher.update_document(current).await?;
+                report.updated += 1;
+            }
+            Ok(Some(_)) => report.unchanged += 1,
+            Ok(None) => report.missing += 1,
+            Err(e) => {
+                log::warn!("Could not reprocess image {}: {}", hash, e);
+                report.failed += 1;
+            }
+        }
+    }
+    jobs.set_progress(job_id, total, total);
+    similarity.save()?;
+    log::info!(
+        "Reprocessed {} images: {} updated, {} unchanged, {} failed, {} missing, {} already current",
+        report.images,
+        report.updated,
+        report.unchanged,
+        report.failed,
+        report.missing,
+        report.current
+    );
+
+    if report.updated > 0 {
+        let group_job_id = jobs.start("group-bursts");
+        match bursts::regroup(searcher, config, jobs, &group_job_id).await {
+            Ok(group_report) => jobs.complete(&group_job_id, serde_json::to_value(&group_report)?),
+            Err(e) => jobs.fail(&group_job_id, e.to_string()),
+        }
+    }
+    Ok(report)
+}
@@ -1,15 +1,289 @@
+use crate::annotations::{ColorLabel, Facets};
+use crate::bursts::BurstFields;
+use crate::colors::{self, Lab};
 use crate::error::AppError;
+use crate::folders::FolderCounts;
+use crate::geo::{BoundingBox, GeoClusters};
 use crate::metadata::ImageMetadata;
+use crate::places::PlaceCounts;
+use crate::stats::LibraryStats;
 use async_trait::async_trait;
+use serde::Deserialize;
 use std::collections::HashSet;
 
+const DEFAULT_SEARCH_LIMIT: usize = 100;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    /// Best text match first.
+    #[default]
+    Relevance,
+    FileSize,
+    ModifiedAt,
+    CreatedAt,
+    Width,
+    Height,
+    Rating,
+    Sharpness,
+    Noise,
+    MeanLuminance,
+    ClippedHighlights,
+    ClippedShadows,
+}
+
+impl SortField {
+    /// The indexed field to sort on, or `None` for relevance.
+    pub fn field_name(&self) -> Option<&'static str> {
+        match self {
+            SortField::Relevance => None,
+            SortField::FileSize => Some("file_size"),
+            SortField::ModifiedAt => Some("modified_at"),
+            SortField::CreatedAt => Some("created_at"),
+            SortField::Width => Some("width"),
+            SortField::Height => Some("height"),
+            SortField::Rating => Some("rating"),
+            SortField::Sharpness => Some("sharpness"),
+            SortField::Noise => Some("noise"),
+            SortField::MeanLuminance => Some("mean_luminance"),
+            SortField::ClippedHighlights => Some("clipped_highlights"),
+            SortField::ClippedShadows => Some("clipped_shadows"),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// A free text query plus optional filters, sort and paging, as sent to `GET /api/images`.
+///
+/// Timestamps are Unix seconds; bounds are inclusive.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SearchRequest {
+    #[serde(default)]
+    pub q: String,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub modified_after: Option<i64>,
+    pub modified_before: Option<i64>,
+    pub extension: Option<String>,
+    pub mime_type: Option<String>,
+    /// Only images with a copy directly in this folder.
+    pub folder: Option<String>,
+    /// Only images with a copy anywhere below this folder.
+    pub path_prefix: Option<String>,
+    /// Only images in this album, by ID or name. Also accepted as `album:` in `q`.
+    pub album: Option<String>,
+    /// Only these content hashes. Filled in from `album` by the web server.
+    #[serde(skip)]
+    pub hashes: Option<Vec<String>>,
+    /// Only images carrying every one of these comma-separated tags.
+    pub tags: Option<String>,
+    pub min_rating: Option<u8>,
+    pub color_label: Option<ColorLabel>,
+    pub favorite: Option<bool>,
+    /// Only images with this XMP or IPTC keyword.
+    pub keyword: Option<String>,
+    /// Only images credited to this IPTC by-line.
+    pub byline: Option<String>,
+    /// Only images with this IPTC city or country.
+    pub city: Option<String>,
+    pub country: Option<String>,
+    /// Only images reverse geocoded to this country (name or ISO code), region or city.
+    pub place_country: Option<String>,
+    pub place_region: Option<String>,
+    pub place_city: Option<String>,
+    /// Bounds on the quality scores, inclusive.
+    pub min_sharpness: Option<f64>,
+    pub max_sharpness: Option<f64>,
+    pub min_noise: Option<f64>,
+    pub max_noise: Option<f64>,
+    pub min_luminance: Option<f64>,
+    pub max_luminance: Option<f64>,
+    pub min_clipped_highlights: Option<f64>,
+    pub max_clipped_highlights: Option<f64>,
+    pub min_clipped_shadows: Option<f64>,
+    pub max_clipped_shadows: Option<f64>,
+    /// Only images in this burst group.
+    pub group_id: Option<String>,
+    /// Hide every grouped image except its group's best pick.
+    #[serde(default)]
+    pub collapse_groups: bool,
+    /// Only images with a dominant color near this one (`#rrggbb` or a name), closest first.
+    /// Overrides `sort`.
+    pub color: Option<String>,
+    /// The largest CIE76 ΔE to `color` still counted as a match.
+    pub color_tolerance: Option<f64>,
+    #[serde(default)]
+    pub sort: SortField,
+    #[serde(default)]
+    pub order: SortOrder,
+    #[serde(default = "default_search_limit")]
+    pub limit: usize,
+    #[serde(default)]
+    pub offset: usize,
+}
+
+fn default_search_limit() -> usize {
+    DEFAULT_SEARCH_LIMIT
+}
+
+impl Default for SearchRequest {
+    fn default() -> Self {
+        Self {
+            q: String::new(),
+            min_size: None,
+            max_size: None,
+            modified_after: None,
+            modified_before: None,
+            extension: None,
+            mime_type: None,
+            folder: None,
+            path_prefix: None,
+            album: None,
+            hashes: None,
+            tags: None,
+            min_rating: None,
+            color_label: None,
+            favorite: None,
+            keyword: None,
+            byline: None,
+            city: None,
+            country: None,
+            place_country: None,
+            place_region: None,
+            place_city: None,
+            min_sharpness: None,
+            max_sharpness: None,
+            min_noise: None,
+            max_noise: None,
+            min_luminance: None,
+            max_luminance: None,
+            min_clipped_highlights: None,
+            max_clipped_highlights: None,
+            min_clipped_shadows: None,
+            max_clipped_shadows: None,
+            group_id: None,
+            collapse_groups: false,
+            color: None,
+            color_tolerance: None,
+            sort: SortField::default(),
+            order: SortOrder::default(),
+            limit: DEFAULT_SEARCH_LIMIT,
+            offset: 0,
+        }
+    }
+}
+
+impl SearchRequest {
+    /// Extensions are stored lowercase and without the leading dot.
+    pub fn normalized_extension(&self) -> Option<String> {
+        self.extension
+            .as_deref()
+            .map(|e| e.trim_start_matches('.').to_lowercase())
+            .filter(|e| !e.is_empty())
+    }
+
+    pub fn tag_list(&self) -> Vec<String> {
+        self.tags
+            .as_deref()
+            .unwrap_or_default()
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect()
+    }
+
+    /// The quality score fields with a lower or upper bound set.
+    pub fn quality_ranges(&self) -> Vec<(&'static str, Option<f64>, Option<f64>)> {
+        [
+            ("sharpness", self.min_sharpness, self.max_sharpness),
+            ("noise", self.min_noise, self.max_noise),
+            ("mean_luminance", self.min_luminance, self.max_luminance),
+            ("clipped_highlights", self.min_clipped_highlights, self.max_clipped_highlights),
+            ("clipped_shadows", self.min_clipped_shadows, self.max_clipped_shadows),
+        ]
+        .into_iter()
+        .filter(|(_, min, max)| min.is_some() || max.is_some())
+        .collect()
+    }
+
+    /// The color to rank by and the tolerance, if `color` is set.
+    pub fn color_target(&self) -> Result<Option<(Lab, f64)>, AppError> {
+        let Some(color) = self.color.as_deref().filter(|c| !c.trim().is_empty()) else {
+            return Ok(None);
+        };
+        let tolerance = self.color_tolerance.unwrap_or(colors::DEFAULT_TOLERANCE);
+        if !tolerance.is_finite() || tolerance < 0.0 {
+            return Err(AppError::BadRequest(format!("Invalid color_tolerance {}", tolerance)));
+        }
+        Ok(Some((colors::parse_color(color)?, tolerance)))
+    }
+
+    /// Takes the album filter out of the request, moving an `album:name` or `album:"long name"`
+    /// term out of `q` if the `album` parameter is not set.
+    pub fn take_album(&mut self) -> Option<String> {
+        if let Some(album) = self.album.take() {
+            return Some(album);
+        }
+        let start = self
+            .q
+            .match_indices("album:")
+            .map(|(i, _)| i)
+            .find(|&i| i == 0 || self.q[..i].ends_with(char::is_whitespace))?;
+        let rest = &self.q[start + "album:".len()..];
+        let (album, consumed) = match rest.strip_prefix('"') {
+            Some(quoted) => match quoted.find('"') {
+                Some(end) => (&quoted[..end], end + 2),
+                None => (quoted, rest.len()),
+            },
+            None => {
+                let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                (&rest[..end], end)
+            }
+        };
+        let album = album.to_string();
+        let end = start + "album:".len() + consumed;
+        self.q = format!("{} {}", &self.q[..start], &self.q[end..]).trim().to_string();
+        Some(album)
+    }
+}
+
 #[async_trait]
 pub trait Searcher: Send + Sync {
     async fn ensure_index_exists(&self) -> Result<(), AppError>;
     async fn index_metadata(&self, metadata: ImageMetadata) -> Result<(), AppError>;
-    async fn search_images(&self, query: String) -> Result<Vec<ImageMetadata>, AppError>;
+    async fn search_images(&self, request: &SearchRequest) -> Result<Vec<ImageMetadata>, AppError>;
+    /// Every image matching the filters of `request`, ignoring its paging.
+    async fn search_all(&self, request: &SearchRequest) -> Result<Vec<ImageMetadata>, AppError>;
     async fn count_images(&self) -> Result<u64, AppError>;
     async fn delete_document(&self, hash: &str) -> Result<(), AppError>;
     async fn update_document(&self, metadata: ImageMetadata) -> Result<(), AppError>;
     async fn get_all_hashes(&self) -> Result<HashSet<String>, AppError>;
+    async fn get_by_hash(&self, hash: &str) -> Result<Option<ImageMetadata>, AppError>;
+    async fn get_by_path(&self, path: &str) -> Result<Option<ImageMetadata>, AppError>;
+    /// Every indexed image, for passes over the whole library.
+    async fn get_all_images(&self) -> Result<Vec<ImageMetadata>, AppError>;
+    /// The fields burst grouping reads, for every indexed image.
+    async fn get_burst_fields(&self) -> Result<Vec<BurstFields>, AppError>;
+    /// Returns every image that has at least one duplicate copy.
+    async fn get_duplicate_groups(&self) -> Result<Vec<ImageMetadata>, AppError>;
+    async fn compute_stats(&self) -> Result<LibraryStats, AppError>;
+    async fn compute_folders(&self) -> Result<FolderCounts, AppError>;
+    /// Counts annotations, keywords and IPTC credits and locations over the images matching `request`.
+    async fn compute_facets(&self, request: &SearchRequest) -> Result<Facets, AppError>;
+    /// Counts the images matching `request` per reverse geocoded country, region and city.
+    async fn compute_places(&self, request: &SearchRequest) -> Result<PlaceCounts, AppError>;
+    /// Buckets the geotagged images matching `request` inside `bbox` into geohash cells of `precision` characters.
+    async fn compute_geo_clusters(
+        &self,
+        request: &SearchRequest,
+        bbox: BoundingBox,
+        precision: usize,
+    ) -> Result<GeoClusters, AppError>;
 }
@@ -1,11 +1,19 @@
+use crate::bursts::BurstFields;
 use crate::config::AppConfig;
+use crate::analyzers;
+use crate::annotations::Facets;
+use crate::colors;
 use crate::error::AppError;
-use crate::metadata::ImageMetadata;
-use crate::search::Searcher;
+use crate::folders::FolderCounts;
+use crate::geo::{BoundingBox, GeoCluster, GeoClusters};
+use crate::metadata::{FileCopy, ImageMetadata};
+use crate::places::{Place, PlaceCounts};
+use crate::search::{SearchRequest, Searcher, SortOrder};
+use crate::stats::{self, LibraryStats};
 use async_trait::async_trait;
 use elasticsearch::{
     http::transport::{BuildError, SingleNodeConnectionPool, TransportBuilder},
-    CountParts, DeleteParts, Elasticsearch, IndexParts, SearchParts, UpdateParts,
+    CountParts, DeleteParts, Elasticsearch, GetParts, IndexParts, SearchParts, UpdateParts,
 };
 use serde_json::json;
 use std::collections::HashSet;
@@ -27,6 +35,623 @@ impl ElasticsearchSearcher {
         log::trace!("Elasticsearch client created successfully.");
         Ok(Self { client })
     }
+
+    /// Scrolls through every image a page at a time, handing each one to `visit`.
+    async fn for_each_image(&self, visit: impl FnMut(ImageMetadata) + Send) -> Result<(), AppError> {
+        self.for_each_matching(json!({ "query": { "match_all": {} } }), visit).await
+    }
+
+    /// Scrolls through the images matched by the `query` and `min_score` of `search`, handing each one to `visit`.
+    async fn for_each_matching(
+        &self,
+        search: serde_json::Value,
+        mut visit: impl FnMut(ImageMetadata) + Send,
+    ) -> Result<(), AppError> {
+        self.for_each_source(search, |source| {
+            visit(from_source(source)?);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Scrolls through the hits of `search` a page at a time, handing the `_source` of each one to `visit`.
+    async fn for_each_source(
+        &self,
+        mut search: serde_json::Value,
+        mut visit: impl FnMut(serde_json::Value) -> Result<(), AppError> + Send,
+    ) -> Result<(), AppError> {
+        search["size"] = json!(1000);
+        let mut scroll_id: Option<String> = None;
+
+        loop {
+            let response = if let Some(sid) = &scroll_id {
+                self.client
+                    .scroll(elasticsearch::ScrollParts::ScrollId(sid))
+                    .scroll("1m")
+                    .send()
+                    .await?
+            } else {
+                self.client
+                    .search(SearchParts::Index(&[INDEX_NAME]))
+                    .scroll("1m")
+                    .body(search.clone())
+                    .send()
+                    .await?
+            };
+
+            let body = response.json::<serde_json::Value>().await?;
+            let hits = match body["hits"]["hits"].as_array() {
+                Some(hits) if !hits.is_empty() => hits,
+                _ => break,
+            };
+
+            for hit in hits {
+                visit(hit["_source"].clone())?;
+            }
+
+            scroll_id = body["_scroll_id"].as_str().map(|s| s.to_string());
+            if scroll_id.is_none() {
+                break;
+            }
+        }
+
+        if let Some(sid) = scroll_id.as_deref() {
+            self.client
+                .clear_scroll(elasticsearch::ClearScrollParts::ScrollId(&[sid]))
+                .send()
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Adds the fields of the current mapping that the `existing` mapping of an older index lacks,
+    /// leaving the fields it already has alone.
+    async fn add_missing_mappings(&self, existing: &serde_json::Value) -> Result<(), AppError> {
+        let Some(missing) = missing_mappings(&index_mappings(), existing) else {
+            return Ok(());
+        };
+        let response = self
+            .client
+            .indices()
+            .put_mapping(elasticsearch::indices::IndicesPutMappingParts::Index(&[INDEX_NAME]))
+            .body(missing)
+            .send()
+            .await?;
+        if !response.status_code().is_success() {
+            let body = response.json::<serde_json::Value>().await.unwrap_or_default();
+            return Err(AppError::Generic(format!(
+                "Could not add the new fields to the mapping of Elasticsearch index '{}' ({}). \
+                 Delete it and rerun indexing.",
+                INDEX_NAME, body["error"]["reason"]
+            )));
+        }
+        Ok(())
+    }
+
+    /// Copies the `concrete` index behind `images` into a new index created with the current
+    /// mapping, converting the documents on the way, then deletes it and points the `images` alias
+    /// at the copy. Needed when a field changed type, which Elasticsearch cannot do in place.
+    async fn reindex_into_new_index(&self, concrete: &str) -> Result<(), AppError> {
+        let target = format!("{}-{}", INDEX_NAME, analyzers::unix_seconds(std::time::SystemTime::now()));
+        log::warn!("Reindexing Elasticsearch index '{}' into '{}' to change field types", concrete, target);
+        self.client
+            .indices()
+            .create(elasticsearch::indices::IndicesCreateParts::Index(&target))
+            .body(json!({ "mappings": index_mappings() }))
+            .send()
+            .await?
+            .error_for_status_code()?;
+
+        let body = self
+            .client
+            .reindex()
+            .wait_for_completion(true)
+            .refresh(true)
+            .body(json!({
+                "source": { "index": concrete },
+                "dest": { "index": target },
+                "script": {
+                    "source": format!("{}{}", MIGRATE_DUPLICATE_PATHS_SCRIPT, BACKFILL_LOCATION_SCRIPT),
+                    "lang": "painless"
+                }
+            }))
+            .send()
+            .await?
+            .error_for_status_code()?
+            .json::<serde_json::Value>()
+            .await?;
+        if let Some(failure) = body["failures"].as_array().and_then(|f| f.first()) {
+            // The old index is still intact, so drop the partial copy and stop.
+            self.client
+                .indices()
+                .delete(elasticsearch::indices::IndicesDeleteParts::Index(&[&target]))
+                .send()
+                .await?;
+            return Err(AppError::Generic(format!(
+                "Could not reindex Elasticsearch index '{}' into '{}' ({}). \
+                 It was left unchanged; delete it and rerun indexing instead.",
+                concrete, target, failure["cause"]["reason"]
+            )));
+        }
+
+        self.client
+            .indices()
+            .update_aliases()
+            .body(json!({
+                "actions": [
+                    { "remove_index": { "index": concrete } },
+                    { "add": { "index": target, "alias": INDEX_NAME } }
+                ]
+            }))
+            .send()
+            .await?
+            .error_for_status_code()?;
+        log::info!("Reindexed {} documents into '{}'", body["created"].as_u64().unwrap_or(0), target);
+        Ok(())
+    }
+
+    /// Sets the `location` of geotagged documents indexed before the field was mapped.
+    async fn backfill_locations(&self) -> Result<(), AppError> {
+        let body = self
+            .client
+            .update_by_query(elasticsearch::UpdateByQueryParts::Index(&[INDEX_NAME]))
+            .conflicts(elasticsearch::params::Conflicts::Proceed)
+            .refresh(true)
+            .body(json!({
+                "query": {
+                    "bool": {
+                        "filter": [
+                            { "exists": { "field": "gps_latitude" } },
+                            { "exists": { "field": "gps_longitude" } }
+                        ],
+                        "must_not": [{ "exists": { "field": "location" } }]
+                    }
+                },
+                "script": { "source": BACKFILL_LOCATION_SCRIPT, "lang": "painless" }
+            }))
+            .send()
+            .await?
+            .error_for_status_code()?
+            .json::<serde_json::Value>()
+            .await?;
+        if let Some(failure) = body["failures"].as_array().and_then(|f| f.first()) {
+            return Err(AppError::Generic(format!(
+                "Could not add GPS locations to the Elasticsearch index '{}' ({}). Delete it and rerun indexing.",
+                INDEX_NAME, failure["cause"]["reason"]
+            )));
+        }
+        log::info!("Added GPS locations to {} documents", body["updated"].as_u64().unwrap_or(0));
+        Ok(())
+    }
+
+    /// Moves the `duplicate_paths` of documents written before copies were recorded into `copies`.
+    async fn migrate_duplicate_paths(&self) -> Result<(), AppError> {
+        let body = self
+            .client
+            .update_by_query(elasticsearch::UpdateByQueryParts::Index(&[INDEX_NAME]))
+            .conflicts(elasticsearch::params::Conflicts::Proceed)
+            .refresh(true)
+            .body(json!({
+                "query": { "exists": { "field": "duplicate_paths" } },
+                "script": { "source": MIGRATE_DUPLICATE_PATHS_SCRIPT, "lang": "painless" }
+            }))
+            .send()
+            .await?
+            .error_for_status_code()?
+            .json::<serde_json::Value>()
+            .await?;
+        let updated = body["updated"].as_u64().unwrap_or(0);
+        if updated > 0 {
+            log::info!("Moved the duplicate paths of {} documents into copies", updated);
+        }
+        Ok(())
+    }
+}
+
+/// The folders holding a copy of the image, as `folders::parent_folder` computes them.
+const PARENT_FOLDERS_SCRIPT: &str = "
+    List folders = new ArrayList();
+    for (p in doc['copies.path']) {
+        int i = p.lastIndexOf('/');
+        String folder = i > 0 ? p.substring(0, i) : (i == 0 ? '/' : '');
+        if (!folders.contains(folder)) { folders.add(folder); }
+    }
+    return folders;
+";
+
+/// Mirrors `from_source` for the documents in the index.
+const MIGRATE_DUPLICATE_PATHS_SCRIPT: &str = "
+    if (ctx._source.copies == null || ctx._source.copies.isEmpty()) {
+        List paths = new ArrayList();
+        paths.add(ctx._source.file_path);
+        for (p in ctx._source.duplicate_paths == null ? [] : ctx._source.duplicate_paths) {
+            if (!paths.contains(p)) { paths.add(p); }
+        }
+        List copies = new ArrayList();
+        for (p in paths) {
+            copies.add([
+                'path': p,
+                'size': ctx._source.file_size == null ? 0 : ctx._source.file_size,
+                'modified_at': ctx._source.modified_at,
+                'library': '',
+                'first_seen': 0
+            ]);
+        }
+        ctx._source.copies = copies;
+    }
+    ctx._source.remove('duplicate_paths');
+";
+
+/// Builds the `location` geo point of a document indexed before the field was mapped.
+const BACKFILL_LOCATION_SCRIPT: &str = "
+    if (ctx._source.location == null && ctx._source.gps_latitude != null && ctx._source.gps_longitude != null) {
+        ctx._source.location = ['lat': ctx._source.gps_latitude, 'lon': ctx._source.gps_longitude];
+    }
+";
+
+/// Sets `copies` to the number of copies `ImageMetadata::paths` yields and `size` to the indexed
+/// file size, for the `LibraryStats` sums.
+const STATS_COPIES_SCRIPT: &str = "
+    int copies = doc['copies.path'].size();
+    if (doc['file_path'].size() > 0 && !doc['copies.path'].contains(doc['file_path'].value)) { copies += 1; }
+    long size = doc['file_size'].size() == 0 ? 0 : doc['file_size'].value;
+";
+
+/// `1 / (1 + ΔE)` to the closest palette color covering at least `min_share`, decoding `colors::pack`.
+const PALETTE_DISTANCE_SCRIPT: &str = "
+    double best = Double.MAX_VALUE;
+    if (doc.containsKey('palette_lab')) {
+        for (long v : doc['palette_lab']) {
+            if ((v & 1023) / 1000.0 < params.min_share) { continue; }
+            double l = ((v >> 34) & 1023) / 10.0 - params.l;
+            double a = ((v >> 22) & 4095) / 10.0 - 128.0 - params.a;
+            double b = ((v >> 10) & 4095) / 10.0 - 128.0 - params.b;
+            best = Math.min(best, Math.sqrt(l * l + a * a + b * b));
+        }
+    }
+    return 1.0 / (1.0 + best);
+";
+
+/// The stored document: the metadata plus a `location` geo point built from the GPS coordinates
+/// and the palette packed into `palette_lab`.
+fn to_document(metadata: &ImageMetadata) -> Result<serde_json::Value, AppError> {
+    let mut document = serde_json::to_value(metadata)?;
+    if let (Some(lat), Some(lon)) = (metadata.gps_latitude, metadata.gps_longitude) {
+        document["location"] = json!({ "lat": lat, "lon": lon });
+    }
+    if let Some(colors) = &metadata.colors {
+        let packed: Vec<u64> = colors.palette_lab().into_iter().map(|(lab, share)| colors::pack(lab, share)).collect();
+        document["palette_lab"] = json!(packed);
+    }
+    Ok(document)
+}
+
+/// Reads a stored document. Documents written before copies were recorded keep their other
+/// paths in `duplicate_paths`; they become copies with the size and time of the primary one.
+fn from_source(mut source: serde_json::Value) -> Result<ImageMetadata, AppError> {
+    let duplicate_paths: Vec<String> = source
+        .as_object_mut()
+        .and_then(|s| s.remove("duplicate_paths"))
+        .and_then(|paths| serde_json::from_value(paths).ok())
+        .unwrap_or_default();
+    let mut metadata: ImageMetadata = serde_json::from_value(source)?;
+    if metadata.copies.is_empty() {
+        let mut paths = vec![metadata.file_path.clone()];
+        paths.extend(duplicate_paths.into_iter().filter(|p| *p != metadata.file_path));
+        metadata.copies = paths
+            .into_iter()
+            .map(|path| FileCopy {
+                path,
+                size: metadata.file_size,
+                modified_at: metadata.modified_at,
+                library: String::new(),
+                first_seen: 0,
+            })
+            .collect();
+    }
+    Ok(metadata)
+}
+
+/// The field mappings of the index, used to create it and to add new fields to an existing one.
+fn index_mappings() -> serde_json::Value {
+    let xmp_mapping = json!({
+        "properties": {
+            "keywords": { "type": "keyword" },
+            "rating": { "type": "byte" },
+            "description": { "type": "text" },
+            "headline": { "type": "text" },
+            "regions": {
+                "properties": {
+                    "name": { "type": "keyword", "fields": { "text": { "type": "text" } } },
+                    "kind": { "type": "keyword" },
+                    "x": { "type": "float" },
+                    "y": { "type": "float" },
+                    "w": { "type": "float" },
+                    "h": { "type": "float" }
+                }
+            }
+        }
+    });
+    let iptc_mapping = json!({
+        "properties": {
+            "title": { "type": "text" },
+            "headline": { "type": "text" },
+            "caption": { "type": "text" },
+            "keywords": { "type": "keyword" },
+            "bylines": { "type": "keyword", "fields": { "text": { "type": "text" } } },
+            "credit": { "type": "text" },
+            "source": { "type": "text" },
+            "copyright": { "type": "text" },
+            "city": { "type": "keyword", "fields": { "text": { "type": "text" } } },
+            "sublocation": { "type": "text" },
+            "province_state": { "type": "text" },
+            "country": { "type": "keyword", "fields": { "text": { "type": "text" } } },
+            "country_code": { "type": "keyword" }
+        }
+    });
+    let colors_mapping = json!({
+        "properties": {
+            "average": { "type": "keyword" },
+            "palette": {
+                "properties": {
+                    "color": { "type": "keyword" },
+                    "share": { "type": "float" }
+                }
+            }
+        }
+    });
+    json!({
+        "properties": {
+            "file_path": { "type": "keyword" },
+            "file_hash": { "type": "keyword" },
+            "has_exif": { "type": "boolean" },
+            "width": { "type": "integer" },
+            "height": { "type": "integer" },
+            "camera_make": { "type": "keyword" },
+            "camera_model": { "type": "keyword" },
+            "date_taken": { "type": "date", "format": "yyyy:MM:dd HH:mm:ss||yyyy-MM-dd HH:mm:ss||epoch_millis" },
+            "gps_latitude": { "type": "double" },
+            "gps_longitude": { "type": "double" },
+            "location": { "type": "geo_point" },
+            "thumbnail_path": { "type": "keyword" },
+            "copies": {
+                "type": "nested",
+                // Also index the copy fields flat on the image for term lookups and scripts.
+                "include_in_parent": true,
+                "properties": {
+                    "path": { "type": "keyword", "fields": { "text": { "type": "text" } } },
+                    "size": { "type": "long" },
+                    "modified_at": { "type": "date", "format": "epoch_second" },
+                    "library": { "type": "keyword" },
+                    "first_seen": { "type": "date", "format": "epoch_second" }
+                }
+            },
+            "linked_paths": { "type": "keyword" },
+            "file_size": { "type": "long" },
+            "modified_at": { "type": "date", "format": "epoch_second" },
+            "created_at": { "type": "date", "format": "epoch_second" },
+            "extension": { "type": "keyword" },
+            "mime_type": { "type": "keyword" },
+            "tags": { "type": "keyword" },
+            "rating": { "type": "byte" },
+            "color_label": { "type": "keyword" },
+            "favorite": { "type": "boolean" },
+            "sharpness": { "type": "double" },
+            "noise": { "type": "double" },
+            "mean_luminance": { "type": "double" },
+            "clipped_highlights": { "type": "double" },
+            "clipped_shadows": { "type": "double" },
+            "xmp": xmp_mapping,
+            "iptc": iptc_mapping,
+            "place": {
+                "properties": {
+                    "country_code": { "type": "keyword" },
+                    "country": { "type": "keyword" },
+                    "region": { "type": "keyword" },
+                    "city": { "type": "keyword" }
+                }
+            },
+            "colors": colors_mapping,
+            "perceptual_hash": { "type": "keyword" },
+            "color_histogram": { "type": "float", "index": false },
+            "group_id": { "type": "keyword" },
+            "best_pick": { "type": "boolean" },
+            "analyzer_versions": { "type": "object", "enabled": false },
+            // Each palette color packed by `colors::pack`, for ranking by color.
+            "palette_lab": { "type": "long" }
+        }
+    })
+}
+
+/// The part of the `wanted` mapping that the `existing` one lacks, or `None` if it has everything.
+/// Objects that gain fields keep their own settings, as Elasticsearch requires for nested ones.
+fn missing_mappings(wanted: &serde_json::Value, existing: &serde_json::Value) -> Option<serde_json::Value> {
+    let mut missing = serde_json::Map::new();
+    for (name, field) in wanted["properties"].as_object()? {
+        match existing["properties"].get(name) {
+            None => {
+                missing.insert(name.clone(), field.clone());
+            }
+            Some(current) if field.get("properties").is_some() => {
+                if let Some(fields) = missing_mappings(field, current) {
+                    let mut field = field.clone();
+                    field["properties"] = fields["properties"].clone();
+                    missing.insert(name.clone(), field);
+                }
+            }
+            Some(_) => {}
+        }
+    }
+    (!missing.is_empty()).then(|| json!({ "properties": missing }))
+}
+
+/// The dotted names of fields whose type in the `existing` mapping differs from the `wanted` one.
+fn conflicting_fields(wanted: &serde_json::Value, existing: &serde_json::Value) -> Vec<String> {
+    let field_type = |field: &serde_json::Value| field["type"].as_str().unwrap_or("object").to_string();
+    let mut conflicts = Vec::new();
+    for (name, field) in wanted["properties"].as_object().into_iter().flatten() {
+        let Some(current) = existing["properties"].get(name) else { continue };
+        if field_type(field) != field_type(current) {
+            conflicts.push(name.clone());
+        } else {
+            conflicts.extend(conflicting_fields(field, current).into_iter().map(|sub| format!("{}.{}", name, sub)));
+        }
+    }
+    conflicts
+}
+
+/// The `query` of a search for `request`, plus the `min_score` that cuts off a color search at its tolerance.
+fn matching_search(request: &SearchRequest) -> Result<serde_json::Value, AppError> {
+    let Some((target, tolerance)) = request.color_target()? else {
+        return Ok(json!({ "query": build_query(request) }));
+    };
+    // Scores fall as the distance grows, so `min_score` drops everything beyond the tolerance.
+    Ok(json!({
+        "query": {
+            "script_score": {
+                "query": build_query(request),
+                "script": {
+                    "source": PALETTE_DISTANCE_SCRIPT,
+                    "params": {
+                        "l": target[0],
+                        "a": target[1],
+                        "b": target[2],
+                        "min_share": colors::MIN_SEARCH_SHARE
+                    }
+                }
+            }
+        },
+        "min_score": 1.0 / (1.0 + tolerance)
+    }))
+}
+
+/// The text query of `request` combined with all of its filters.
+fn build_query(request: &SearchRequest) -> serde_json::Value {
+    let text_query = if request.q.is_empty() {
+        json!({ "match_all": {} })
+    } else {
+        json!({
+            "multi_match": {
+                "query": request.q,
+                "fields": [
+                    "file_path", "file_hash", "camera_make", "camera_model", "date_taken", "copies.path.text", "tags",
+                    "xmp.keywords", "xmp.description", "xmp.headline", "xmp.regions.name.text",
+                    "iptc.keywords", "iptc.title", "iptc.headline", "iptc.caption", "iptc.bylines.text", "iptc.credit",
+                    "iptc.source", "iptc.copyright", "iptc.city.text", "iptc.sublocation", "iptc.province_state",
+                    "iptc.country.text"
+                ]
+            }
+        })
+    };
+
+    let mut filters = Vec::new();
+    if request.min_size.is_some() || request.max_size.is_some() {
+        filters.push(json!({ "range": { "file_size": { "gte": request.min_size, "lte": request.max_size } } }));
+    }
+    for (name, min, max) in request.quality_ranges() {
+        filters.push(json!({ "range": { name: { "gte": min, "lte": max } } }));
+    }
+    if request.modified_after.is_some() || request.modified_before.is_some() {
+        filters.push(json!({ "range": { "modified_at": { "gte": request.modified_after, "lte": request.modified_before } } }));
+    }
+    if let Some(extension) = request.normalized_extension() {
+        filters.push(json!({ "term": { "extension": extension } }));
+    }
+    if let Some(mime_type) = &request.mime_type {
+        filters.push(json!({ "term": { "mime_type": mime_type } }));
+    }
+    if let Some(folder) = &request.folder {
+        let pattern = format!("{}[^/]*", escape_regex(&folder_prefix(folder)));
+        filters.push(json!({ "regexp": { "copies.path": pattern } }));
+    }
+    if let Some(hashes) = &request.hashes {
+        filters.push(json!({ "terms": { "file_hash": hashes } }));
+    }
+    if let Some(path_prefix) = &request.path_prefix {
+        filters.push(json!({ "prefix": { "copies.path": folder_prefix(path_prefix) } }));
+    }
+    for tag in request.tag_list() {
+        filters.push(json!({ "term": { "tags": tag } }));
+    }
+    if let Some(min_rating) = request.min_rating {
+        filters.push(json!({ "range": { "rating": { "gte": min_rating } } }));
+    }
+    if let Some(color_label) = request.color_label {
+        filters.push(json!({ "term": { "color_label": color_label.as_str() } }));
+    }
+    if let Some(favorite) = request.favorite {
+        filters.push(json!({ "term": { "favorite": favorite } }));
+    }
+    if let Some(keyword) = &request.keyword {
+        filters.push(json!({
+            "bool": {
+                "should": [
+                    { "term": { "xmp.keywords": keyword } },
+                    { "term": { "iptc.keywords": keyword } }
+                ],
+                "minimum_should_match": 1
+            }
+        }));
+    }
+    if let Some(country) = &request.place_country {
+        filters.push(json!({
+            "bool": {
+                "should": [
+                    { "term": { "place.country_code": country } },
+                    { "term": { "place.country": country } }
+                ],
+                "minimum_should_match": 1
+            }
+        }));
+    }
+    if let Some(region) = &request.place_region {
+        filters.push(json!({ "term": { "place.region": region } }));
+    }
+    if let Some(city) = &request.place_city {
+        filters.push(json!({ "term": { "place.city": city } }));
+    }
+    if let Some(group_id) = &request.group_id {
+        filters.push(json!({ "term": { "group_id": group_id } }));
+    }
+    if request.collapse_groups {
+        filters.push(json!({
+            "bool": {
+                "must_not": {
+                    "bool": { "filter": [{ "exists": { "field": "group_id" } }, { "term": { "best_pick": false } }] }
+                }
+            }
+        }));
+    }
+    let iptc_keywords = [("iptc.bylines", &request.byline), ("iptc.city", &request.city), ("iptc.country", &request.country)];
+    for (field, value) in iptc_keywords {
+        if let Some(value) = value {
+            filters.push(json!({ "term": { field: value } }));
+        }
+    }
+
+    json!({
+        "bool": {
+            "must": text_query,
+            "filter": filters
+        }
+    })
+}
+
+/// Escapes the characters Lucene regular expressions treat as operators.
+fn escape_regex(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if ".?+*|{}[]()\"\\#@&<>~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// `/photos` becomes `/photos/`, and `/` stays `/`.
+fn folder_prefix(folder: &str) -> String {
+    format!("{}/", folder.trim_end_matches('/'))
 }
 
 #[async_trait]
@@ -51,28 +676,40 @@ impl Searcher for ElasticsearchSearcher {
                 .create(elasticsearch::indices::IndicesCreateParts::Index(
                     INDEX_NAME,
                 ))
-                .body(json!({
-                    "mappings": {
-                        "properties": {
-                            "file_path": { "type": "keyword" },
-                            "file_hash": { "type": "keyword" },
-                            "width": { "type": "integer" },
-                            "height": { "type": "integer" },
-                            "camera_make": { "type": "keyword" },
-                            "camera_model": { "type": "keyword" },
-                            "date_taken": { "type": "date", "format": "yyyy:MM:dd HH:mm:ss||yyyy-MM-dd HH:mm:ss||epoch_millis" },
-                            "gps_latitude": { "type": "geo_point" },
-                            "gps_longitude": { "type": "geo_point" },
-                            "thumbnail_path": { "type": "keyword" },
-                            "duplicate_paths": { "type": "keyword" }
-                        }
-                    }
-                }))
+                .body(json!({ "mappings": index_mappings() }))
                 .send()
                 .await?;
             log::info!("Elasticsearch index '{}' created successfully.", INDEX_NAME);
         } else {
             log::debug!("Elasticsearch index '{}' already exists.", INDEX_NAME);
+            let mapping = self
+                .client
+                .indices()
+                .get_mapping(elasticsearch::indices::IndicesGetMappingParts::Index(&[INDEX_NAME]))
+                .send()
+                .await?
+                .error_for_status_code()?
+                .json::<serde_json::Value>()
+                .await?;
+            // Keyed by the concrete index, which differs from `images` once a reindex put an alias there.
+            let Some((concrete, existing)) = mapping
+                .as_object()
+                .and_then(|indices| indices.iter().next())
+                .map(|(name, index)| (name.clone(), index["mappings"].clone()))
+            else {
+                return Err(AppError::Generic(format!("Elasticsearch returned no mapping for '{}'", INDEX_NAME)));
+            };
+            let conflicts = conflicting_fields(&index_mappings(), &existing);
+            if !conflicts.is_empty() {
+                log::warn!("Fields of Elasticsearch index '{}' changed type: {}", concrete, conflicts.join(", "));
+                return self.reindex_into_new_index(&concrete).await;
+            }
+            let had_location = !existing["properties"]["location"].is_null();
+            self.add_missing_mappings(&existing).await?;
+            self.migrate_duplicate_paths().await?;
+            if !had_location {
+                self.backfill_locations().await?;
+            }
         }
 
         Ok(())
@@ -81,72 +718,39 @@ impl Searcher for ElasticsearchSearcher {
     async fn index_metadata(&self, metadata: ImageMetadata) -> Result<(), AppError> {
         log::debug!("Attempting to index metadata for file: {}", metadata.file_path);
 
-        let response = self
-            .client
-            .search(SearchParts::Index(&[INDEX_NAME]))
+        // Documents are keyed by hash, so one upsert either creates the document or merges the new
+        // copies into it, without the window a search before writing would leave for a second copy.
+        self.client
+            .update(UpdateParts::IndexId(INDEX_NAME, &metadata.file_hash))
+            .retry_on_conflict(3)
             .body(json!({
-                "query": {
-                    "term": {
-                        "file_hash": &metadata.file_hash
+                "script": {
+                    "source": "if (ctx._source.copies == null) { ctx._source.copies = [] } for (copy in params.copies) { boolean known = false; for (c in ctx._source.copies) { if (c.path == copy.path) { known = true } } if (!known) { ctx._source.copies.add(copy) } }",
+                    "lang": "painless",
+                    "params": {
+                        "copies": metadata.copies
                     }
-                }
+                },
+                "upsert": to_document(&metadata)?
             }))
             .send()
-            .await?;
-
-        let body = response.json::<serde_json::Value>().await?;
-        let hits = body["hits"]["hits"].as_array().unwrap();
-
-        if hits.is_empty() {
-            log::trace!("No existing document found for hash: {}. Indexing new document.", metadata.file_hash);
-            self.client
-                .index(IndexParts::IndexId(INDEX_NAME, &metadata.file_hash))
-                .body(metadata.clone())
-                .send()
-                .await?;
-            log::debug!("New document indexed for file: {}", metadata.file_path);
-        } else {
-            let doc_id = hits[0]["_id"].as_str().unwrap();
-            log::trace!("Duplicate image found for hash: {}. Updating existing document ID: {} with new path: {}", metadata.file_hash, doc_id, metadata.file_path);
-            self.client
-                .update(UpdateParts::IndexId(INDEX_NAME, doc_id))
-                .body(json!({
-                    "script": {
-                        "source": "if (ctx._source.duplicate_paths.indexOf(params.path) == -1) { ctx._source.duplicate_paths.add(params.path) }",
-                        "lang": "painless",
-                        "params": {
-                            "path": metadata.file_path
-                        }
-                    }
-                }))
-                .send()
-                .await?;
-            log::debug!("Existing document updated for file: {}", metadata.file_path);
-        }
+            .await?
+            .error_for_status_code()?;
+        log::debug!("Indexed file: {}", metadata.file_path);
 
         Ok(())
     }
 
-    async fn search_images(&self, query: String) -> Result<Vec<ImageMetadata>, AppError> {
-        log::debug!("Searching Elasticsearch for images with query: {}", query);
-        let search_query = if query.is_empty() {
-            json!({
-                "query": {
-                    "match_all": {}
-                },
-                "size": 100 // Limit results for UI
-            })
-        } else {
-            json!({
-                "query": {
-                    "multi_match": {
-                        "query": query,
-                        "fields": ["file_path", "file_hash", "camera_make", "camera_model", "date_taken", "duplicate_paths"]
-                    }
-                },
-                "size": 100 // Limit results for UI
-            })
-        };
+    async fn search_images(&self, request: &SearchRequest) -> Result<Vec<ImageMetadata>, AppError> {
+        log::debug!("Searching Elasticsearch for images with request: {:?}", request);
+        let mut search_query = matching_search(request)?;
+        search_query["from"] = json!(request.offset);
+        search_query["size"] = json!(request.limit);
+        // A color search stays ranked by distance.
+        if let (None, Some(field_name)) = (request.color_target()?, request.sort.field_name()) {
+            let order = if request.order == SortOrder::Asc { "asc" } else { "desc" };
+            search_query["sort"] = json!([{ field_name: { "order": order, "missing": "_last" } }]);
+        }
 
         let response = self.client
             .search(SearchParts::Index(&[INDEX_NAME]))
@@ -161,12 +765,19 @@ impl Searcher for ElasticsearchSearcher {
         if let Some(hits) = body["hits"]["hits"].as_array() {
             for hit in hits {
                 if let Some(source) = hit["_source"].as_object() {
-                    let metadata: ImageMetadata = serde_json::from_value(serde_json::Value::Object(source.clone()))?;
+                    let metadata = from_source(serde_json::Value::Object(source.clone()))?;
                     images.push(metadata);
                 }
             }
         }
-        log::debug!("Found {} images in Elasticsearch for query: {}", images.len(), query);
+        log::debug!("Found {} images in Elasticsearch for query: {}", images.len(), request.q);
+        Ok(images)
+    }
+
+    async fn search_all(&self, request: &SearchRequest) -> Result<Vec<ImageMetadata>, AppError> {
+        // Scrolls, as `from` cannot page past the index's `max_result_window`.
+        let mut images = Vec::new();
+        self.for_each_matching(matching_search(request)?, |metadata| images.push(metadata)).await?;
         Ok(images)
     }
 
@@ -195,7 +806,7 @@ impl Searcher for ElasticsearchSearcher {
     async fn update_document(&self, metadata: ImageMetadata) -> Result<(), AppError> {
         self.client
             .index(IndexParts::IndexId(INDEX_NAME, &metadata.file_hash))
-            .body(metadata.clone())
+            .body(to_document(&metadata)?)
             .send()
             .await?
             .error_for_status_code()?;
@@ -260,4 +871,514 @@ impl Searcher for ElasticsearchSearcher {
 
         Ok(hashes)
     }
+
+    async fn get_by_hash(&self, hash: &str) -> Result<Option<ImageMetadata>, AppError> {
+        log::debug!("Looking up image by hash: {}", hash);
+        // Documents are indexed with their hash as the document ID.
+        let response = self
+            .client
+            .get(GetParts::IndexId(INDEX_NAME, hash))
+            .send()
+            .await?;
+
+        if response.status_code().as_u16() == 404 {
+            return Ok(None);
+        }
+        let body = response.error_for_status_code()?.json::<serde_json::Value>().await?;
+        match body.get("_source") {
+            Some(source) => Ok(Some(from_source(source.clone())?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_by_path(&self, path: &str) -> Result<Option<ImageMetadata>, AppError> {
+        log::debug!("Looking up image by path: {}", path);
+        let response = self
+            .client
+            .search(SearchParts::Index(&[INDEX_NAME]))
+            .body(json!({
+                "query": {
+                    "bool": {
+                        "should": [
+                            { "term": { "file_path": path } },
+                            { "term": { "copies.path": path } }
+                        ],
+                        "minimum_should_match": 1
+                    }
+                },
+                "size": 1
+            }))
+            .send()
+            .await?;
+
+        let body = response.json::<serde_json::Value>().await?;
+        match body["hits"]["hits"].as_array().and_then(|hits| hits.first()) {
+            Some(hit) => Ok(Some(from_source(hit["_source"].clone())?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_all_images(&self) -> Result<Vec<ImageMetadata>, AppError> {
+        let mut images = Vec::new();
+        self.for_each_image(|metadata| images.push(metadata)).await?;
+        Ok(images)
+    }
+
+    async fn get_burst_fields(&self) -> Result<Vec<BurstFields>, AppError> {
+        let mut images = Vec::new();
+        let search = json!({ "query": { "match_all": {} }, "_source": BurstFields::SOURCE_FIELDS });
+        self.for_each_source(search, |source| {
+            images.push(serde_json::from_value(source)?);
+            Ok(())
+        })
+        .await?;
+        Ok(images)
+    }
+
+    async fn get_duplicate_groups(&self) -> Result<Vec<ImageMetadata>, AppError> {
+        let mut groups = Vec::new();
+        let mut scroll_id: Option<String> = None;
+
+        loop {
+            let response = if let Some(sid) = &scroll_id {
+                self.client
+                    .scroll(elasticsearch::ScrollParts::ScrollId(sid))
+                    .scroll("1m")
+                    .send()
+                    .await?
+            } else {
+                self.client
+                    .search(SearchParts::Index(&[INDEX_NAME]))
+                    .scroll("1m")
+                    .body(json!({
+                        "query": {
+                            "script": {
+                                "script": "doc['copies.path'].size() > 1"
+                            }
+                        },
+                        "size": 1000
+                    }))
+                    .send()
+                    .await?
+            };
+
+            let body = response.json::<serde_json::Value>().await?;
+            let hits = match body["hits"]["hits"].as_array() {
+                Some(hits) if !hits.is_empty() => hits,
+                _ => break,
+            };
+
+            for hit in hits {
+                let metadata = from_source(hit["_source"].clone())?;
+                if metadata.paths().count() > 1 {
+                    groups.push(metadata);
+                }
+            }
+
+            scroll_id = body["_scroll_id"].as_str().map(|s| s.to_string());
+            if scroll_id.is_none() {
+                break;
+            }
+        }
+
+        if let Some(sid) = scroll_id.as_deref() {
+            self.client
+                .clear_scroll(elasticsearch::ClearScrollParts::ScrollId(&[sid]))
+                .send()
+                .await?;
+        }
+
+        log::debug!("Found {} duplicate groups in Elasticsearch", groups.len());
+        Ok(groups)
+    }
+
+    async fn compute_stats(&self) -> Result<LibraryStats, AppError> {
+        let sum = |result: &str| json!({ "sum": { "script": { "source": format!("{}{}", STATS_COPIES_SCRIPT, result) } } });
+        let response = self
+            .client
+            .search(SearchParts::Index(&[INDEX_NAME]))
+            .body(json!({
+                "size": 0,
+                "track_total_hits": true,
+                "aggs": {
+                    "total_files": sum("return copies;"),
+                    "total_bytes": sum("return size * copies;"),
+                    "duplicate_groups": sum("return copies > 1 ? 1 : 0;"),
+                    "bytes_reclaimable": sum(
+                        "return copies > 1 ? size * Math.max(copies - 1 - doc['linked_paths'].size(), 0) : 0;"
+                    ),
+                    "with_gps": { "filter": { "exists": { "field": "location" } } },
+                    "without_exif": { "filter": { "bool": { "must_not": { "term": { "has_exif": true } } } } },
+                    "extensions": { "terms": { "field": "extension", "size": 1000, "missing": "" } },
+                    "makes": {
+                        "terms": { "field": "camera_make", "size": 1000, "missing": "" },
+                        "aggs": { "models": { "terms": { "field": "camera_model", "size": 1000, "missing": "" } } }
+                    },
+                    "years": {
+                        "date_histogram": {
+                            "field": "date_taken",
+                            "calendar_interval": "year",
+                            "format": "yyyy",
+                            "min_doc_count": 1
+                        }
+                    }
+                }
+            }))
+            .send()
+            .await?;
+
+        let body = response.error_for_status_code()?.json::<serde_json::Value>().await?;
+        let aggregations = &body["aggregations"];
+        let value = |name: &str| aggregations[name]["value"].as_f64().unwrap_or(0.0) as u64;
+        let buckets = |value: &serde_json::Value| value["buckets"].as_array().cloned().unwrap_or_default();
+        let non_empty = |key: &serde_json::Value| key.as_str().filter(|k| !k.is_empty()).map(|k| k.to_string());
+        let mut stats = LibraryStats {
+            unique_images: body["hits"]["total"]["value"].as_u64().unwrap_or(0),
+            total_files: value("total_files"),
+            total_bytes: value("total_bytes"),
+            duplicate_groups: value("duplicate_groups"),
+            bytes_reclaimable: value("bytes_reclaimable"),
+            with_gps: aggregations["with_gps"]["doc_count"].as_u64().unwrap_or(0),
+            without_exif: aggregations["without_exif"]["doc_count"].as_u64().unwrap_or(0),
+            ..LibraryStats::default()
+        };
+        for bucket in buckets(&aggregations["extensions"]) {
+            let extension = non_empty(&bucket["key"]).unwrap_or_else(|| stats::UNKNOWN.to_string());
+            *stats.by_extension.entry(extension).or_default() += bucket["doc_count"].as_u64().unwrap_or(0);
+        }
+        for make in buckets(&aggregations["makes"]) {
+            for model in buckets(&make["models"]) {
+                let camera = stats::camera(non_empty(&make["key"]).as_deref(), non_empty(&model["key"]).as_deref());
+                *stats.by_camera.entry(camera).or_default() += model["doc_count"].as_u64().unwrap_or(0);
+            }
+        }
+        let mut dated = 0;
+        for bucket in buckets(&aggregations["years"]) {
+            if let Some(year) = bucket["key_as_string"].as_str() {
+                let count = bucket["doc_count"].as_u64().unwrap_or(0);
+                stats.by_year.insert(year.to_string(), count);
+                dated += count;
+            }
+        }
+        if stats.unique_images > dated {
+            stats.by_year.insert(stats::UNKNOWN.to_string(), stats.unique_images - dated);
+        }
+        log::debug!("Computed stats over {} images in Elasticsearch", stats.unique_images);
+        Ok(stats)
+    }
+
+    async fn compute_facets(&self, request: &SearchRequest) -> Result<Facets, AppError> {
+        let response = self
+            .client
+            .search(SearchParts::Index(&[INDEX_NAME]))
+            .body(json!({
+                "query": build_query(request),
+                "size": 0,
+                "track_total_hits": true,
+                "aggs": {
+                    "tags": { "terms": { "field": "tags", "size": 1000 } },
+                    "ratings": { "terms": { "field": "rating", "size": 10, "missing": 0 } },
+                    "color_labels": { "terms": { "field": "color_label", "size": 10 } },
+                    "favorites": { "filter": { "term": { "favorite": true } } },
+                    // XMP and IPTC keywords counted once per image.
+                    "keywords": {
+                        "terms": {
+                            "script": {
+                                "source": "def k = new HashSet(); for (f in ['xmp.keywords', 'iptc.keywords']) { if (doc.containsKey(f)) { k.addAll(doc[f]); } } return new ArrayList(k);"
+                            },
+                            "size": 1000
+                        }
+                    },
+                    "bylines": { "terms": { "field": "iptc.bylines", "size": 1000 } },
+                    "cities": { "terms": { "field": "iptc.city", "size": 1000 } },
+                    "countries": { "terms": { "field": "iptc.country", "size": 1000 } }
+                }
+            }))
+            .send()
+            .await?;
+
+        let body = response.error_for_status_code()?.json::<serde_json::Value>().await?;
+        let buckets = |name: &str| body["aggregations"][name]["buckets"].as_array().cloned().unwrap_or_default();
+        let mut facets = Facets {
+            total: body["hits"]["total"]["value"].as_u64().unwrap_or(0),
+            favorites: body["aggregations"]["favorites"]["doc_count"].as_u64().unwrap_or(0),
+            ..Facets::default()
+        };
+        for bucket in buckets("tags") {
+            if let Some(tag) = bucket["key"].as_str() {
+                facets.tags.insert(tag.to_string(), bucket["doc_count"].as_u64().unwrap_or(0));
+            }
+        }
+        for bucket in buckets("ratings") {
+            if let Some(rating) = bucket["key"].as_u64() {
+                facets.ratings.insert(rating as u8, bucket["doc_count"].as_u64().unwrap_or(0));
+            }
+        }
+        for bucket in buckets("color_labels") {
+            if let Some(color_label) = bucket["key"].as_str() {
+                facets.color_labels.insert(color_label.to_string(), bucket["doc_count"].as_u64().unwrap_or(0));
+            }
+        }
+        let keyword_facets = [
+            ("keywords", &mut facets.keywords),
+            ("bylines", &mut facets.bylines),
+            ("cities", &mut facets.cities),
+            ("countries", &mut facets.countries),
+        ];
+        for (name, counts) in keyword_facets {
+            for bucket in buckets(name) {
+                if let Some(key) = bucket["key"].as_str() {
+                    counts.insert(key.to_string(), bucket["doc_count"].as_u64().unwrap_or(0));
+                }
+            }
+        }
+        Ok(facets)
+    }
+
+    async fn compute_places(&self, request: &SearchRequest) -> Result<PlaceCounts, AppError> {
+        let response = self
+            .client
+            .search(SearchParts::Index(&[INDEX_NAME]))
+            .body(json!({
+                "query": build_query(request),
+                "size": 0,
+                "aggs": {
+                    "countries": {
+                        "terms": { "field": "place.country_code", "size": 300 },
+                        "aggs": {
+                            "names": { "terms": { "field": "place.country", "size": 1 } },
+                            "regions": {
+                                "terms": { "field": "place.region", "size": 1000, "missing": "" },
+                                "aggs": {
+                                    "cities": {
+                                        "terms": { "field": "place.city", "size": 1000 },
+                                        "aggs": {
+                                            "sample": { "top_hits": { "size": 1, "_source": ["file_hash"] } }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }))
+            .send()
+            .await?;
+
+        let body = response.error_for_status_code()?.json::<serde_json::Value>().await?;
+        let buckets = |value: &serde_json::Value| value["buckets"].as_array().cloned().unwrap_or_default();
+        let mut places = PlaceCounts::default();
+        for country in buckets(&body["aggregations"]["countries"]) {
+            let country_code = country["key"].as_str().unwrap_or_default().to_string();
+            let name = buckets(&country["names"])
+                .first()
+                .and_then(|b| b["key"].as_str().map(|s| s.to_string()))
+                .unwrap_or_else(|| country_code.clone());
+            for region in buckets(&country["regions"]) {
+                let region_name = region["key"].as_str().filter(|r| !r.is_empty()).map(|r| r.to_string());
+                for city in buckets(&region["cities"]) {
+                    let place = Place {
+                        country_code: country_code.clone(),
+                        country: name.clone(),
+                        region: region_name.clone(),
+                        city: city["key"].as_str().unwrap_or_default().to_string(),
+                    };
+                    let sample = city["sample"]["hits"]["hits"][0]["_source"]["file_hash"].as_str();
+                    places.add_count(&place, city["doc_count"].as_u64().unwrap_or(0), sample);
+                }
+            }
+        }
+        Ok(places)
+    }
+
+    async fn compute_geo_clusters(
+        &self,
+        request: &SearchRequest,
+        bbox: BoundingBox,
+        precision: usize,
+    ) -> Result<GeoClusters, AppError> {
+        let response = self
+            .client
+            .search(SearchParts::Index(&[INDEX_NAME]))
+            .body(json!({
+                "query": {
+                    "bool": {
+                        "must": build_query(request),
+                        "filter": {
+                            "geo_bounding_box": {
+                                "location": {
+                                    "top_left": { "lat": bbox.north, "lon": bbox.west },
+                                    "bottom_right": { "lat": bbox.south, "lon": bbox.east }
+                                }
+                            }
+                        }
+                    }
+                },
+                "size": 0,
+                "aggs": {
+                    "cells": {
+                        "geohash_grid": { "field": "location", "precision": precision, "size": 10000 },
+                        "aggs": {
+                            "centroid": { "geo_centroid": { "field": "location" } },
+                            "sample": { "top_hits": { "size": 1, "_source": ["file_hash"] } }
+                        }
+                    }
+                }
+            }))
+            .send()
+            .await?;
+
+        let body = response.error_for_status_code()?.json::<serde_json::Value>().await?;
+        let clusters: Vec<GeoCluster> = body["aggregations"]["cells"]["buckets"]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+            .into_iter()
+            .map(|bucket| GeoCluster {
+                geohash: bucket["key"].as_str().unwrap_or_default().to_string(),
+                count: bucket["doc_count"].as_u64().unwrap_or(0),
+                latitude: bucket["centroid"]["location"]["lat"].as_f64().unwrap_or_default(),
+                longitude: bucket["centroid"]["location"]["lon"].as_f64().unwrap_or_default(),
+                sample_hash: bucket["sample"]["hits"]["hits"][0]["_source"]["file_hash"]
+                    .as_str()
+                    .map(|s| s.to_string()),
+            })
+            .collect();
+        Ok(GeoClusters {
+            precision,
+            total: clusters.iter().map(|c| c.count).sum(),
+            clusters,
+        })
+    }
+
+    async fn compute_folders(&self) -> Result<FolderCounts, AppError> {
+        // Pages through the parent folders of all copies instead of fetching every document.
+        let mut counts = FolderCounts::default();
+        let mut after: Option<serde_json::Value> = None;
+        loop {
+            let mut composite = json!({
+                "size": 1000,
+                "sources": [
+                    { "folder": { "terms": { "script": { "source": PARENT_FOLDERS_SCRIPT, "lang": "painless" } } } }
+                ]
+            });
+            if let Some(after) = after.take() {
+                composite["after"] = after;
+            }
+            let body = self
+                .client
+                .search(SearchParts::Index(&[INDEX_NAME]))
+                .body(json!({
+                    "size": 0,
+                    "aggs": {
+                        "folders": {
+                            "composite": composite,
+                            "aggs": {
+                                "sample": { "top_hits": { "size": 1, "_source": ["file_hash"] } }
+                            }
+                        }
+                    }
+                }))
+                .send()
+                .await?
+                .error_for_status_code()?
+                .json::<serde_json::Value>()
+                .await?;
+            let folders = &body["aggregations"]["folders"];
+            for bucket in folders["buckets"].as_array().into_iter().flatten() {
+                counts.add_folder(
+                    bucket["key"]["folder"].as_str().unwrap_or_default(),
+                    bucket["doc_count"].as_u64().unwrap_or(0),
+                    bucket["sample"]["hits"]["hits"][0]["_source"]["file_hash"].as_str(),
+                );
+            }
+            match folders.get("after_key") {
+                Some(key) if !key.is_null() => after = Some(key.clone()),
+                _ => return Ok(counts),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What Elasticsearch reports for an index created by the first release, before copies existed.
+    fn baseline_mapping() -> serde_json::Value {
+        json!({
+            "properties": {
+                "file_path": { "type": "keyword" },
+                "file_hash": { "type": "keyword" },
+                "width": { "type": "integer" },
+                "height": { "type": "integer" },
+                "camera_make": { "type": "keyword" },
+                "camera_model": { "type": "keyword" },
+                "date_taken": { "type": "date", "format": "yyyy:MM:dd HH:mm:ss||yyyy-MM-dd HH:mm:ss||epoch_millis" },
+                "gps_latitude": { "type": "geo_point" },
+                "gps_longitude": { "type": "geo_point" },
+                "thumbnail_path": { "type": "keyword" },
+                "duplicate_paths": { "type": "keyword" }
+            }
+        })
+    }
+
+    #[test]
+    fn baseline_index_is_reindexed_for_the_gps_type_change() {
+        assert_eq!(conflicting_fields(&index_mappings(), &baseline_mapping()), ["gps_latitude", "gps_longitude"]);
+    }
+
+    #[test]
+    fn missing_mappings_leave_existing_fields_alone() {
+        let mut existing = baseline_mapping();
+        existing["properties"]["gps_latitude"] = json!({ "type": "double" });
+        existing["properties"]["gps_longitude"] = json!({ "type": "double" });
+        assert!(conflicting_fields(&index_mappings(), &existing).is_empty());
+
+        let missing = missing_mappings(&index_mappings(), &existing).unwrap();
+        let fields = missing["properties"].as_object().unwrap();
+        for field in ["file_path", "file_hash", "date_taken", "gps_latitude", "gps_longitude", "duplicate_paths"] {
+            assert!(!fields.contains_key(field), "{} should be left alone", field);
+        }
+        assert_eq!(fields["copies"]["type"], "nested");
+        assert!(fields.contains_key("file_size"));
+    }
+
+    #[test]
+    fn older_index_gains_the_location_field() {
+        let mut existing = index_mappings();
+        existing["properties"].as_object_mut().unwrap().remove("location");
+        assert!(conflicting_fields(&index_mappings(), &existing).is_empty());
+        assert_eq!(
+            missing_mappings(&index_mappings(), &existing),
+            Some(json!({ "properties": { "location": { "type": "geo_point" } } }))
+        );
+    }
+
+    #[test]
+    fn nested_objects_only_gain_their_missing_fields() {
+        let mut existing = index_mappings();
+        existing["properties"]["copies"]["properties"].as_object_mut().unwrap().remove("first_seen");
+        let missing = missing_mappings(&index_mappings(), &existing).unwrap();
+        assert_eq!(
+            missing,
+            json!({
+                "properties": {
+                    "copies": {
+                        "type": "nested",
+                        "include_in_parent": true,
+                        "properties": {
+                            "first_seen": { "type": "date", "format": "epoch_second" }
+                        }
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn current_index_needs_no_changes() {
+        assert!(missing_mappings(&index_mappings(), &index_mappings()).is_none());
+        assert!(conflicting_fields(&index_mappings(), &index_mappings()).is_empty());
+    }
 }
@@ -1,12 +1,26 @@
 use crate::config::AppConfig;
+use crate::annotations::{Annotations, ColorLabel, Facets};
+use crate::bursts::BurstFields;
+use crate::colors;
 use crate::error::AppError;
-use crate::metadata::ImageMetadata;
-use crate::search::Searcher;
+use crate::folders::{self, FolderCounts};
+use crate::geo::{BoundingBox, ClusterCounts, GeoClusters};
+use crate::metadata::{FileCopy, ImageMetadata};
+use crate::places::PlaceCounts;
+use crate::quality::QualityMetrics;
+use crate::search::{SearchRequest, Searcher, SortOrder};
+use crate::stats::LibraryStats;
 use async_trait::async_trait;
-use tantivy::collector::TopDocs;
-use tantivy::query::QueryParser;
-use tantivy::schema::{Document, Schema, Term, STORED, TEXT, STRING};
-use tantivy::{Index, IndexWriter};
+use std::ops::Bound;
+use tantivy::collector::{DocSetCollector, TopDocs};
+use tantivy::query::{
+    AllQuery, BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery, TermSetQuery,
+};
+use tantivy::schema::{
+    Cardinality, Document, FieldType, IndexRecordOption, NumericOptions, Schema, Term, Value, FAST, INDEXED,
+    STORED, STRING, TEXT,
+};
+use tantivy::{DocAddress, DocId, DocSet, Index, IndexWriter, SegmentReader, TERMINATED};
 
 pub struct TantivySearcher {
     index: Index,
@@ -22,23 +36,610 @@ impl TantivySearcher {
 
         schema_builder.add_text_field("file_path", TEXT | STORED);
         schema_builder.add_text_field("file_hash", STRING | STORED);
-        schema_builder.add_u64_field("width", STORED);
-        schema_builder.add_u64_field("height", STORED);
+        schema_builder.add_u64_field("width", STORED | FAST);
+        schema_builder.add_u64_field("height", STORED | FAST);
         schema_builder.add_text_field("camera_make", TEXT | STORED);
         schema_builder.add_text_field("camera_model", TEXT | STORED);
         schema_builder.add_text_field("date_taken", TEXT | STORED);
+        schema_builder.add_f64_field("gps_latitude", STORED | INDEXED | FAST);
+        schema_builder.add_f64_field("gps_longitude", STORED | INDEXED | FAST);
+        schema_builder.add_u64_field("has_exif", STORED);
         schema_builder.add_text_field("thumbnail_path", TEXT | STORED);
-        schema_builder.add_text_field("duplicate_paths", TEXT | STORED);
+        // One stored record per physical copy.
+        schema_builder.add_json_field("copies", STORED);
+        // Every known path of the image, tokenized one value per copy for search...
+        schema_builder.add_text_field("copy_paths", TEXT);
+        // ...and untokenized for exact lookups.
+        schema_builder.add_text_field("paths", STRING);
+        // The folder holding each copy, and every folder above it.
+        schema_builder.add_text_field("parent_folders", STRING);
+        schema_builder.add_text_field("folders", STRING);
+        schema_builder.add_u64_field("duplicate_count", INDEXED);
+        schema_builder.add_text_field("linked_paths", STORED);
+        schema_builder.add_u64_field("file_size", STORED | INDEXED | FAST);
+        schema_builder.add_i64_field("modified_at", STORED | INDEXED | FAST);
+        schema_builder.add_i64_field("created_at", STORED | INDEXED | FAST);
+        schema_builder.add_text_field("extension", STRING | STORED);
+        schema_builder.add_text_field("mime_type", STRING | STORED);
+        schema_builder.add_text_field("tags", STRING | STORED);
+        schema_builder.add_u64_field("rating", STORED | INDEXED | FAST);
+        schema_builder.add_text_field("color_label", STRING | STORED);
+        schema_builder.add_u64_field("favorite", STORED | INDEXED);
+        schema_builder.add_json_field("xmp", STORED);
+        schema_builder.add_json_field("iptc", STORED);
+        // XMP and IPTC keywords as exact terms, and their captions, credits and place names as searchable text.
+        schema_builder.add_text_field("keywords", STRING);
+        schema_builder.add_text_field("xmp_text", TEXT);
+        schema_builder.add_text_field("iptc_text", TEXT);
+        schema_builder.add_text_field("bylines", STRING);
+        schema_builder.add_text_field("city", STRING);
+        schema_builder.add_text_field("country", STRING);
+        schema_builder.add_json_field("place", STORED);
+        // The reverse geocoded country is indexed by both ISO code and name.
+        schema_builder.add_text_field("place_country", STRING);
+        schema_builder.add_text_field("place_region", STRING);
+        schema_builder.add_text_field("place_city", STRING);
+        for name in ["sharpness", "noise", "mean_luminance", "clipped_highlights", "clipped_shadows"] {
+            schema_builder.add_f64_field(name, STORED | INDEXED | FAST);
+        }
+        schema_builder.add_text_field("perceptual_hash", STRING | STORED);
+        // Little-endian f32s, read back to rebuild the similarity index.
+        schema_builder.add_bytes_field("color_histogram", STORED);
+        schema_builder.add_text_field("group_id", STRING | STORED);
+        // Only set on grouped images, so collapsing can exclude the `0` term.
+        schema_builder.add_u64_field("best_pick", STORED | INDEXED);
+        schema_builder.add_json_field("colors", STORED);
+        schema_builder.add_json_field("analyzer_versions", STORED);
+        // Each palette color packed by `colors::pack`, read back when ranking by color.
+        schema_builder.add_u64_field("palette_lab", NumericOptions::default().set_fast(Cardinality::MultiValues));
 
         let schema = schema_builder.build();
-        let index = Index::open_in_dir(index_path).unwrap_or_else(|_| {
-            log::info!("Tantivy index not found at {}. Creating new index.", index_path);
-            std::fs::create_dir_all(index_path).unwrap();
-            Index::create_in_dir(index_path, schema.clone()).unwrap()
-        });
+        let index = match Index::open_in_dir(index_path) {
+            Ok(index) if index.schema() == schema => index,
+            Ok(old_index) if !config.recreate_index => Self::migrate(index_path, old_index, &schema)?,
+            Ok(_) => {
+                log::warn!(
+                    "Recreating Tantivy index at {} with the current schema; rerun indexing to repopulate.",
+                    index_path
+                );
+                std::fs::remove_dir_all(index_path)?;
+                std::fs::create_dir_all(index_path)?;
+                Index::create_in_dir(index_path, schema.clone())?
+            }
+            Err(_) => {
+                log::info!("Tantivy index not found at {}. Creating new index.", index_path);
+                std::fs::create_dir_all(index_path)?;
+                Index::create_in_dir(index_path, schema.clone())?
+            }
+        };
         log::debug!("Tantivy searcher initialized successfully.");
         Ok(Self { index, schema })
     }
+
+    /// Rebuilds an index created with an older schema from its doc store, so nothing that is
+    /// only kept in the index (copies, annotations, analyzer results) has to be indexed again.
+    /// The new index is built next to the old one and only replaces it once it is complete.
+    fn migrate(index_path: &str, old_index: Index, schema: &Schema) -> Result<Index, AppError> {
+        log::warn!(
+            "Tantivy index at {} was created with an older schema; migrating its documents to the current one.",
+            index_path
+        );
+        let index_path = index_path.trim_end_matches('/');
+        let migrated_path = format!("{}.migrating", index_path);
+        let old_path = format!("{}.old", index_path);
+        if std::path::Path::new(&migrated_path).exists() {
+            std::fs::remove_dir_all(&migrated_path)?;
+        }
+        std::fs::create_dir_all(&migrated_path)?;
+
+        let migrated_index = Index::create_in_dir(&migrated_path, schema.clone())?;
+        let mut index_writer: IndexWriter = migrated_index.writer(50_000_000)?;
+        let old_schema = old_index.schema();
+        let mut count = 0;
+        {
+            let searcher = old_index.reader()?.searcher();
+            for segment_reader in searcher.segment_readers() {
+                let store_reader = segment_reader.get_store_reader(1)?;
+                for doc in store_reader.iter(segment_reader.alive_bitset()) {
+                    let stored = Self::convert_document(&old_schema, schema, &doc?);
+                    index_writer.add_document(Self::to_document(schema, &Self::to_metadata(schema, &stored)))?;
+                    count += 1;
+                }
+            }
+        }
+        index_writer.commit()?;
+        index_writer.wait_merging_threads()?;
+        drop(migrated_index);
+        drop(old_index);
+
+        std::fs::rename(index_path, &old_path)?;
+        std::fs::rename(&migrated_path, index_path)?;
+        std::fs::remove_dir_all(&old_path)?;
+        log::info!("Migrated {} documents to the current Tantivy schema.", count);
+        Ok(Index::open_in_dir(index_path)?)
+    }
+
+    /// Carries the stored values of `doc` over to the fields of `schema` with the same name and type.
+    /// Fields that were dropped or changed type are left out and read back as missing.
+    fn convert_document(old_schema: &Schema, schema: &Schema, doc: &Document) -> Document {
+        let mut converted = Document::default();
+        for field_value in doc.field_values() {
+            let Some(field) = schema.get_field(old_schema.get_field_name(field_value.field())) else {
+                continue;
+            };
+            let fits = matches!(
+                (schema.get_field_entry(field).field_type(), field_value.value()),
+                (FieldType::Str(_), Value::Str(_))
+                    | (FieldType::U64(_), Value::U64(_))
+                    | (FieldType::I64(_), Value::I64(_))
+                    | (FieldType::F64(_), Value::F64(_))
+                    | (FieldType::Bool(_), Value::Bool(_))
+                    | (FieldType::Date(_), Value::Date(_))
+                    | (FieldType::Bytes(_), Value::Bytes(_))
+                    | (FieldType::JsonObject(_), Value::JsonObject(_))
+            );
+            if fits {
+                converted.add_field_value(field, field_value.value().clone());
+            }
+        }
+        converted
+    }
+
+    fn to_document(schema: &Schema, metadata: &ImageMetadata) -> Document {
+        let field = |name: &str| schema.get_field(name).unwrap();
+
+        let mut doc = Document::default();
+        doc.add_text(field("file_path"), &metadata.file_path);
+        doc.add_text(field("file_hash"), &metadata.file_hash);
+        doc.add_u64(field("width"), metadata.width as u64);
+        doc.add_u64(field("height"), metadata.height as u64);
+        if let Some(make) = &metadata.camera_make {
+            doc.add_text(field("camera_make"), make);
+        }
+        if let Some(model) = &metadata.camera_model {
+            doc.add_text(field("camera_model"), model);
+        }
+        if let Some(date) = &metadata.date_taken {
+            doc.add_text(field("date_taken"), date);
+        }
+        if let Some(latitude) = metadata.gps_latitude {
+            doc.add_f64(field("gps_latitude"), latitude);
+        }
+        if let Some(longitude) = metadata.gps_longitude {
+            doc.add_f64(field("gps_longitude"), longitude);
+        }
+        doc.add_u64(field("has_exif"), metadata.has_exif as u64);
+        doc.add_u64(field("file_size"), metadata.file_size);
+        if let Some(modified_at) = metadata.modified_at {
+            doc.add_i64(field("modified_at"), modified_at);
+        }
+        if let Some(created_at) = metadata.created_at {
+            doc.add_i64(field("created_at"), created_at);
+        }
+        doc.add_text(field("extension"), &metadata.extension);
+        doc.add_text(field("mime_type"), &metadata.mime_type);
+        for tag in &metadata.annotations.tags {
+            doc.add_text(field("tags"), tag);
+        }
+        doc.add_u64(field("rating"), metadata.annotations.rating as u64);
+        if let Some(color_label) = metadata.annotations.color_label {
+            doc.add_text(field("color_label"), color_label.as_str());
+        }
+        doc.add_u64(field("favorite"), metadata.annotations.favorite as u64);
+        doc.add_text(field("thumbnail_path"), &metadata.thumbnail_path);
+        if !metadata.xmp.is_empty() {
+            if let Ok(serde_json::Value::Object(xmp)) = serde_json::to_value(&metadata.xmp) {
+                doc.add_json_object(field("xmp"), xmp);
+            }
+        }
+        if !metadata.iptc.is_empty() {
+            if let Ok(serde_json::Value::Object(iptc)) = serde_json::to_value(&metadata.iptc) {
+                doc.add_json_object(field("iptc"), iptc);
+            }
+        }
+        for keyword in metadata.keywords() {
+            doc.add_text(field("keywords"), keyword);
+        }
+        for text in metadata.iptc.text_values() {
+            doc.add_text(field("iptc_text"), text);
+        }
+        for byline in &metadata.iptc.bylines {
+            doc.add_text(field("bylines"), byline);
+        }
+        if let Some(city) = &metadata.iptc.city {
+            doc.add_text(field("city"), city);
+        }
+        if let Some(country) = &metadata.iptc.country {
+            doc.add_text(field("country"), country);
+        }
+        if let Some(place) = &metadata.place {
+            if let Ok(serde_json::Value::Object(record)) = serde_json::to_value(place) {
+                doc.add_json_object(field("place"), record);
+            }
+            doc.add_text(field("place_country"), &place.country_code);
+            doc.add_text(field("place_country"), &place.country);
+            if let Some(region) = &place.region {
+                doc.add_text(field("place_region"), region);
+            }
+            doc.add_text(field("place_city"), &place.city);
+        }
+        if let Some(hash) = &metadata.perceptual_hash {
+            doc.add_text(field("perceptual_hash"), hash);
+        }
+        if let Some(histogram) = &metadata.color_histogram {
+            let bytes: Vec<u8> = histogram.iter().flat_map(|v| v.to_le_bytes()).collect();
+            doc.add_bytes(field("color_histogram"), bytes);
+        }
+        if let Some(group_id) = &metadata.group_id {
+            doc.add_text(field("group_id"), group_id);
+            doc.add_u64(field("best_pick"), metadata.best_pick as u64);
+        }
+        let quality = &metadata.quality;
+        let scores = [
+            ("sharpness", quality.sharpness),
+            ("noise", quality.noise),
+            ("mean_luminance", quality.mean_luminance),
+            ("clipped_highlights", quality.clipped_highlights),
+            ("clipped_shadows", quality.clipped_shadows),
+        ];
+        for (name, score) in scores {
+            if let Some(score) = score {
+                doc.add_f64(field(name), score);
+            }
+        }
+        if let Some(colors) = &metadata.colors {
+            if let Ok(serde_json::Value::Object(record)) = serde_json::to_value(colors) {
+                doc.add_json_object(field("colors"), record);
+            }
+            for (lab, share) in colors.palette_lab() {
+                doc.add_u64(field("palette_lab"), colors::pack(lab, share));
+            }
+        }
+        if let Ok(serde_json::Value::Object(record)) = serde_json::to_value(&metadata.analyzer_versions) {
+            doc.add_json_object(field("analyzer_versions"), record);
+        }
+        let xmp_text = [&metadata.xmp.description, &metadata.xmp.headline]
+            .into_iter()
+            .flatten()
+            .chain(metadata.xmp.regions.iter().filter_map(|r| r.name.as_ref()));
+        for text in xmp_text {
+            doc.add_text(field("xmp_text"), text);
+        }
+        for copy in &metadata.copies {
+            if let Ok(serde_json::Value::Object(record)) = serde_json::to_value(copy) {
+                doc.add_json_object(field("copies"), record);
+            }
+        }
+        doc.add_u64(field("duplicate_count"), metadata.paths().count() as u64 - 1);
+        for path in &metadata.linked_paths {
+            doc.add_text(field("linked_paths"), path);
+        }
+        let mut ancestors = std::collections::BTreeSet::new();
+        for path in metadata.paths() {
+            doc.add_text(field("copy_paths"), path);
+            doc.add_text(field("paths"), path);
+            ancestors.extend(folders::ancestor_folders(path));
+        }
+        let parents: std::collections::BTreeSet<String> =
+            metadata.paths().filter_map(folders::parent_folder).collect();
+        for folder in parents {
+            doc.add_text(field("parent_folders"), folder);
+        }
+        for folder in ancestors {
+            doc.add_text(field("folders"), folder);
+        }
+        doc
+    }
+
+    fn to_quality(schema: &Schema, doc: &Document) -> QualityMetrics {
+        let float = |name: &str| doc.get_first(schema.get_field(name).unwrap()).and_then(|v| v.as_f64());
+        QualityMetrics {
+            sharpness: float("sharpness"),
+            noise: float("noise"),
+            mean_luminance: float("mean_luminance"),
+            clipped_highlights: float("clipped_highlights"),
+            clipped_shadows: float("clipped_shadows"),
+        }
+    }
+
+    fn to_burst_fields(schema: &Schema, doc: &Document) -> BurstFields {
+        let field = |name: &str| schema.get_field(name).unwrap();
+        let text = |name: &str| doc.get_first(field(name)).and_then(|v| v.as_text()).map(|s| s.to_string());
+
+        BurstFields {
+            file_hash: text("file_hash").unwrap_or_default(),
+            camera_make: text("camera_make"),
+            camera_model: text("camera_model"),
+            date_taken: text("date_taken"),
+            perceptual_hash: text("perceptual_hash"),
+            group_id: text("group_id"),
+            best_pick: doc.get_first(field("best_pick")).and_then(|v| v.as_u64()).unwrap_or(0) != 0,
+            quality: Self::to_quality(schema, doc),
+        }
+    }
+
+    fn to_metadata(schema: &Schema, doc: &Document) -> ImageMetadata {
+        let field = |name: &str| schema.get_field(name).unwrap();
+        let text = |name: &str| doc.get_first(field(name)).and_then(|v| v.as_text()).map(|s| s.to_string());
+        let number = |name: &str| doc.get_first(field(name)).and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+        let float = |name: &str| doc.get_first(field(name)).and_then(|v| v.as_f64());
+        let signed = |name: &str| doc.get_first(field(name)).and_then(|v| v.as_i64());
+
+        ImageMetadata {
+            file_path: text("file_path").unwrap_or_default(),
+            file_hash: text("file_hash").unwrap_or_default(),
+            width: number("width"),
+            height: number("height"),
+            camera_make: text("camera_make"),
+            camera_model: text("camera_model"),
+            date_taken: text("date_taken"),
+            gps_latitude: float("gps_latitude"),
+            gps_longitude: float("gps_longitude"),
+            has_exif: number("has_exif") != 0,
+            file_size: doc.get_first(field("file_size")).and_then(|v| v.as_u64()).unwrap_or(0),
+            modified_at: signed("modified_at"),
+            created_at: signed("created_at"),
+            extension: text("extension").unwrap_or_default(),
+            mime_type: text("mime_type").unwrap_or_default(),
+            thumbnail_path: text("thumbnail_path").unwrap_or_default(),
+            copies: doc
+                .get_all(field("copies"))
+                .filter_map(|v| v.as_json())
+                .filter_map(|record| serde_json::from_value::<FileCopy>(record.clone().into()).ok())
+                .collect(),
+            linked_paths: doc
+                .get_all(field("linked_paths"))
+                .filter_map(|v| v.as_text())
+                .map(|s| s.to_string())
+                .collect(),
+            xmp: doc
+                .get_first(field("xmp"))
+                .and_then(|v| v.as_json())
+                .and_then(|xmp| serde_json::from_value(xmp.clone().into()).ok())
+                .unwrap_or_default(),
+            iptc: doc
+                .get_first(field("iptc"))
+                .and_then(|v| v.as_json())
+                .and_then(|iptc| serde_json::from_value(iptc.clone().into()).ok())
+                .unwrap_or_default(),
+            place: doc
+                .get_first(field("place"))
+                .and_then(|v| v.as_json())
+                .and_then(|place| serde_json::from_value(place.clone().into()).ok()),
+            colors: doc
+                .get_first(field("colors"))
+                .and_then(|v| v.as_json())
+                .and_then(|colors| serde_json::from_value(colors.clone().into()).ok()),
+            perceptual_hash: text("perceptual_hash"),
+            color_histogram: doc.get_first(field("color_histogram")).and_then(|v| v.as_bytes()).map(|bytes| {
+                bytes
+                    .chunks_exact(4)
+                    .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                    .collect()
+            }),
+            group_id: text("group_id"),
+            best_pick: number("best_pick") != 0,
+            analyzer_versions: doc
+                .get_first(field("analyzer_versions"))
+                .and_then(|v| v.as_json())
+                .and_then(|versions| serde_json::from_value(versions.clone().into()).ok())
+                .unwrap_or_default(),
+            quality: Self::to_quality(schema, doc),
+            annotations: Annotations {
+                tags: doc
+                    .get_all(field("tags"))
+                    .filter_map(|v| v.as_text())
+                    .map(|s| s.to_string())
+                    .collect(),
+                rating: number("rating") as u8,
+                color_label: text("color_label").as_deref().and_then(ColorLabel::parse),
+                favorite: number("favorite") != 0,
+            },
+        }
+    }
+
+    /// Adds the range and keyword filters of `request` to the text query.
+    fn build_query(
+        index: &Index,
+        schema: &Schema,
+        request: &SearchRequest,
+    ) -> Result<Box<dyn Query>, AppError> {
+        let field = |name: &str| schema.get_field(name).unwrap();
+
+        let text_query: Box<dyn Query> = if request.q.trim().is_empty() {
+            Box::new(AllQuery)
+        } else {
+            let query_parser = QueryParser::for_index(
+                index,
+                vec![
+                    field("copy_paths"),
+                    field("tags"),
+                    field("keywords"),
+                    field("xmp_text"),
+                    field("iptc_text"),
+                    field("file_hash"),
+                    field("camera_make"),
+                    field("camera_model"),
+                    field("date_taken"),
+                ],
+            );
+            query_parser.parse_query(&request.q)?
+        };
+
+        let mut clauses: Vec<(Occur, Box<dyn Query>)> = vec![(Occur::Must, text_query)];
+        if request.min_size.is_some() || request.max_size.is_some() {
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_u64_bounds(
+                    field("file_size"),
+                    request.min_size.map_or(Bound::Unbounded, Bound::Included),
+                    request.max_size.map_or(Bound::Unbounded, Bound::Included),
+                )),
+            ));
+        }
+        if request.modified_after.is_some() || request.modified_before.is_some() {
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_i64_bounds(
+                    field("modified_at"),
+                    request.modified_after.map_or(Bound::Unbounded, Bound::Included),
+                    request.modified_before.map_or(Bound::Unbounded, Bound::Included),
+                )),
+            ));
+        }
+        for (name, min, max) in request.quality_ranges() {
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_f64_bounds(
+                    field(name),
+                    min.map_or(Bound::Unbounded, Bound::Included),
+                    max.map_or(Bound::Unbounded, Bound::Included),
+                )),
+            ));
+        }
+        if let Some(min_rating) = request.min_rating {
+            clauses.push((
+                Occur::Must,
+                Box::new(RangeQuery::new_u64_bounds(
+                    field("rating"),
+                    Bound::Included(min_rating as u64),
+                    Bound::Unbounded,
+                )),
+            ));
+        }
+        if let Some(favorite) = request.favorite {
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_u64(field("favorite"), favorite as u64),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+        for tag in request.tag_list() {
+            clauses.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_text(field("tags"), &tag),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
+        let keywords = [
+            ("extension", request.normalized_extension()),
+            ("mime_type", request.mime_type.clone()),
+            ("parent_folders", request.folder.as_deref().map(folders::normalize_folder)),
+            ("folders", request.path_prefix.as_deref().map(folders::normalize_folder)),
+            ("color_label", request.color_label.map(|c| c.as_str().to_string())),
+            ("keywords", request.keyword.clone()),
+            ("bylines", request.byline.clone()),
+            ("city", request.city.clone()),
+            ("country", request.country.clone()),
+            ("place_country", request.place_country.clone()),
+            ("place_region", request.place_region.clone()),
+            ("place_city", request.place_city.clone()),
+            ("group_id", request.group_id.clone()),
+        ];
+        for (name, value) in keywords {
+            if let Some(value) = value {
+                clauses.push((
+                    Occur::Must,
+                    Box::new(TermQuery::new(
+                        Term::from_field_text(field(name), &value),
+                        IndexRecordOption::Basic,
+                    )),
+                ));
+            }
+        }
+
+        if request.collapse_groups {
+            clauses.push((
+                Occur::MustNot,
+                Box::new(TermQuery::new(
+                    Term::from_field_u64(field("best_pick"), 0),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
+        if let Some(hashes) = &request.hashes {
+            let terms = hashes.iter().map(|hash| Term::from_field_text(field("file_hash"), hash));
+            clauses.push((Occur::Must, Box::new(TermSetQuery::new(terms))));
+        }
+
+        if clauses.len() == 1 {
+            Ok(clauses.pop().unwrap().1)
+        } else {
+            Ok(Box::new(BooleanQuery::new(clauses)))
+        }
+    }
+
+    /// Reads a numeric fast field as `f64`, so every sort key can share one collector.
+    fn sort_key_reader(
+        segment_reader: &SegmentReader,
+        schema: &Schema,
+        field_name: &str,
+    ) -> Box<dyn Fn(DocId) -> f64> {
+        let field = schema.get_field(field_name).unwrap();
+        let fast_fields = segment_reader.fast_fields();
+        match schema.get_field_entry(field).field_type() {
+            FieldType::I64(_) => match fast_fields.i64(field) {
+                Ok(column) => Box::new(move |doc| column.get_val(doc) as f64),
+                Err(_) => Box::new(|_| 0.0),
+            },
+            FieldType::F64(_) => match fast_fields.f64(field) {
+                Ok(column) => Box::new(move |doc| column.get_val(doc)),
+                Err(_) => Box::new(|_| 0.0),
+            },
+            _ => match fast_fields.u64(field) {
+                Ok(column) => Box::new(move |doc| column.get_val(doc) as f64),
+                Err(_) => Box::new(|_| 0.0),
+            },
+        }
+    }
+
+    /// Streams every live document from the doc store, segment by segment.
+    fn for_each_image(
+        index: &Index,
+        schema: &Schema,
+        mut visit: impl FnMut(ImageMetadata),
+    ) -> Result<(), AppError> {
+        Self::for_each_document(index, |doc| visit(Self::to_metadata(schema, doc)))
+    }
+
+    /// Streams every live stored document, segment by segment, without converting it.
+    fn for_each_document(index: &Index, mut visit: impl FnMut(&Document)) -> Result<(), AppError> {
+        let searcher = index.reader()?.searcher();
+        for segment_reader in searcher.segment_readers() {
+            let store_reader = segment_reader.get_store_reader(1)?;
+            for doc in store_reader.iter(segment_reader.alive_bitset()) {
+                visit(&doc?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Looks up the single document whose untokenized `field_name` equals `value`.
+    fn find_by_term(
+        index: &Index,
+        schema: &Schema,
+        field_name: &str,
+        value: &str,
+    ) -> Result<Option<ImageMetadata>, AppError> {
+        let searcher = index.reader()?.searcher();
+        let field = schema.get_field(field_name).unwrap();
+        let query = TermQuery::new(Term::from_field_text(field, value), IndexRecordOption::Basic);
+        let top_docs = searcher.search(&query, &TopDocs::with_limit(1))?;
+
+        match top_docs.first() {
+            Some((_score, doc_address)) => {
+                let doc = searcher.doc(*doc_address)?;
+                Ok(Some(Self::to_metadata(schema, &doc)))
+            }
+            None => Ok(None),
+        }
+    }
 }
 
 #[async_trait]
@@ -56,70 +657,25 @@ impl Searcher for TantivySearcher {
         tokio::task::spawn_blocking(move || {
             log::debug!("Attempting to index metadata for file: {}", metadata.file_path);
             let mut index_writer: IndexWriter = index.writer(50_000_000)?;
-
-            let file_path_field = schema.get_field("file_path").unwrap();
             let file_hash_field = schema.get_field("file_hash").unwrap();
-            let width_field = schema.get_field("width").unwrap();
-            let height_field = schema.get_field("height").unwrap();
-            let camera_make_field = schema.get_field("camera_make").unwrap();
-            let camera_model_field = schema.get_field("camera_model").unwrap();
-            let date_taken_field = schema.get_field("date_taken").unwrap();
-            let thumbnail_path_field = schema.get_field("thumbnail_path").unwrap();
-            let duplicate_paths_field = schema.get_field("duplicate_paths").unwrap();
 
-            let searcher = index.reader()?.searcher();
-            let query_parser = QueryParser::for_index(&index, vec![file_hash_field]);
-            let query = query_parser.parse_query(&metadata.file_hash)?;
             log::trace!("Searching for existing document with hash: {}", metadata.file_hash);
-            let top_docs = searcher.search(&query, &TopDocs::with_limit(1))?;
-
-            if top_docs.is_empty() {
-                log::trace!("No existing document found for hash: {}. Indexing new document.", metadata.file_hash);
-                let mut doc = Document::default();
-                doc.add_text(file_path_field, &metadata.file_path);
-                doc.add_text(file_hash_field, &metadata.file_hash);
-                doc.add_u64(width_field, metadata.width as u64);
-                doc.add_u64(height_field, metadata.height as u64);
-                if let Some(make) = &metadata.camera_make {
-                    doc.add_text(camera_make_field, make);
+            match Self::find_by_term(&index, &schema, "file_hash", &metadata.file_hash)? {
+                None => {
+                    log::trace!("No existing document found for hash: {}. Indexing new document.", metadata.file_hash);
+                    index_writer.add_document(Self::to_document(&schema, &metadata))?;
+                    log::debug!("New document indexed for file: {}", metadata.file_path);
                 }
-                if let Some(model) = &metadata.camera_model {
-                    doc.add_text(camera_model_field, model);
-                }
-                if let Some(date) = &metadata.date_taken {
-                    doc.add_text(date_taken_field, date);
-                }
-                doc.add_text(thumbnail_path_field, &metadata.thumbnail_path);
-                doc.add_text(
-                    duplicate_paths_field,
-                    &metadata.duplicate_paths.join(","),
-                );
-                index_writer.add_document(doc)?;
-                log::debug!("New document indexed for file: {}", metadata.file_path);
-            } else {
-                let (score, doc_address) = top_docs[0];
-                log::trace!("Duplicate image found for hash: {}. Score: {}. Doc Address: {:?}", metadata.file_hash, score, doc_address);
-                let existing_doc = searcher.doc(doc_address)?;
-                let mut new_doc = Document::default();
-
-                for field_value in existing_doc.field_values() {
-                    if field_value.field() == duplicate_paths_field {
-                        let mut paths = field_value.value().as_text().unwrap_or("").to_string();
-                        // Only add if the path is not already present
-                        if !paths.split(',').any(|s| s == metadata.file_path) {
-                             if !paths.is_empty() {
-                                paths.push(',');
-                            }
-                            paths.push_str(&metadata.file_path);
-                        }
-                        new_doc.add_text(duplicate_paths_field, &paths);
-                    } else {
-                        new_doc.add_field_value(field_value.field(), field_value.value().clone());
+                Some(mut existing) => {
+                    log::trace!("Duplicate image found for hash: {}", metadata.file_hash);
+                    // Only add copies whose path is not already present
+                    for copy in metadata.copies {
+                        existing.add_copy(copy);
                     }
+                    index_writer.delete_term(Term::from_field_text(file_hash_field, &metadata.file_hash));
+                    index_writer.add_document(Self::to_document(&schema, &existing))?;
+                    log::debug!("Existing document updated for file: {}", metadata.file_path);
                 }
-                index_writer.delete_term(Term::from_field_text(file_hash_field, &metadata.file_hash));
-                index_writer.add_document(new_doc)?;
-                log::debug!("Existing document updated for file: {}", metadata.file_path);
             }
 
             index_writer.commit()?;
@@ -129,76 +685,81 @@ impl Searcher for TantivySearcher {
         .await?
     }
 
-    async fn search_images(&self, query: String) -> Result<Vec<ImageMetadata>, AppError> {
+    async fn search_images(&self, request: &SearchRequest) -> Result<Vec<ImageMetadata>, AppError> {
         let index = self.index.clone();
         let schema = self.schema.clone();
+        let request = request.clone();
 
         tokio::task::spawn_blocking(move || {
-            log::debug!("Searching Tantivy for images with query: {}", query);
+            log::debug!("Searching Tantivy for images with request: {:?}", request);
             let searcher = index.reader()?.searcher();
-            let mut images = Vec::new();
-
-            let file_path_field = schema.get_field("file_path").unwrap();
-            let file_hash_field = schema.get_field("file_hash").unwrap();
-            let camera_make_field = schema.get_field("camera_make").unwrap();
-            let camera_model_field = schema.get_field("camera_model").unwrap();
-            let date_taken_field = schema.get_field("date_taken").unwrap();
-            let thumbnail_path_field = schema.get_field("thumbnail_path").unwrap();
-            let duplicate_paths_field = schema.get_field("duplicate_paths").unwrap();
-            let width_field = schema.get_field("width").unwrap();
-            let height_field = schema.get_field("height").unwrap();
-
+            let query = Self::build_query(&index, &schema, &request)?;
+            let collector = TopDocs::with_limit(request.limit.max(1)).and_offset(request.offset);
 
-            let query_parser = QueryParser::for_index(
-                &index,
-                vec![
-                    file_path_field,
-                    file_hash_field,
-                    camera_make_field,
-                    camera_model_field,
-                    date_taken_field,
-                ],
-            );
-
-            let query_obj = if query.is_empty() {
-                query_parser.parse_query("*")? // Match all if query is empty
-            } else {
-                query_parser.parse_query(&query)?
+            let color_target = request.color_target()?;
+            let doc_addresses: Vec<DocAddress> = match (color_target, request.sort.field_name()) {
+                (Some((target, tolerance)), _) => {
+                    let palette_field = schema.get_field("palette_lab").unwrap();
+                    let collector = collector.custom_score(move |segment_reader: &SegmentReader| {
+                        let palettes = segment_reader.fast_fields().u64s(palette_field).ok();
+                        let mut values = Vec::new();
+                        move |doc: DocId| {
+                            let Some(palettes) = &palettes else { return f64::NEG_INFINITY };
+                            palettes.get_vals(doc, &mut values);
+                            let distance = colors::palette_distance(values.iter().map(|v| colors::unpack(*v)), target);
+                            -distance.unwrap_or(f64::INFINITY)
+                        }
+                    });
+                    // Matches rank ahead of every non-match, so cutting at the tolerance keeps paging intact.
+                    searcher
+                        .search(&query, &collector)?
+                        .into_iter()
+                        .take_while(|(score, _)| -score <= tolerance)
+                        .map(|(_score, doc_address)| doc_address)
+                        .collect()
+                }
+                (None, None) => searcher
+                    .search(&query, &collector)?
+                    .into_iter()
+                    .map(|(_score, doc_address)| doc_address)
+                    .collect(),
+                (None, Some(field_name)) => {
+                    // TopDocs keeps the highest scores, so negate the key for ascending order.
+                    let sign = if request.order == SortOrder::Asc { -1.0 } else { 1.0 };
+                    let sort_schema = schema.clone();
+                    let field_name = field_name.to_string();
+                    let collector = collector.custom_score(move |segment_reader: &SegmentReader| {
+                        let key = Self::sort_key_reader(segment_reader, &sort_schema, &field_name);
+                        move |doc: DocId| sign * key(doc)
+                    });
+                    searcher
+                        .search(&query, &collector)?
+                        .into_iter()
+                        .map(|(_key, doc_address)| doc_address)
+                        .collect()
+                }
             };
 
-            let top_docs = searcher.search(&query_obj, &TopDocs::with_limit(100))?; // Limit results for UI
-
-            for (_score, doc_address) in top_docs {
+            let mut images = Vec::with_capacity(doc_addresses.len());
+            for doc_address in doc_addresses {
                 let retrieved_doc = searcher.doc(doc_address)?;
-                let file_path_val = retrieved_doc.get_first(file_path_field).and_then(|v| v.as_text()).unwrap_or("").to_string();
-                let file_hash_val = retrieved_doc.get_first(file_hash_field).and_then(|v| v.as_text()).unwrap_or("").to_string();
-                let width_val = retrieved_doc.get_first(width_field).and_then(|v| v.as_u64()).unwrap_or(0) as u32;
-                let height_val = retrieved_doc.get_first(height_field).and_then(|v| v.as_u64()).unwrap_or(0) as u32;
-                let camera_make_val = retrieved_doc.get_first(camera_make_field).and_then(|v| v.as_text()).map(|s| s.to_string());
-                let camera_model_val = retrieved_doc.get_first(camera_model_field).and_then(|v| v.as_text()).map(|s| s.to_string());
-                let date_taken_val = retrieved_doc.get_first(date_taken_field).and_then(|v| v.as_text()).map(|s| s.to_string());
-                let thumbnail_path_val = retrieved_doc.get_first(thumbnail_path_field).and_then(|v| v.as_text()).unwrap_or("").to_string();
-                let duplicate_paths_val = retrieved_doc.get_first(duplicate_paths_field).and_then(|v| v.as_text()).unwrap_or("").split(',').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect();
-
-                images.push(ImageMetadata {
-                    file_path: file_path_val,
-                    file_hash: file_hash_val,
-                    width: width_val,
-                    height: height_val,
-                    camera_make: camera_make_val,
-                    camera_model: camera_model_val,
-                    date_taken: date_taken_val,
-                    gps_latitude: None, // Tantivy doesn't have direct geo_point
-                    gps_longitude: None, // Tantivy doesn't have direct geo_point
-                    thumbnail_path: thumbnail_path_val,
-                    duplicate_paths: duplicate_paths_val,
-                });
+                images.push(Self::to_metadata(&schema, &retrieved_doc));
             }
-            log::debug!("Found {} images in Tantivy for query: {}", images.len(), query);
+            log::debug!("Found {} images in Tantivy for query: {}", images.len(), request.q);
             Ok(images)
         }).await?
     }
 
+    async fn search_all(&self, request: &SearchRequest) -> Result<Vec<ImageMetadata>, AppError> {
+        let total = self.count_images().await? as usize;
+        let request = SearchRequest {
+            limit: total,
+            offset: 0,
+            ..request.clone()
+        };
+        self.search_images(&request).await
+    }
+
     async fn count_images(&self) -> Result<u64, AppError> {
         let index = self.index.clone();
         let result = tokio::task::spawn_blocking(move || {
@@ -251,7 +812,7 @@ impl Searcher for TantivySearcher {
 
             // Create a collector that collects all documents.
             // We can use TopDocs with a limit of all docs.
-            let top_docs = searcher.search(&query, &tantivy::collector::TopDocs::with_limit(searcher.num_docs() as usize))?;
+            let top_docs = searcher.search(&query, &tantivy::collector::TopDocs::with_limit((searcher.num_docs() as usize).max(1)))?;
 
             for (_score, doc_address) in top_docs {
                 let retrieved_doc = searcher.doc(doc_address)?;
@@ -266,4 +827,225 @@ impl Searcher for TantivySearcher {
         })
         .await?
     }
+
+    async fn get_by_hash(&self, hash: &str) -> Result<Option<ImageMetadata>, AppError> {
+        let index = self.index.clone();
+        let schema = self.schema.clone();
+        let hash = hash.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            log::debug!("Looking up image by hash: {}", hash);
+            Self::find_by_term(&index, &schema, "file_hash", &hash)
+        })
+        .await?
+    }
+
+    async fn get_by_path(&self, path: &str) -> Result<Option<ImageMetadata>, AppError> {
+        let index = self.index.clone();
+        let schema = self.schema.clone();
+        let path = path.to_string();
+
+        tokio::task::spawn_blocking(move || {
+            log::debug!("Looking up image by path: {}", path);
+            Self::find_by_term(&index, &schema, "paths", &path)
+        })
+        .await?
+    }
+
+    async fn get_all_images(&self) -> Result<Vec<ImageMetadata>, AppError> {
+        let index = self.index.clone();
+        let schema = self.schema.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut images = Vec::new();
+            Self::for_each_image(&index, &schema, |metadata| images.push(metadata))?;
+            Ok(images)
+        })
+        .await?
+    }
+
+    async fn get_burst_fields(&self) -> Result<Vec<BurstFields>, AppError> {
+        let index = self.index.clone();
+        let schema = self.schema.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut images = Vec::new();
+            Self::for_each_document(&index, |doc| images.push(Self::to_burst_fields(&schema, doc)))?;
+            Ok(images)
+        })
+        .await?
+    }
+
+    async fn get_duplicate_groups(&self) -> Result<Vec<ImageMetadata>, AppError> {
+        let index = self.index.clone();
+        let schema = self.schema.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let searcher = index.reader()?.searcher();
+            let duplicate_count_field = schema.get_field("duplicate_count").unwrap();
+            let query = RangeQuery::new_u64(duplicate_count_field, 1..u64::MAX);
+            let doc_addresses = searcher.search(&query, &DocSetCollector)?;
+
+            let mut groups = Vec::with_capacity(doc_addresses.len());
+            for doc_address in doc_addresses {
+                let doc = searcher.doc(doc_address)?;
+                groups.push(Self::to_metadata(&schema, &doc));
+            }
+            log::debug!("Found {} duplicate groups in Tantivy", groups.len());
+            Ok(groups)
+        })
+        .await?
+    }
+
+    async fn compute_stats(&self) -> Result<LibraryStats, AppError> {
+        let index = self.index.clone();
+        let schema = self.schema.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut stats = LibraryStats::default();
+            Self::for_each_image(&index, &schema, |metadata| stats.add(&metadata))?;
+            log::debug!("Computed stats over {} images in Tantivy", stats.unique_images);
+            Ok(stats)
+        })
+        .await?
+    }
+
+    async fn compute_facets(&self, request: &SearchRequest) -> Result<Facets, AppError> {
+        let index = self.index.clone();
+        let schema = self.schema.clone();
+        let request = request.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let searcher = index.reader()?.searcher();
+            let query = Self::build_query(&index, &schema, &request)?;
+            let mut facets = Facets::default();
+            for doc_address in searcher.search(&query, &DocSetCollector)? {
+                facets.add(&Self::to_metadata(&schema, &searcher.doc(doc_address)?));
+            }
+            Ok(facets)
+        })
+        .await?
+    }
+
+    async fn compute_places(&self, request: &SearchRequest) -> Result<PlaceCounts, AppError> {
+        let index = self.index.clone();
+        let schema = self.schema.clone();
+        let request = request.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let searcher = index.reader()?.searcher();
+            let query = Self::build_query(&index, &schema, &request)?;
+            let mut places = PlaceCounts::default();
+            for doc_address in searcher.search(&query, &DocSetCollector)? {
+                places.add(&Self::to_metadata(&schema, &searcher.doc(doc_address)?));
+            }
+            Ok(places)
+        })
+        .await?
+    }
+
+    async fn compute_geo_clusters(
+        &self,
+        request: &SearchRequest,
+        bbox: BoundingBox,
+        precision: usize,
+    ) -> Result<GeoClusters, AppError> {
+        let index = self.index.clone();
+        let schema = self.schema.clone();
+        let request = request.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let field = |name: &str| schema.get_field(name).unwrap();
+            let searcher = index.reader()?.searcher();
+
+            // Only documents with a position have terms in the coordinate fields, so the ranges also skip the rest.
+            let longitudes: Vec<(Occur, Box<dyn Query>)> = bbox
+                .longitude_ranges()
+                .into_iter()
+                .map(|(west, east)| {
+                    let range = RangeQuery::new_f64_bounds(
+                        field("gps_longitude"),
+                        Bound::Included(west),
+                        Bound::Included(east),
+                    );
+                    (Occur::Should, Box::new(range) as Box<dyn Query>)
+                })
+                .collect();
+            let query = BooleanQuery::new(vec![
+                (Occur::Must, Self::build_query(&index, &schema, &request)?),
+                (
+                    Occur::Must,
+                    Box::new(RangeQuery::new_f64_bounds(
+                        field("gps_latitude"),
+                        Bound::Included(bbox.south),
+                        Bound::Included(bbox.north),
+                    )),
+                ),
+                (Occur::Must, Box::new(BooleanQuery::new(longitudes))),
+            ]);
+
+            let columns = searcher
+                .segment_readers()
+                .iter()
+                .map(|reader| {
+                    let fast_fields = reader.fast_fields();
+                    Ok((fast_fields.f64(field("gps_latitude"))?, fast_fields.f64(field("gps_longitude"))?))
+                })
+                .collect::<Result<Vec<_>, AppError>>()?;
+            let mut counts = ClusterCounts::new(precision);
+            for address in searcher.search(&query, &DocSetCollector)? {
+                let (latitudes, longitudes) = &columns[address.segment_ord as usize];
+                counts.add(latitudes.get_val(address.doc_id), longitudes.get_val(address.doc_id), || address);
+            }
+            // Only one stored document per cell is read, for its hash.
+            Ok(counts.into_clusters(|address| {
+                let doc = searcher.doc(address).ok()?;
+                doc.get_first(field("file_hash")).and_then(|v| v.as_text()).map(|s| s.to_string())
+            }))
+        })
+        .await?
+    }
+
+    async fn compute_folders(&self) -> Result<FolderCounts, AppError> {
+        let index = self.index.clone();
+        let schema = self.schema.clone();
+
+        // Counts the postings of each `parent_folders` term instead of reading every document.
+        tokio::task::spawn_blocking(move || {
+            let searcher = index.reader()?.searcher();
+            let field = schema.get_field("parent_folders").unwrap();
+            let hash_field = schema.get_field("file_hash").unwrap();
+            let mut counts = FolderCounts::default();
+            for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+                let alive = segment_reader.alive_bitset();
+                let inverted_index = segment_reader.inverted_index(field)?;
+                let mut terms = inverted_index.terms().stream()?;
+                while terms.advance() {
+                    let folder = String::from_utf8_lossy(terms.key()).to_string();
+                    let mut postings =
+                        inverted_index.read_postings_from_terminfo(terms.value(), IndexRecordOption::Basic)?;
+                    let (mut count, mut first) = (0, None);
+                    let mut doc = postings.doc();
+                    while doc != TERMINATED {
+                        if alive.is_none_or(|alive| alive.is_alive(doc)) {
+                            count += 1;
+                            first.get_or_insert(doc);
+                        }
+                        doc = postings.advance();
+                    }
+                    let sample = match first {
+                        Some(doc) if !counts.has_representative(&folder) => searcher
+                            .doc(DocAddress::new(segment_ord as u32, doc))?
+                            .get_first(hash_field)
+                            .and_then(|v| v.as_text())
+                            .map(|s| s.to_string()),
+                        _ => None,
+                    };
+                    counts.add_folder(&folder, count, sample.as_deref());
+                }
+            }
+            Ok(counts)
+        })
+        .await?
+    }
 }
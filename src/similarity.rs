@@ -0,0 +1,196 @@
+use crate::error::AppError;
+use crate::metadata::ImageMetadata;
+use crate::perceptual;
+use image::DynamicImage;
+use std::collections::HashMap;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+/// Levels per RGB channel in the color histogram, giving `LEVELS^3` bins.
+const LEVELS: usize = 4;
+pub const HISTOGRAM_BINS: usize = LEVELS * LEVELS * LEVELS;
+const FILE_MAGIC: &[u8; 4] = b"SIMI";
+const FILE_VERSION: u32 = 1;
+
+/// A normalized color histogram of the thumbnail: the fraction of pixels in each bin.
+pub fn color_histogram(image: &DynamicImage) -> Vec<f32> {
+    let rgb = image.to_rgb8();
+    let mut counts = [0u32; HISTOGRAM_BINS];
+    for pixel in rgb.pixels() {
+        let [r, g, b] = pixel.0.map(|c| c as usize * LEVELS / 256);
+        counts[(r * LEVELS + g) * LEVELS + b] += 1;
+    }
+    let total = rgb.pixels().len().max(1) as f32;
+    counts.iter().map(|c| *c as f32 / total).collect()
+}
+
+/// What an image is compared by: its color histogram and perceptual hash.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Features {
+    pub histogram: Vec<f32>,
+    pub hash: u64,
+}
+
+impl Features {
+    pub fn from_metadata(metadata: &ImageMetadata) -> Option<Self> {
+        let histogram = metadata.color_histogram.clone().filter(|h| h.len() == HISTOGRAM_BINS)?;
+        let hash = perceptual::from_hex(metadata.perceptual_hash.as_deref()?)?;
+        Some(Self { histogram, hash })
+    }
+
+    /// From 0 for identical features to 1: the mean of the histogram distance
+    /// (half the L1 distance) and the fraction of differing hash bits.
+    pub fn distance(&self, other: &Features) -> f64 {
+        let histogram: f64 = self
+            .histogram
+            .iter()
+            .zip(&other.histogram)
+            .map(|(a, b)| (a - b).abs() as f64)
+            .sum::<f64>()
+            / 2.0;
+        let hash = perceptual::distance(self.hash, other.hash) as f64 / 64.0;
+        (histogram.min(1.0) + hash) / 2.0
+    }
+}
+
+/// Features of every indexed image by content hash, searched exhaustively and saved to a
+/// binary file so the server does not have to read every document on startup.
+///
+/// Entries of images deleted from the search index are not removed right away; callers
+/// drop them with `remove` when a lookup turns up a hash the index no longer has.
+pub struct SimilarityIndex {
+    path: PathBuf,
+    entries: RwLock<HashMap<String, Features>>,
+}
+
+impl SimilarityIndex {
+    /// Loads the index saved at `path`, or starts empty if there is none or it is unreadable.
+    pub fn load(path: &str) -> Self {
+        let path = PathBuf::from(path);
+        let entries = match read_entries(&path) {
+            Ok(entries) => entries,
+            Err(AppError::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                log::warn!("Could not read similarity index {:?}, starting empty: {}", path, e);
+                HashMap::new()
+            }
+        };
+        log::info!("Loaded similarity features for {} images from {:?}", entries.len(), path);
+        Self {
+            path,
+            entries: RwLock::new(entries),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.read().unwrap().len()
+    }
+
+    pub fn get(&self, hash: &str) -> Option<Features> {
+        self.entries.read().unwrap().get(hash).cloned()
+    }
+
+    /// Adds or replaces the features of an image. Images without features are removed.
+    pub fn insert(&self, metadata: &ImageMetadata) {
+        let mut entries = self.entries.write().unwrap();
+        match Features::from_metadata(metadata) {
+            Some(features) => entries.insert(metadata.file_hash.clone(), features),
+            None => entries.remove(&metadata.file_hash),
+        };
+    }
+
+    pub fn remove(&self, hash: &str) {
+        self.entries.write().unwrap().remove(hash);
+    }
+
+    /// Replaces every entry with the features of `images`.
+    pub fn rebuild(&self, images: &[ImageMetadata]) {
+        let entries = images
+            .iter()
+            .filter_map(|m| Some((m.file_hash.clone(), Features::from_metadata(m)?)))
+            .collect();
+        *self.entries.write().unwrap() = entries;
+    }
+
+    /// Hashes of the images closest to `target`, nearest first, up to `max_distance`.
+    pub fn nearest(&self, target: &Features, exclude: &str, max_distance: f64) -> Vec<(String, f64)> {
+        let mut matches: Vec<(String, f64)> = self
+            .entries
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(hash, _)| hash.as_str() != exclude)
+            .map(|(hash, features)| (hash.clone(), target.distance(features)))
+            .filter(|(_, distance)| *distance <= max_distance)
+            .collect();
+        matches.sort_by(|a, b| a.1.total_cmp(&b.1).then_with(|| a.0.cmp(&b.0)));
+        matches
+    }
+
+    /// Writes every entry to a temporary file and renames it into place.
+    pub fn save(&self) -> Result<(), AppError> {
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let tmp_path = self.path.with_extension("tmp");
+        let mut writer = BufWriter::new(std::fs::File::create(&tmp_path)?);
+        writer.write_all(FILE_MAGIC)?;
+        writer.write_all(&FILE_VERSION.to_le_bytes())?;
+        let entries = self.entries.read().unwrap();
+        writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+        for (hash, features) in entries.iter() {
+            writer.write_all(&(hash.len() as u16).to_le_bytes())?;
+            writer.write_all(hash.as_bytes())?;
+            writer.write_all(&features.hash.to_le_bytes())?;
+            for value in &features.histogram {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        writer.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        log::debug!("Saved similarity features for {} images to {:?}", entries.len(), self.path);
+        Ok(())
+    }
+}
+
+fn read_entries(path: &PathBuf) -> Result<HashMap<String, Features>, AppError> {
+    let mut reader = BufReader::new(std::fs::File::open(path)?);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    let version = read_u32(&mut reader)?;
+    if &magic != FILE_MAGIC || version != FILE_VERSION {
+        return Err(AppError::Generic(format!("unsupported similarity index format {}", version)));
+    }
+    let mut count = [0u8; 8];
+    reader.read_exact(&mut count)?;
+    let count = u64::from_le_bytes(count) as usize;
+
+    let mut entries = HashMap::with_capacity(count);
+    for _ in 0..count {
+        let mut length = [0u8; 2];
+        reader.read_exact(&mut length)?;
+        let mut hash = vec![0u8; u16::from_le_bytes(length) as usize];
+        reader.read_exact(&mut hash)?;
+        let mut perceptual_hash = [0u8; 8];
+        reader.read_exact(&mut perceptual_hash)?;
+        let mut histogram = Vec::with_capacity(HISTOGRAM_BINS);
+        for _ in 0..HISTOGRAM_BINS {
+            histogram.push(f32::from_bits(read_u32(&mut reader)?));
+        }
+        entries.insert(
+            String::from_utf8_lossy(&hash).to_string(),
+            Features {
+                histogram,
+                hash: u64::from_le_bytes(perceptual_hash),
+            },
+        );
+    }
+    Ok(entries)
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32, AppError> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
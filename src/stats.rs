@@ -0,0 +1,88 @@
+use crate::metadata::ImageMetadata;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The bucket for images without an extension, camera or date.
+pub const UNKNOWN: &str = "unknown";
+
+/// Aggregate figures about the whole library.
+#[derive(Serialize, Debug, Default)]
+pub struct LibraryStats {
+    pub total_files: u64,
+    pub total_bytes: u64,
+    pub unique_images: u64,
+    pub duplicate_groups: u64,
+    pub bytes_reclaimable: u64,
+    pub with_gps: u64,
+    pub without_exif: u64,
+    pub by_extension: BTreeMap<String, u64>,
+    pub by_camera: BTreeMap<String, u64>,
+    pub by_year: BTreeMap<String, u64>,
+    pub thumbnail_count: u64,
+    pub thumbnail_bytes: u64,
+}
+
+impl LibraryStats {
+    pub fn add(&mut self, metadata: &ImageMetadata) {
+        let copies = metadata.paths().count() as u64;
+        let linked = metadata.linked_paths.len() as u64;
+        let size = metadata.file_size;
+
+        self.unique_images += 1;
+        self.total_files += copies;
+        self.total_bytes += size * copies;
+        if copies > 1 {
+            self.duplicate_groups += 1;
+            self.bytes_reclaimable += size * (copies - 1).saturating_sub(linked);
+        }
+        if metadata.gps_latitude.is_some() && metadata.gps_longitude.is_some() {
+            self.with_gps += 1;
+        }
+        if !metadata.has_exif {
+            self.without_exif += 1;
+        }
+
+        *self.by_extension.entry(extension(metadata)).or_default() += 1;
+        *self
+            .by_camera
+            .entry(camera(metadata.camera_make.as_deref(), metadata.camera_model.as_deref()))
+            .or_default() += 1;
+        *self.by_year.entry(year(metadata.date_taken.as_deref())).or_default() += 1;
+    }
+}
+
+fn extension(metadata: &ImageMetadata) -> String {
+    if !metadata.extension.is_empty() {
+        return metadata.extension.clone();
+    }
+    Path::new(&metadata.file_path)
+        .extension()
+        .and_then(|s| s.to_str())
+        .map(|s| s.to_lowercase())
+        .unwrap_or_else(|| UNKNOWN.to_string())
+}
+
+/// The make and model joined, without the quotes some cameras write around them.
+pub fn camera(make: Option<&str>, model: Option<&str>) -> String {
+    let parts: Vec<&str> = [make, model]
+        .into_iter()
+        .flatten()
+        .map(|s| s.trim_matches(|c: char| c == '"' || c.is_whitespace()))
+        .filter(|s| !s.is_empty())
+        .collect();
+    if parts.is_empty() {
+        UNKNOWN.to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// EXIF dates look like `2016:05:04 03:02:01` or `2016-05-04 03:02:01`.
+fn year(date_taken: Option<&str>) -> String {
+    date_taken
+        .and_then(|date| date.get(0..4))
+        .filter(|year| year.chars().all(|c| c.is_ascii_digit()))
+        .map(|year| year.to_string())
+        .unwrap_or_else(|| UNKNOWN.to_string())
+}
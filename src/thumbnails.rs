@@ -0,0 +1,176 @@
+use crate::error::AppError;
+use image::DynamicImage;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+const THUMBNAIL_EXTENSION: &str = "jpg";
+const THUMBNAIL_SIZE: u32 = 256;
+
+#[derive(Serialize, Debug, Default)]
+pub struct GcReport {
+    pub scanned: u64,
+    pub removed: u64,
+    pub bytes_freed: u64,
+}
+
+/// Returns the sharded location of a thumbnail, e.g. `ab/cd/abcd....jpg`.
+pub fn thumbnail_path_for(thumbnail_dir: &Path, hash: &str) -> PathBuf {
+    let mut path = thumbnail_dir.to_path_buf();
+    if hash.len() >= 4 && hash.is_char_boundary(2) && hash.is_char_boundary(4) {
+        path.push(&hash[0..2]);
+        path.push(&hash[2..4]);
+    }
+    path.push(format!("{}.{}", hash, THUMBNAIL_EXTENSION));
+    path
+}
+
+/// Decodes `source`, writes its thumbnail to `thumbnail_path` and returns the thumbnail.
+pub fn generate_thumbnail(source: &Path, thumbnail_path: &Path) -> Result<DynamicImage, AppError> {
+    let thumbnail = make_thumbnail(&open_image(source)?);
+    save_thumbnail(&thumbnail, thumbnail_path)?;
+    Ok(thumbnail)
+}
+
+pub fn open_image(source: &Path) -> Result<DynamicImage, AppError> {
+    log::trace!("Opening image for thumbnail generation: {:?}", source);
+    let image = image::open(source).map_err(|e| {
+        log::warn!("Could not open image for thumbnail generation {:?}: {}", source, e);
+        e
+    })?;
+    Ok(image)
+}
+
+/// Scales an already decoded image down to thumbnail size, keeping its aspect ratio.
+pub fn make_thumbnail(image: &DynamicImage) -> DynamicImage {
+    image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+}
+
+/// Writes a thumbnail to `thumbnail_path`.
+///
+/// The thumbnail is written to a temporary file first and renamed into place, so
+/// concurrent readers never see a partially written JPEG.
+pub fn save_thumbnail(thumbnail: &DynamicImage, thumbnail_path: &Path) -> Result<(), AppError> {
+    if let Some(shard_dir) = thumbnail_path.parent() {
+        std::fs::create_dir_all(shard_dir)?;
+    }
+
+    let tmp_path = thumbnail_path.with_extension(format!("{}.tmp", THUMBNAIL_EXTENSION));
+    thumbnail.save_with_format(&tmp_path, image::ImageFormat::Jpeg)?;
+    std::fs::rename(&tmp_path, thumbnail_path)?;
+    log::debug!("Thumbnail saved to: {:?}", thumbnail_path);
+    Ok(())
+}
+
+/// Moves thumbnails left in the old flat layout into their sharded directories.
+pub fn migrate_flat_layout(thumbnail_dir: &Path) -> Result<u64, AppError> {
+    if !thumbnail_dir.exists() {
+        return Ok(0);
+    }
+
+    let mut migrated = 0;
+    for entry in std::fs::read_dir(thumbnail_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let hash = match thumbnail_hash(&path) {
+            Some(hash) => hash,
+            None => continue,
+        };
+
+        let target = thumbnail_path_for(thumbnail_dir, &hash);
+        if target == path {
+            continue;
+        }
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        if target.exists() {
+            log::debug!("Sharded thumbnail already exists for {}, removing flat copy", hash);
+            std::fs::remove_file(&path)?;
+        } else {
+            std::fs::rename(&path, &target)?;
+            log::trace!("Migrated thumbnail {:?} to {:?}", path, target);
+        }
+        migrated += 1;
+    }
+
+    if migrated > 0 {
+        log::info!("Migrated {} thumbnails to the sharded layout in {:?}", migrated, thumbnail_dir);
+    }
+    Ok(migrated)
+}
+
+/// Deletes every thumbnail whose hash is not in `live_hashes`.
+pub fn collect_garbage(thumbnail_dir: &Path, live_hashes: &HashSet<String>) -> Result<GcReport, AppError> {
+    let mut report = GcReport::default();
+    if !thumbnail_dir.exists() {
+        return Ok(report);
+    }
+
+    for entry in WalkDir::new(thumbnail_dir).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path();
+        let hash = match thumbnail_hash(path) {
+            Some(hash) => hash,
+            None => continue,
+        };
+        report.scanned += 1;
+
+        if live_hashes.contains(&hash) {
+            continue;
+        }
+        let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+        match std::fs::remove_file(path) {
+            Ok(_) => {
+                log::debug!("Removed orphaned thumbnail: {:?}", path);
+                report.removed += 1;
+                report.bytes_freed += size;
+            }
+            Err(e) => log::warn!("Failed to remove orphaned thumbnail {:?}: {}", path, e),
+        }
+    }
+
+    log::info!(
+        "Thumbnail GC scanned {} files, removed {} ({} bytes)",
+        report.scanned,
+        report.removed,
+        report.bytes_freed
+    );
+    Ok(report)
+}
+
+/// Returns the number of thumbnails and the bytes they occupy.
+pub fn cache_usage(thumbnail_dir: &Path) -> (u64, u64) {
+    WalkDir::new(thumbnail_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file() && thumbnail_hash(e.path()).is_some())
+        .filter_map(|e| e.metadata().ok())
+        .fold((0, 0), |(count, bytes), m| (count + 1, bytes + m.len()))
+}
+
+/// Removes the thumbnail for `hash`, if there is one.
+pub fn remove_thumbnail(thumbnail_dir: &Path, hash: &str) -> Result<(), AppError> {
+    let path = thumbnail_path_for(thumbnail_dir, hash);
+    match std::fs::remove_file(&path) {
+        Ok(_) => {
+            log::debug!("Removed thumbnail: {:?}", path);
+            Ok(())
+        }
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(AppError::Io(e)),
+    }
+}
+
+fn thumbnail_hash(path: &Path) -> Option<String> {
+    if path.extension().and_then(|s| s.to_str()) != Some(THUMBNAIL_EXTENSION) {
+        return None;
+    }
+    path.file_stem().and_then(|s| s.to_str()).map(|s| s.to_string())
+}
@@ -0,0 +1,254 @@
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const JOURNAL_FILE: &str = "journal.jsonl";
+const FILES_DIR: &str = "files";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrashEntry {
+    pub id: String,
+    pub original_path: String,
+    pub trashed_path: String,
+    pub file_hash: String,
+    pub size: u64,
+    pub trashed_at: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TrashAction {
+    Trash,
+    Restore,
+    Purge,
+}
+
+/// One line of the undo journal.
+#[derive(Serialize, Deserialize, Debug)]
+struct JournalRecord {
+    action: TrashAction,
+    at: u64,
+    entry: TrashEntry,
+}
+
+/// A quarantine directory that files are moved into instead of being unlinked.
+///
+/// Every move, restore and purge is appended to `journal.jsonl`; the current content of the
+/// trash is whatever the journal says was trashed and not yet restored or purged.
+pub struct Trash {
+    directory: PathBuf,
+    journal_lock: Mutex<()>,
+    next_id: AtomicU64,
+}
+
+impl Trash {
+    pub fn new(directory: &str) -> Result<Self, AppError> {
+        let directory = PathBuf::from(directory);
+        std::fs::create_dir_all(directory.join(FILES_DIR))?;
+        let seed = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        Ok(Self {
+            directory,
+            journal_lock: Mutex::new(()),
+            next_id: AtomicU64::new(seed),
+        })
+    }
+
+    /// Moves `path` into the trash, keeping its original directory structure below the entry.
+    pub fn move_to_trash(&self, path: &str, file_hash: &str) -> Result<TrashEntry, AppError> {
+        let source = Path::new(path);
+        let size = std::fs::metadata(source)?.len();
+        let id = format!("{:x}", self.next_id.fetch_add(1, Ordering::Relaxed));
+        let target = self.directory.join(FILES_DIR).join(&id).join(relative_to_root(source));
+
+        let entry = TrashEntry {
+            id,
+            original_path: path.to_string(),
+            trashed_path: target.to_string_lossy().to_string(),
+            file_hash: file_hash.to_string(),
+            size,
+            trashed_at: now(),
+        };
+        if let Err(e) = self.move_and_record(source, &target, TrashAction::Trash, &entry) {
+            // Only clean up once the file is back where it was.
+            if source.exists() {
+                self.remove_entry_dir(&entry.id);
+            }
+            return Err(e);
+        }
+        log::info!("Moved {:?} to trash at {:?}", source, target);
+        Ok(entry)
+    }
+
+    /// Lists everything currently in the trash, oldest first.
+    pub fn list(&self) -> Result<Vec<TrashEntry>, AppError> {
+        let _guard = self.journal_lock.lock().unwrap();
+        self.replay()
+    }
+
+    /// Moves a trashed file back to where it came from.
+    pub fn restore(&self, id: &str) -> Result<TrashEntry, AppError> {
+        let entry = self.find(id)?;
+        let original = Path::new(&entry.original_path);
+        if original.exists() {
+            return Err(AppError::BadRequest(format!(
+                "Cannot restore {}: a file already exists at that path",
+                entry.original_path
+            )));
+        }
+
+        self.move_and_record(Path::new(&entry.trashed_path), original, TrashAction::Restore, &entry)?;
+        self.remove_entry_dir(&entry.id);
+        log::info!("Restored {} from trash", entry.original_path);
+        Ok(entry)
+    }
+
+    /// Permanently deletes everything in the trash. Returns the purged entries.
+    pub fn empty(&self) -> Result<Vec<TrashEntry>, AppError> {
+        let mut purged = Vec::new();
+        for entry in self.list()? {
+            match std::fs::remove_file(&entry.trashed_path) {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => {
+                    log::error!("Failed to purge {} from trash: {}", entry.trashed_path, e);
+                    continue;
+                }
+            }
+            self.remove_entry_dir(&entry.id);
+            self.record(TrashAction::Purge, &entry)?;
+            purged.push(entry);
+        }
+        log::info!("Emptied trash, {} files purged", purged.len());
+        Ok(purged)
+    }
+
+    fn find(&self, id: &str) -> Result<TrashEntry, AppError> {
+        self.list()?
+            .into_iter()
+            .find(|entry| entry.id == id)
+            .ok_or_else(|| AppError::NotFound(format!("Trash entry {} not found", id)))
+    }
+
+    /// Moves `from` to `to` and journals it, moving the file back if the journal cannot be written
+    /// so the trash never holds a file it has no record of, or loses track of one it restored.
+    fn move_and_record(&self, from: &Path, to: &Path, action: TrashAction, entry: &TrashEntry) -> Result<(), AppError> {
+        move_file(from, to)?;
+        if let Err(e) = self.record(action, entry) {
+            log::error!("Failed to journal {:?} of {:?}, moving it back: {}", action, from, e);
+            if let Err(rollback) = move_file(to, from) {
+                log::error!("Failed to move {:?} back to {:?}: {}", to, from, rollback);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn record(&self, action: TrashAction, entry: &TrashEntry) -> Result<(), AppError> {
+        let _guard = self.journal_lock.lock().unwrap();
+        let record = JournalRecord {
+            action,
+            at: now(),
+            entry: entry.clone(),
+        };
+        let mut journal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.directory.join(JOURNAL_FILE))?;
+        writeln!(journal, "{}", serde_json::to_string(&record)?)?;
+        journal.sync_data()?;
+        Ok(())
+    }
+
+    fn replay(&self) -> Result<Vec<TrashEntry>, AppError> {
+        let journal_path = self.directory.join(JOURNAL_FILE);
+        if !journal_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let mut order = Vec::new();
+        let mut current: HashMap<String, TrashEntry> = HashMap::new();
+        for line in BufReader::new(std::fs::File::open(journal_path)?).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let record: JournalRecord = match serde_json::from_str(&line) {
+                Ok(record) => record,
+                Err(e) => {
+                    log::warn!("Skipping unreadable trash journal line: {}", e);
+                    continue;
+                }
+            };
+            match record.action {
+                TrashAction::Trash => {
+                    order.push(record.entry.id.clone());
+                    current.insert(record.entry.id.clone(), record.entry);
+                }
+                TrashAction::Restore | TrashAction::Purge => {
+                    current.remove(&record.entry.id);
+                }
+            }
+        }
+        Ok(order.into_iter().filter_map(|id| current.remove(&id)).collect())
+    }
+
+    fn remove_entry_dir(&self, id: &str) {
+        let entry_dir = self.directory.join(FILES_DIR).join(id);
+        if let Err(e) = std::fs::remove_dir_all(&entry_dir) {
+            log::debug!("Could not remove trash entry directory {:?}: {}", entry_dir, e);
+        }
+    }
+}
+
+/// Strips the root and any prefix so an absolute path can be nested below another directory.
+fn relative_to_root(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect()
+}
+
+/// Renames `from` to `to`, falling back to copy and delete when they are on different filesystems.
+fn move_file(from: &Path, to: &Path) -> Result<(), AppError> {
+    if let Some(parent) = to.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if std::fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    std::fs::copy(from, to)?;
+    if let Err(e) = std::fs::remove_file(from) {
+        let _ = std::fs::remove_file(to);
+        return Err(AppError::Io(e));
+    }
+    Ok(())
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_file_stays_in_place_when_the_journal_cannot_be_written() {
+        let dir = std::env::temp_dir().join(format!("trash-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let trash = Trash::new(&dir.join("trash").to_string_lossy()).unwrap();
+        // A directory where the journal should be makes every append fail.
+        std::fs::create_dir_all(dir.join("trash").join(JOURNAL_FILE)).unwrap();
+        let photo = dir.join("photo.jpg");
+        std::fs::write(&photo, b"jpeg").unwrap();
+
+        assert!(trash.move_to_trash(&photo.to_string_lossy(), "hash").is_err());
+        assert_eq!(std::fs::read(&photo).unwrap(), b"jpeg");
+        assert_eq!(std::fs::read_dir(dir.join("trash").join(FILES_DIR)).unwrap().count(), 0);
+    }
+}
@@ -1,19 +1,100 @@
 use actix_files::NamedFile;
-use actix_web::{web, App, HttpResponse, HttpServer};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
 use std::path::{PathBuf, Path};
 use std::sync::Arc;
+use tokio::sync::Semaphore;
+use crate::albums::{AlbumStore, AlbumSummary};
+use crate::analyzers::AnalyzerRegistry;
+use crate::annotations::{AnnotationPatch, AnnotationStore, Annotations};
+use crate::bursts;
+use crate::colors::ColorInfo;
 use crate::config::AppConfig;
+use crate::dedup::{self, DuplicateFilter, DuplicateGroup, KeepPolicy, LinkRequest, ResolveRequest};
 use crate::error::AppError;
-use crate::search::Searcher;
+use crate::folders::{self, FolderSummary};
+use crate::geo::{self, BoundingBox};
+use crate::iptc::IptcMetadata;
+use crate::jobs::JobRegistry;
+use crate::metadata::{FileCopy, ImageMetadata};
+use crate::places::{Gazetteer, Place};
+use crate::processor;
+use crate::quality::QualityMetrics;
+use crate::render::{RenderParams, Renderer, Rendition};
+use crate::reprocess::{self, ReprocessRequest};
+use crate::search::{SearchRequest, Searcher, SortField, SortOrder};
+use crate::similarity::{Features, SimilarityIndex};
+use crate::thumbnails;
+use crate::trash::Trash;
+use crate::xmp::{self, XmpMetadata};
 
-async fn read_file_bytes(path: &Path) -> std::io::Result<Vec<u8>> {
-    tokio::fs::read(path).await
+const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Rejects anything that is not a hex SHA-256 digest before it is used to build a path.
+fn validate_hash(hash: &str) -> Result<(), AppError> {
+    if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(AppError::NotFound(format!("Invalid image hash: {}", hash)))
+    }
+}
+
+async fn find_image(searcher: &Arc<dyn Searcher>, hash: &str) -> Result<ImageMetadata, AppError> {
+    searcher
+        .get_by_hash(hash)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("Image with hash {} not found", hash)))
+}
+
+/// Returns the first copy of the image that still exists on disk.
+fn existing_copy(metadata: &ImageMetadata) -> Result<PathBuf, AppError> {
+    metadata
+        .paths()
+        .map(PathBuf::from)
+        .find(|p| p.exists())
+        .ok_or_else(|| AppError::NotFound(format!("No source file left for image with hash {}", metadata.file_hash)))
+}
+
+fn etag_for(hash: &str) -> String {
+    format!("\"{}\"", hash)
+}
+
+fn etag_matches(req: &HttpRequest, etag: &str) -> bool {
+    req.headers()
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| {
+            value
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag)
+        })
+        .unwrap_or(false)
+}
+
+fn not_modified(etag: &str) -> HttpResponse {
+    with_cache_headers(HttpResponse::NotModified().finish(), etag)
+}
+
+/// Content is addressed by its hash, so responses never change and can be cached forever.
+fn with_cache_headers(mut response: HttpResponse, etag: &str) -> HttpResponse {
+    let headers = response.headers_mut();
+    if let Ok(value) = HeaderValue::from_str(etag) {
+        headers.insert(header::ETAG, value);
+    }
+    headers.insert(header::CACHE_CONTROL, HeaderValue::from_static(IMMUTABLE_CACHE_CONTROL));
+    response
 }
 
 #[derive(Deserialize)]
 pub struct DeleteDuplicatesRequest {
     mode: String, // "all" or "keep-one"
+    #[serde(default)]
+    keep: KeepPolicy,
+    #[serde(default)]
+    dry_run: bool,
 }
 
 #[derive(Deserialize)]
@@ -31,6 +112,10 @@ async fn start_indexing_handler(
     payload: web::Json<StartIndexingRequest>,
     app_config: web::Data<Arc<AppConfig>>,
     searcher_data: web::Data<Arc<dyn Searcher>>,
+    annotations: web::Data<AnnotationStore>,
+    analyzers: web::Data<AnalyzerRegistry>,
+    jobs: web::Data<JobRegistry>,
+    similarity: web::Data<SimilarityIndex>,
 ) -> Result<HttpResponse, AppError> {
     log::info!("Received request to start indexing for path: {}", &payload.scan_directory);
 
@@ -40,11 +125,114 @@ async fn start_indexing_handler(
     let mut config_for_job: AppConfig = app_config.as_ref().as_ref().clone();
     config_for_job.scan_directory = payload.scan_directory.clone();
     config_for_job.num_workers = payload.num_workers.unwrap_or(app_config.num_workers);
-    crate::start_indexing_job(config_for_job, searcher_data.get_ref().clone());
+    crate::start_indexing_job(
+        config_for_job,
+        searcher_data.get_ref().clone(),
+        annotations.into_inner(),
+        analyzers.into_inner(),
+        jobs.into_inner(),
+        similarity.into_inner(),
+    );
 
     Ok(HttpResponse::Accepted().json(serde_json::json!({ "status": "indexing_started" })))
 }
 
+async fn regroup_bursts(
+    app_config: web::Data<Arc<AppConfig>>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    jobs: web::Data<JobRegistry>,
+) -> Result<HttpResponse, AppError> {
+    log::info!("Received request to regroup bursts.");
+
+    let job_id = jobs.start("group-bursts");
+    let searcher = searcher_data.get_ref().clone();
+    let config = app_config.get_ref().clone();
+    let jobs = jobs.into_inner();
+    let id = job_id.clone();
+    tokio::spawn(async move {
+        match bursts::regroup(&searcher, &config, &jobs, &id).await {
+            Ok(report) => match serde_json::to_value(&report) {
+                Ok(report) => jobs.complete(&id, report),
+                Err(e) => jobs.fail(&id, e.to_string()),
+            },
+            Err(e) => jobs.fail(&id, e.to_string()),
+        }
+    });
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({ "status": "started", "job_id": job_id })))
+}
+
+
+async fn list_analyzers(analyzers: web::Data<AnalyzerRegistry>) -> Result<HttpResponse, AppError> {
+    log::debug!("Received request to list analyzers.");
+    Ok(HttpResponse::Ok().json(analyzers.list()))
+}
+
+async fn rerun_analyzer(
+    path: web::Path<String>,
+    app_config: web::Data<Arc<AppConfig>>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    analyzers: web::Data<AnalyzerRegistry>,
+    similarity: web::Data<SimilarityIndex>,
+    jobs: web::Data<JobRegistry>,
+) -> Result<HttpResponse, AppError> {
+    let name = path.into_inner();
+    log::info!("Received request to re-run analyzer {}.", name);
+    let request = ReprocessRequest {
+        analyzers: [name].into(),
+        filter: None,
+    };
+    start_reprocessing(request, app_config, searcher_data, analyzers, similarity, jobs)
+}
+
+/// Reprocesses outdated images, or re-runs the analyzers in the body, on every image or on the
+/// images matching the `/api/images` filters in `filter`.
+async fn reprocess_images(
+    body: Option<web::Json<ReprocessRequest>>,
+    app_config: web::Data<Arc<AppConfig>>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    albums: web::Data<AlbumStore>,
+    analyzers: web::Data<AnalyzerRegistry>,
+    similarity: web::Data<SimilarityIndex>,
+    jobs: web::Data<JobRegistry>,
+) -> Result<HttpResponse, AppError> {
+    let mut request = body.map(|b| b.into_inner()).unwrap_or_default();
+    log::info!("Received request to reprocess images: {:?}", request);
+    if let Some(filter) = request.filter.as_mut() {
+        resolve_album(&albums, filter)?;
+    }
+    start_reprocessing(request, app_config, searcher_data, analyzers, similarity, jobs)
+}
+
+fn start_reprocessing(
+    request: ReprocessRequest,
+    app_config: web::Data<Arc<AppConfig>>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    analyzers: web::Data<AnalyzerRegistry>,
+    similarity: web::Data<SimilarityIndex>,
+    jobs: web::Data<JobRegistry>,
+) -> Result<HttpResponse, AppError> {
+    analyzers.check_rerunnable(&request.analyzers)?;
+
+    let job_id = jobs.start("reprocess");
+    let searcher = searcher_data.get_ref().clone();
+    let config = app_config.get_ref().clone();
+    let analyzers = analyzers.into_inner();
+    let similarity = similarity.into_inner();
+    let jobs = jobs.into_inner();
+    let id = job_id.clone();
+    tokio::spawn(async move {
+        match reprocess::reprocess(&searcher, &config, &analyzers, &similarity, &jobs, &id, request).await {
+            Ok(report) => match serde_json::to_value(&report) {
+                Ok(report) => jobs.complete(&id, report),
+                Err(e) => jobs.fail(&id, e.to_string()),
+            },
+            Err(e) => jobs.fail(&id, e.to_string()),
+        }
+    });
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({ "status": "started", "job_id": job_id })))
+}
 
 async fn get_status(
     searcher_data: web::Data<Arc<dyn Searcher>>,
@@ -57,52 +245,432 @@ async fn get_status(
     Ok(HttpResponse::Ok().json(status))
 }
 
+async fn get_stats(
+    app_config: web::Data<Arc<AppConfig>>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+) -> Result<HttpResponse, AppError> {
+    log::debug!("Received request for library statistics.");
+    let mut stats = searcher_data.compute_stats().await?;
+    let thumbnail_dir = PathBuf::from(&app_config.thumbnail_directory);
+    let (count, bytes) = tokio::task::spawn_blocking(move || thumbnails::cache_usage(&thumbnail_dir)).await?;
+    stats.thumbnail_count = count;
+    stats.thumbnail_bytes = bytes;
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+#[derive(Deserialize)]
+pub struct FoldersQuery {
+    root: Option<String>,
+}
+
+async fn get_folders(
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    query: web::Query<FoldersQuery>,
+) -> Result<HttpResponse, AppError> {
+    log::debug!("Received request for the folder tree below {:?}", query.root);
+    let counts = searcher_data.compute_folders().await?;
+    match counts.tree(query.root.as_deref()) {
+        Some(tree) => Ok(HttpResponse::Ok().json(tree)),
+        None => match &query.root {
+            Some(root) => Err(AppError::NotFound(format!("No indexed images below {}", root))),
+            None => Ok(HttpResponse::Ok().json(serde_json::Value::Null)),
+        },
+    }
+}
+
+#[derive(Deserialize)]
+pub struct BrowseQuery {
+    path: Option<String>,
+    #[serde(default)]
+    sort: SortField,
+    #[serde(default)]
+    order: SortOrder,
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+}
+
+#[derive(Serialize)]
+struct BrowseResponse {
+    path: String,
+    parent: Option<String>,
+    folders: Vec<FolderSummary>,
+    images: Vec<WebImage>,
+    total_images: u64,
+    limit: usize,
+    offset: usize,
+}
+
+async fn browse(
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    query: web::Query<BrowseQuery>,
+) -> Result<HttpResponse, AppError> {
+    log::debug!("Received request to browse {:?}", query.path);
+    let counts = searcher_data.compute_folders().await?;
+    let path = match query.path.as_deref().filter(|p| !p.is_empty()) {
+        Some(path) => folders::normalize_folder(path),
+        None => counts.root().unwrap_or_else(|| "/".to_string()),
+    };
+    if query.path.is_some() && !counts.contains(&path) {
+        return Err(AppError::NotFound(format!("No indexed images in {}", path)));
+    }
+
+    let request = SearchRequest {
+        folder: Some(path.clone()),
+        sort: query.sort,
+        order: query.order,
+        limit: query.limit.unwrap_or(SearchRequest::default().limit),
+        offset: query.offset,
+        ..SearchRequest::default()
+    };
+    let images = searcher_data.search_images(&request).await?;
+
+    Ok(HttpResponse::Ok().json(BrowseResponse {
+        parent: folders::parent_folder(&path).filter(|p| *p != path),
+        folders: counts.subfolders(&path),
+        images: images.into_iter().map(WebImage::from).collect(),
+        total_images: counts.image_count(&path),
+        limit: request.limit,
+        offset: request.offset,
+        path,
+    }))
+}
+
+#[derive(Deserialize)]
+pub struct AlbumNameRequest {
+    name: String,
+}
+
+#[derive(Deserialize)]
+pub struct AlbumImagesRequest {
+    hashes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PageQuery {
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: usize,
+}
+
+async fn list_albums(albums: web::Data<AlbumStore>) -> Result<HttpResponse, AppError> {
+    log::debug!("Received request to list albums.");
+    Ok(HttpResponse::Ok().json(albums.list()))
+}
+
+async fn create_album(
+    albums: web::Data<AlbumStore>,
+    payload: web::Json<AlbumNameRequest>,
+) -> Result<HttpResponse, AppError> {
+    log::info!("Received request to create album: {}", payload.name);
+    let album = albums.create(&payload.name)?;
+    Ok(HttpResponse::Created().json(AlbumSummary::from(&album)))
+}
+
+async fn get_album(path: web::Path<String>, albums: web::Data<AlbumStore>) -> Result<HttpResponse, AppError> {
+    let album = albums.get(&path.into_inner())?;
+    Ok(HttpResponse::Ok().json(AlbumSummary::from(&album)))
+}
+
+async fn rename_album(
+    path: web::Path<String>,
+    albums: web::Data<AlbumStore>,
+    payload: web::Json<AlbumNameRequest>,
+) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+    log::info!("Received request to rename album {} to {}", id, payload.name);
+    let album = albums.rename(&id, &payload.name)?;
+    Ok(HttpResponse::Ok().json(AlbumSummary::from(&album)))
+}
+
+async fn delete_album(path: web::Path<String>, albums: web::Data<AlbumStore>) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+    log::info!("Received request to delete album {}", id);
+    let album = albums.delete(&id)?;
+    Ok(HttpResponse::Ok().json(AlbumSummary::from(&album)))
+}
+
+/// Lists one page of an album in album order. Hashes no longer in the index are reported in `missing`.
+async fn get_album_images(
+    path: web::Path<String>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    albums: web::Data<AlbumStore>,
+    query: web::Query<PageQuery>,
+) -> Result<HttpResponse, AppError> {
+    let album = albums.get(&path.into_inner())?;
+    let limit = query.limit.unwrap_or(SearchRequest::default().limit);
+
+    let mut images = Vec::new();
+    let mut missing = Vec::new();
+    for hash in album.images.iter().skip(query.offset).take(limit) {
+        match searcher_data.get_by_hash(hash).await? {
+            Some(metadata) => images.push(WebImage::from(metadata)),
+            None => missing.push(hash.clone()),
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "album": AlbumSummary::from(&album),
+        "images": images,
+        "missing": missing,
+        "total_images": album.images.len(),
+        "limit": limit,
+        "offset": query.offset,
+    })))
+}
+
+/// Adds images to an album. Hashes that are not in the index are skipped and reported in `not_found`.
+async fn add_album_images(
+    path: web::Path<String>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    albums: web::Data<AlbumStore>,
+    payload: web::Json<AlbumImagesRequest>,
+) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+    albums.get(&id)?;
+
+    let mut known = Vec::new();
+    let mut not_found = Vec::new();
+    for hash in &payload.hashes {
+        let indexed = validate_hash(hash).is_ok() && searcher_data.get_by_hash(hash).await?.is_some();
+        if indexed {
+            known.push(hash.clone());
+        } else {
+            not_found.push(hash.clone());
+        }
+    }
+
+    let (album, added) = albums.add_images(&id, &known)?;
+    log::info!("Added {} images to album {}", added.len(), id);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "album": AlbumSummary::from(&album),
+        "added": added,
+        "not_found": not_found,
+    })))
+}
+
+async fn remove_album_images(
+    path: web::Path<String>,
+    albums: web::Data<AlbumStore>,
+    payload: web::Json<AlbumImagesRequest>,
+) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+    let (album, removed) = albums.remove_images(&id, &payload.hashes)?;
+    log::info!("Removed {} images from album {}", removed.len(), id);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "album": AlbumSummary::from(&album),
+        "removed": removed,
+    })))
+}
+
 async fn delete_duplicates(
     path: web::Path<String>,
+    app_config: web::Data<Arc<AppConfig>>,
     searcher_data: web::Data<Arc<dyn Searcher>>,
+    trash: web::Data<Trash>,
     payload: web::Json<DeleteDuplicatesRequest>,
 ) -> Result<HttpResponse, AppError> {
     let hash = path.into_inner();
     log::info!("Received request to delete duplicates for hash: {} with mode: {}", &hash, &payload.mode);
 
     // 1. Find the document
-    let results = searcher_data.search_images(format!("\"{}\"", &hash)).await?;
-    let mut metadata = if let Some(meta) = results.into_iter().next() {
-        meta
-    } else {
-        return Err(AppError::NotFound(format!("Image with hash {} not found", &hash)));
-    };
+    let metadata = find_image(searcher_data.get_ref(), &hash).await?;
 
     // 2. Determine which files to delete
-    let mut files_to_delete: Vec<String> = Vec::new();
-    if payload.mode == "all" {
-        files_to_delete.push(metadata.file_path.clone());
-        files_to_delete.extend(metadata.duplicate_paths.clone());
+    let plan = if payload.mode == "all" {
+        dedup::plan_delete_all(&metadata)
     } else if payload.mode == "keep-one" {
-        files_to_delete.extend(metadata.duplicate_paths.clone());
+        dedup::plan_keep_one(&metadata, &payload.keep)?
     } else {
         return Ok(HttpResponse::BadRequest().body("Invalid mode. Use 'all' or 'keep-one'."));
+    };
+
+    if payload.dry_run {
+        log::info!("Dry run for hash {}: keep {:?}, delete {:?}", &hash, plan.keep, plan.delete);
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "dry_run", "plan": plan })));
     }
 
-    // 3. Delete the files
-    for file_path in &files_to_delete {
-        match std::fs::remove_file(file_path) {
-            Ok(_) => log::debug!("Deleted file: {}", file_path),
-            Err(e) => log::error!("Failed to delete file {}: {}", file_path, e), // Log error but continue
-        }
+    // 3. Move the files into the trash and update the index
+    let thumbnail_dir = Path::new(&app_config.thumbnail_directory);
+    let outcome = dedup::apply_plan(searcher_data.get_ref(), &trash.into_inner(), thumbnail_dir, metadata, &plan).await?;
+
+    let deleted_files: Vec<&str> = outcome.trashed.iter().map(|entry| entry.original_path.as_str()).collect();
+    let status = if outcome.failed.is_empty() { "success" } else { "partial" };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": status,
+        "plan": plan,
+        "deleted_files": deleted_files,
+        "trashed": outcome.trashed,
+        "failed": outcome.failed,
+    })))
+}
+
+async fn link_duplicates(
+    path: web::Path<String>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    payload: web::Json<LinkRequest>,
+) -> Result<HttpResponse, AppError> {
+    let hash = path.into_inner();
+    log::info!("Received request to link duplicates for hash: {} with {:?}", &hash, payload);
+
+    let metadata = find_image(searcher_data.get_ref(), &hash).await?;
+    let mut plan = dedup::plan_keep_one(&metadata, &payload.keep)?;
+    // Copies that are already linked to the kept file need no work.
+    if plan.keep.as_ref() == Some(&metadata.file_path) {
+        plan.delete.retain(|p| !metadata.linked_paths.contains(p));
     }
 
-    // 4. Update the index
-    if payload.mode == "all" {
-        searcher_data.delete_document(&hash).await?;
-        log::info!("Deleted document from index for hash: {}", &hash);
-    } else if payload.mode == "keep-one" {
-        metadata.duplicate_paths.clear();
-        searcher_data.update_document(metadata).await?;
-        log::info!("Updated document in index for hash: {}", &hash);
+    if payload.dry_run {
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "dry_run", "plan": plan })));
     }
 
-    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "success", "deleted_files": files_to_delete })))
+    let outcome = dedup::apply_link_plan(searcher_data.get_ref(), metadata, &plan, payload.method).await?;
+    let status = if outcome.failed.is_empty() { "success" } else { "partial" };
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": status,
+        "plan": plan,
+        "linked": outcome.linked,
+        "failed": outcome.failed,
+    })))
+}
+
+#[derive(Deserialize)]
+pub struct DuplicatesQuery {
+    path_prefix: Option<String>,
+    limit: Option<usize>,
+    offset: Option<usize>,
+}
+
+async fn list_duplicates(
+    query: web::Query<DuplicatesQuery>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+) -> Result<HttpResponse, AppError> {
+    log::debug!("Received request to list duplicate groups under {:?}", query.path_prefix);
+    let filter = DuplicateFilter {
+        path_prefix: query.path_prefix.clone(),
+    };
+    let metadata = searcher_data.get_duplicate_groups().await?;
+
+    let mut groups: Vec<DuplicateGroup> = tokio::task::spawn_blocking(move || {
+        metadata
+            .into_iter()
+            .filter(|m| filter.matches(m))
+            .map(DuplicateGroup::from_metadata)
+            .collect()
+    })
+    .await?;
+    groups.sort_by_key(|g| std::cmp::Reverse(g.wasted_bytes));
+
+    let total_groups = groups.len();
+    let total_wasted_bytes: u64 = groups.iter().map(|g| g.wasted_bytes).sum();
+    let groups: Vec<DuplicateGroup> = groups
+        .into_iter()
+        .skip(query.offset.unwrap_or(0))
+        .take(query.limit.unwrap_or(100))
+        .collect();
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "total_groups": total_groups,
+        "total_wasted_bytes": total_wasted_bytes,
+        "groups": groups,
+    })))
+}
+
+async fn resolve_duplicates(
+    payload: web::Json<ResolveRequest>,
+    app_config: web::Data<Arc<AppConfig>>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    trash: web::Data<Trash>,
+    jobs: web::Data<JobRegistry>,
+) -> Result<HttpResponse, AppError> {
+    let request = payload.into_inner();
+    log::info!("Received request to resolve duplicates: {:?}", request);
+
+    let job_id = jobs.start("resolve-duplicates");
+    let searcher = searcher_data.get_ref().clone();
+    let trash = trash.into_inner();
+    let jobs = jobs.into_inner();
+    let thumbnail_dir = PathBuf::from(&app_config.thumbnail_directory);
+    let id = job_id.clone();
+    tokio::spawn(async move {
+        match dedup::resolve_all(&searcher, &trash, &thumbnail_dir, &jobs, &id, &request).await {
+            Ok(report) => match serde_json::to_value(&report) {
+                Ok(report) => jobs.complete(&id, report),
+                Err(e) => jobs.fail(&id, e.to_string()),
+            },
+            Err(e) => jobs.fail(&id, e.to_string()),
+        }
+    });
+
+    Ok(HttpResponse::Accepted().json(serde_json::json!({ "status": "started", "job_id": job_id })))
+}
+
+async fn list_jobs(jobs: web::Data<JobRegistry>) -> Result<HttpResponse, AppError> {
+    Ok(HttpResponse::Ok().json(jobs.list()))
+}
+
+async fn get_job(path: web::Path<String>, jobs: web::Data<JobRegistry>) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+    let job = jobs
+        .get(&id)
+        .ok_or_else(|| AppError::NotFound(format!("Job {} not found", id)))?;
+    Ok(HttpResponse::Ok().json(job))
+}
+
+async fn get_job_report(path: web::Path<String>, jobs: web::Data<JobRegistry>) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+    let report = jobs
+        .get(&id)
+        .ok_or_else(|| AppError::NotFound(format!("Job {} not found", id)))?
+        .report
+        .ok_or_else(|| AppError::NotFound(format!("Job {} has no report yet", id)))?;
+    Ok(HttpResponse::Ok()
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}-report.json\"", id),
+        ))
+        .json(report))
+}
+
+async fn list_trash(trash: web::Data<Trash>) -> Result<HttpResponse, AppError> {
+    log::debug!("Received request to list trash.");
+    Ok(HttpResponse::Ok().json(trash.list()?))
+}
+
+async fn restore_from_trash(
+    path: web::Path<String>,
+    app_config: web::Data<Arc<AppConfig>>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    trash: web::Data<Trash>,
+    annotations: web::Data<AnnotationStore>,
+    analyzers: web::Data<AnalyzerRegistry>,
+    similarity: web::Data<SimilarityIndex>,
+) -> Result<HttpResponse, AppError> {
+    let id = path.into_inner();
+    log::info!("Received request to restore trash entry: {}", id);
+    let entry = trash.restore(&id)?;
+
+    // Re-extract the restored file so it is indexed again, as a new image or as a duplicate.
+    let config = app_config.get_ref().clone();
+    let restored_path = PathBuf::from(&entry.original_path);
+    let metadata = tokio::task::spawn_blocking(move || {
+        processor::process_image(&config, &analyzers, &restored_path, &HashSet::new())
+    })
+    .await??;
+    if let Some(mut metadata) = metadata {
+        metadata.annotations = annotations.get(&metadata.file_hash);
+        similarity.insert(&metadata);
+        searcher_data.index_metadata(metadata).await?;
+        similarity.save()?;
+    }
+
+    Ok(HttpResponse::Ok().json(entry))
+}
+
+async fn empty_trash(trash: web::Data<Trash>) -> Result<HttpResponse, AppError> {
+    log::info!("Received request to empty trash.");
+    let purged = trash.empty()?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "success", "purged": purged })))
 }
 
 #[derive(Serialize, Debug)]
@@ -116,8 +684,63 @@ struct WebImage {
     date_taken: Option<String>,
     gps_latitude: Option<f64>,
     gps_longitude: Option<f64>,
+    has_exif: bool,
+    file_size: u64,
+    modified_at: Option<i64>,
+    created_at: Option<i64>,
+    extension: String,
+    mime_type: String,
     thumbnail_path: String,
     duplicate_paths: Vec<String>,
+    copies: Vec<FileCopy>,
+    linked_paths: Vec<String>,
+    xmp: XmpMetadata,
+    iptc: IptcMetadata,
+    place: Option<Place>,
+    colors: Option<ColorInfo>,
+    perceptual_hash: Option<String>,
+    group_id: Option<String>,
+    best_pick: bool,
+    #[serde(flatten)]
+    quality: QualityMetrics,
+    #[serde(flatten)]
+    annotations: Annotations,
+}
+
+impl From<ImageMetadata> for WebImage {
+    fn from(m: ImageMetadata) -> Self {
+        let duplicate_paths = m.duplicate_paths();
+        Self {
+            file_path: m.file_path,
+            file_hash: m.file_hash,
+            width: m.width,
+            height: m.height,
+            camera_make: m.camera_make,
+            camera_model: m.camera_model,
+            date_taken: m.date_taken,
+            gps_latitude: m.gps_latitude,
+            gps_longitude: m.gps_longitude,
+            has_exif: m.has_exif,
+            file_size: m.file_size,
+            modified_at: m.modified_at,
+            created_at: m.created_at,
+            extension: m.extension,
+            mime_type: m.mime_type,
+            thumbnail_path: m.thumbnail_path,
+            duplicate_paths,
+            copies: m.copies,
+            linked_paths: m.linked_paths,
+            xmp: m.xmp,
+            iptc: m.iptc,
+            place: m.place,
+            colors: m.colors,
+            perceptual_hash: m.perceptual_hash,
+            group_id: m.group_id,
+            best_pick: m.best_pick,
+            quality: m.quality,
+            annotations: m.annotations,
+        }
+    }
 }
 
 async fn index() -> Result<NamedFile, AppError> {
@@ -129,85 +752,452 @@ async fn index() -> Result<NamedFile, AppError> {
 
 async fn get_images(
     searcher_data: web::Data<Arc<dyn Searcher>>,
-    query: web::Query<std::collections::HashMap<String, String>>, // For future filtering
+    albums: web::Data<AlbumStore>,
+    query: web::Query<SearchRequest>,
 ) -> Result<HttpResponse, AppError> {
     log::debug!("Received request for images with query: {:?}", query);
-    
-    let search_query = query.get("q").cloned().unwrap_or_default();
-    let metadata_results = searcher_data.search_images(search_query).await?;
 
-    let web_images: Vec<WebImage> = metadata_results
-        .into_iter()
-        .map(|m| WebImage {
-            file_path: m.file_path,
-            file_hash: m.file_hash,
-            width: m.width,
-            height: m.height,
-            camera_make: m.camera_make,
-            camera_model: m.camera_model,
-            date_taken: m.date_taken,
-            gps_latitude: m.gps_latitude,
-            gps_longitude: m.gps_longitude,
-            thumbnail_path: m.thumbnail_path,
-            duplicate_paths: m.duplicate_paths,
-        })
-        .collect();
+    let mut request = query.into_inner();
+    resolve_album(&albums, &mut request)?;
+    let metadata_results = searcher_data.search_images(&request).await?;
+
+    let web_images: Vec<WebImage> = metadata_results.into_iter().map(WebImage::from).collect();
 
     Ok(HttpResponse::Ok().json(web_images))
 }
 
+#[derive(Deserialize)]
+pub struct PathLookupQuery {
+    path: String,
+}
+
+async fn get_image_metadata(
+    path: web::Path<String>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+) -> Result<HttpResponse, AppError> {
+    let hash = path.into_inner();
+    log::debug!("Received request for metadata of image with hash: {}", hash);
+    let metadata = find_image(searcher_data.get_ref(), &hash).await?;
+    Ok(HttpResponse::Ok().json(metadata))
+}
+
+/// Turns an album filter into the list of hashes the backends filter on.
+fn resolve_album(albums: &AlbumStore, request: &mut SearchRequest) -> Result<(), AppError> {
+    if let Some(album) = request.take_album() {
+        let album = albums
+            .find(&album)
+            .ok_or_else(|| AppError::NotFound(format!("Album {} not found", album)))?;
+        request.hashes = Some(album.images);
+    }
+    Ok(())
+}
+
+async fn get_facets(
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    albums: web::Data<AlbumStore>,
+    query: web::Query<SearchRequest>,
+) -> Result<HttpResponse, AppError> {
+    log::debug!("Received request for facets with query: {:?}", query);
+    let mut request = query.into_inner();
+    resolve_album(&albums, &mut request)?;
+    Ok(HttpResponse::Ok().json(searcher_data.compute_facets(&request).await?))
+}
+
+/// Groups the images matching the `/api/images` filters by country, region and city.
+async fn get_places(
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    albums: web::Data<AlbumStore>,
+    query: web::Query<SearchRequest>,
+) -> Result<HttpResponse, AppError> {
+    log::debug!("Received request for places with query: {:?}", query);
+    let mut request = query.into_inner();
+    resolve_album(&albums, &mut request)?;
+    Ok(HttpResponse::Ok().json(searcher_data.compute_places(&request).await?.tree()))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GeoClusterQuery {
+    /// `west,south,east,north`; the whole world if omitted.
+    bbox: Option<String>,
+    #[serde(default)]
+    zoom: u32,
+}
+
+/// Clusters geotagged images for a map view. The `/api/images` filters can be combined with `bbox` and `zoom`.
+async fn get_geo_clusters(
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    albums: web::Data<AlbumStore>,
+    query: web::Query<GeoClusterQuery>,
+    filters: web::Query<SearchRequest>,
+) -> Result<HttpResponse, AppError> {
+    log::debug!("Received request for geo clusters: {:?}", query);
+    let bbox = match &query.bbox {
+        Some(bbox) => BoundingBox::parse(bbox)?,
+        None => BoundingBox::WORLD,
+    };
+    let mut request = filters.into_inner();
+    resolve_album(&albums, &mut request)?;
+    let clusters = searcher_data
+        .compute_geo_clusters(&request, bbox, geo::precision_for_zoom(query.zoom))
+        .await?;
+    Ok(HttpResponse::Ok().json(clusters))
+}
+
+#[derive(Deserialize, Debug)]
+pub struct SimilarQuery {
+    #[serde(default = "default_similar_limit")]
+    limit: usize,
+    /// From 0 (identical) to 1; everything if omitted.
+    max_distance: Option<f64>,
+}
+
+fn default_similar_limit() -> usize {
+    20
+}
+
+#[derive(Serialize)]
+struct SimilarImage {
+    distance: f64,
+    #[serde(flatten)]
+    image: WebImage,
+}
+
+/// Ranks the other images by color histogram and perceptual hash distance to the given one.
+async fn get_similar_images(
+    path: web::Path<String>,
+    query: web::Query<SimilarQuery>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    similarity: web::Data<SimilarityIndex>,
+) -> Result<HttpResponse, AppError> {
+    let hash = path.into_inner();
+    log::debug!("Received request for images similar to {}: {:?}", hash, query);
+    let target = find_image(searcher_data.get_ref(), &hash).await?;
+    let features = similarity
+        .get(&hash)
+        .or_else(|| Features::from_metadata(&target))
+        .ok_or_else(|| {
+            AppError::BadRequest(format!("Image {} has no similarity features yet; reindex it first", hash))
+        })?;
+
+    let mut results = Vec::new();
+    for (candidate, distance) in similarity.nearest(&features, &hash, query.max_distance.unwrap_or(1.0)) {
+        if results.len() >= query.limit {
+            break;
+        }
+        match searcher_data.get_by_hash(&candidate).await? {
+            Some(metadata) => results.push(SimilarImage {
+                distance,
+                image: WebImage::from(metadata),
+            }),
+            // Deleted since it was added to the similarity index.
+            None => similarity.remove(&candidate),
+        }
+    }
+    Ok(HttpResponse::Ok().json(results))
+}
+
+#[derive(Deserialize)]
+pub struct BulkAnnotationRequest {
+    hashes: Vec<String>,
+    #[serde(flatten)]
+    patch: AnnotationPatch,
+}
+
+async fn get_annotations(
+    path: web::Path<String>,
+    annotations: web::Data<AnnotationStore>,
+) -> Result<HttpResponse, AppError> {
+    let hash = path.into_inner();
+    validate_hash(&hash)?;
+    Ok(HttpResponse::Ok().json(annotations.get(&hash)))
+}
+
+/// Mirrors annotation changes into the `.xmp` sidecar of every copy, if enabled. Each image comes with
+/// its annotations from before the change. Failures are only logged. The stored XMP is then re-read
+/// from the indexed copy so it matches the sidecars.
+async fn write_xmp_sidecars(
+    app_config: &AppConfig,
+    searcher: &Arc<dyn Searcher>,
+    images: Vec<(Annotations, ImageMetadata)>,
+) -> Result<(), AppError> {
+    if !app_config.xmp_write_back {
+        return Ok(());
+    }
+    let refreshed: Vec<(String, XmpMetadata)> = tokio::task::spawn_blocking(move || {
+        images
+            .into_iter()
+            .filter_map(|(before, metadata)| {
+                for path in metadata.paths().filter(|p| Path::new(p).is_file()) {
+                    if let Err(e) = xmp::write_sidecar(Path::new(path), &before, &metadata.annotations) {
+                        log::warn!("Could not write XMP sidecar for {}: {}", path, e);
+                    }
+                }
+                let xmp = xmp::read_for_image(Path::new(&metadata.file_path));
+                (xmp != metadata.xmp).then_some((metadata.file_hash, xmp))
+            })
+            .collect()
+    })
+    .await?;
+
+    for (hash, xmp) in refreshed {
+        // Re-read the document so edits made while the sidecars were written are not overwritten.
+        let Some(mut metadata) = searcher.get_by_hash(&hash).await? else { continue };
+        metadata.xmp = xmp;
+        searcher.update_document(metadata).await?;
+    }
+    Ok(())
+}
+
+async fn update_annotations(
+    path: web::Path<String>,
+    app_config: web::Data<Arc<AppConfig>>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    annotations: web::Data<AnnotationStore>,
+    payload: web::Json<AnnotationPatch>,
+) -> Result<HttpResponse, AppError> {
+    let hash = path.into_inner();
+    validate_hash(&hash)?;
+    log::info!("Received request to update annotations of {}: {:?}", hash, payload);
+    let mut metadata = find_image(searcher_data.get_ref(), &hash).await?;
+
+    let updated = annotations.update(std::slice::from_ref(&hash), &payload)?;
+    let before = std::mem::replace(&mut metadata.annotations, updated.into_iter().next().unwrap_or_default());
+    let result = metadata.annotations.clone();
+    searcher_data.update_document(metadata.clone()).await?;
+    write_xmp_sidecars(&app_config, searcher_data.get_ref(), vec![(before, metadata)]).await?;
+    Ok(HttpResponse::Ok().json(result))
+}
+
+/// Applies the same change to many images. Hashes that are not indexed are skipped and reported in `not_found`.
+async fn bulk_update_annotations(
+    app_config: web::Data<Arc<AppConfig>>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    annotations: web::Data<AnnotationStore>,
+    payload: web::Json<BulkAnnotationRequest>,
+) -> Result<HttpResponse, AppError> {
+    log::info!("Received request to update annotations of {} images", payload.hashes.len());
+    payload.patch.validate()?;
+
+    let mut images = Vec::new();
+    let mut not_found = Vec::new();
+    for hash in &payload.hashes {
+        let metadata = match validate_hash(hash) {
+            Ok(()) => searcher_data.get_by_hash(hash).await?,
+            Err(_) => None,
+        };
+        match metadata {
+            Some(metadata) => images.push(metadata),
+            None => not_found.push(hash.clone()),
+        }
+    }
+
+    let hashes: Vec<String> = images.iter().map(|m| m.file_hash.clone()).collect();
+    let updated = annotations.update(&hashes, &payload.patch)?;
+    let mut changed = Vec::with_capacity(images.len());
+    for (mut metadata, new_annotations) in images.into_iter().zip(updated) {
+        let before = std::mem::replace(&mut metadata.annotations, new_annotations);
+        searcher_data.update_document(metadata.clone()).await?;
+        changed.push((before, metadata));
+    }
+    write_xmp_sidecars(&app_config, searcher_data.get_ref(), changed).await?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "updated": hashes,
+        "not_found": not_found,
+    })))
+}
+
+async fn get_image_by_path(
+    query: web::Query<PathLookupQuery>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+) -> Result<HttpResponse, AppError> {
+    log::debug!("Received request for metadata of image at path: {}", query.path);
+    let metadata = searcher_data
+        .get_by_path(&query.path)
+        .await?
+        .ok_or_else(|| AppError::NotFound(format!("No image indexed at path {}", query.path)))?;
+    Ok(HttpResponse::Ok().json(metadata))
+}
+
 async fn get_thumbnail(
+    req: HttpRequest,
     path: web::Path<String>,
     app_config: web::Data<Arc<AppConfig>>,
-) -> Result<NamedFile, AppError> {
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    regeneration_limit: web::Data<Semaphore>,
+) -> Result<HttpResponse, AppError> {
     let hash = path.into_inner();
     log::debug!("Received request for thumbnail with hash: {}", hash);
-    
-    let thumbnail_path: PathBuf = Path::new(&app_config.thumbnail_directory)
-        .join(format!("{}.jpg", hash));
-    
+    validate_hash(&hash)?;
+
+    let etag = etag_for(&hash);
+    if etag_matches(&req, &etag) {
+        return Ok(not_modified(&etag));
+    }
+
+    let thumbnail_path: PathBuf = thumbnails::thumbnail_path_for(Path::new(&app_config.thumbnail_directory), &hash);
+    if !thumbnail_path.exists() {
+        regenerate_thumbnail(&hash, &thumbnail_path, searcher_data.get_ref(), &regeneration_limit).await?;
+    }
+
     log::trace!("Attempting to serve thumbnail from: {:?}", thumbnail_path);
-    Ok(NamedFile::open_async(&thumbnail_path).await?)
+    let file = NamedFile::open_async(&thumbnail_path)
+        .await?
+        .use_etag(false)
+        .use_last_modified(false);
+    Ok(with_cache_headers(file.into_response(&req), &etag))
+}
+
+/// Rebuilds a missing thumbnail from the first copy of the source image that still exists.
+async fn regenerate_thumbnail(
+    hash: &str,
+    thumbnail_path: &Path,
+    searcher: &Arc<dyn Searcher>,
+    regeneration_limit: &Semaphore,
+) -> Result<(), AppError> {
+    let _permit = regeneration_limit
+        .acquire()
+        .await
+        .map_err(|e| AppError::Generic(e.to_string()))?;
+
+    // Another request may have generated it while we were waiting for a permit.
+    if thumbnail_path.exists() {
+        return Ok(());
+    }
+
+    let metadata = find_image(searcher, hash).await?;
+    let source = existing_copy(&metadata)?;
+
+    log::info!("Regenerating missing thumbnail for {} from {:?}", hash, source);
+    let thumbnail_path = thumbnail_path.to_path_buf();
+    tokio::task::spawn_blocking(move || thumbnails::generate_thumbnail(&source, &thumbnail_path)).await??;
+    Ok(())
+}
+
+async fn collect_thumbnail_garbage(
+    app_config: web::Data<Arc<AppConfig>>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+) -> Result<HttpResponse, AppError> {
+    log::info!("Received request to garbage collect orphaned thumbnails.");
+    let live_hashes = searcher_data.get_all_hashes().await?;
+    let thumbnail_dir = PathBuf::from(&app_config.thumbnail_directory);
+    let report = tokio::task::spawn_blocking(move || {
+        thumbnails::collect_garbage(&thumbnail_dir, &live_hashes)
+    })
+    .await??;
+    Ok(HttpResponse::Ok().json(report))
 }
 
 async fn get_full_image(
+    req: HttpRequest,
     path: web::Path<String>,
     searcher_data: web::Data<Arc<dyn Searcher>>,
 ) -> Result<HttpResponse, AppError> {
     let hash = path.into_inner();
     log::debug!("Received request for full image with hash: {}", hash);
 
-    let results = searcher_data.search_images(format!("\"{}\"", hash)).await?;
+    let etag = etag_for(&hash);
+    if etag_matches(&req, &etag) {
+        return Ok(not_modified(&etag));
+    }
 
-    if let Some(metadata) = results.into_iter().next() {
-        let file_path = PathBuf::from(metadata.file_path);
-        log::trace!("Attempting to read full image from: {:?}", file_path);
+    let metadata = find_image(searcher_data.get_ref(), &hash).await?;
+    let file_path = existing_copy(&metadata)?;
+    log::trace!("Streaming full image from: {:?}", file_path);
 
-        match read_file_bytes(&file_path).await {
-            Ok(bytes) => {
-                let mime_type = mime_guess::from_path(&file_path).first_or(mime::APPLICATION_OCTET_STREAM);
-                
-                Ok(HttpResponse::Ok()
-                    .content_type(mime_type.as_ref())
-                    .body(bytes))
-            }
-            Err(e) => {
-                log::error!("Failed to read file for full image at {:?}: {}", file_path, e);
-                Err(AppError::Io(e))
-            }
-        }
-    } else {
-        Err(AppError::NotFound(format!("Image with hash {} not found", hash)))
+    // NamedFile streams the file and handles Range requests for us.
+    let file = NamedFile::open_async(&file_path)
+        .await?
+        .use_etag(false)
+        .use_last_modified(false);
+    Ok(with_cache_headers(file.into_response(&req), &etag))
+}
+
+async fn render_image(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Query<RenderParams>,
+    searcher_data: web::Data<Arc<dyn Searcher>>,
+    renderer: web::Data<Renderer>,
+) -> Result<HttpResponse, AppError> {
+    let hash = path.into_inner();
+    let params = params.into_inner();
+    log::debug!("Received request to render image {} with {:?}", hash, params);
+    validate_hash(&hash)?;
+    params.validate()?;
+
+    let cache_key = params.cache_key(&hash);
+    let etag = format!("\"{}\"", cache_key);
+    if etag_matches(&req, &etag) {
+        return Ok(not_modified(&etag));
     }
+
+    let metadata = find_image(searcher_data.get_ref(), &hash).await?;
+    let source = existing_copy(&metadata)?;
+    let mime_type = params.format.mime_type();
+    let response = match renderer.render(&hash, source, params).await? {
+        Rendition::Cached(path) => NamedFile::open_async(&path)
+            .await?
+            .use_etag(false)
+            .use_last_modified(false)
+            .into_response(&req),
+        Rendition::Uncached(bytes) => HttpResponse::Ok().content_type(mime_type).body(bytes),
+    };
+    Ok(with_cache_headers(response, &etag))
 }
 
 
+/// Rebuilds the similarity index from the search index in the background when their image counts disagree,
+/// e.g. after the index was created by an older version or the similarity file was lost.
+fn sync_similarity_index(searcher: Arc<dyn Searcher>, similarity: Arc<SimilarityIndex>) {
+    tokio::spawn(async move {
+        let result = async {
+            if searcher.count_images().await? == similarity.len() as u64 {
+                return Ok(());
+            }
+            log::info!("Similarity index is out of date; rebuilding it from the search index.");
+            let images = searcher.get_all_images().await?;
+            similarity.rebuild(&images);
+            similarity.save()
+        }
+        .await;
+        if let Err(e) = result {
+            log::error!("Could not rebuild the similarity index: {}", e);
+        }
+    });
+}
+
 pub async fn start_web_server(
     config: Arc<AppConfig>,
     searcher: Arc<dyn Searcher>,
 ) -> std::io::Result<()> {
     let port = config.web_port;
-    let config_data = web::Data::from(config);
+    let regeneration_limit = web::Data::new(Semaphore::new(config.thumbnail_regeneration_limit.max(1)));
+    let renderer = web::Data::new(
+        Renderer::new(
+            &config.render_cache_directory,
+            config.render_cache_max_bytes,
+            config.render_concurrency_limit,
+        )
+        .map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
+    let trash = web::Data::new(
+        Trash::new(&config.trash_directory).map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
+    let albums = web::Data::new(
+        AlbumStore::new(&config.album_store_path).map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
+    let annotations = web::Data::new(
+        AnnotationStore::new(&config.annotation_store_path).map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
+    let gazetteer = Gazetteer::load(&config.gazetteer_path).map_err(|e| std::io::Error::other(e.to_string()))?;
+    let analyzers = web::Data::new(
+        AnalyzerRegistry::new(&config, Arc::new(gazetteer)).map_err(|e| std::io::Error::other(e.to_string()))?,
+    );
+    let jobs = web::Data::new(JobRegistry::new());
+    let similarity = web::Data::new(SimilarityIndex::load(&config.similarity_index_path));
+    sync_similarity_index(searcher.clone(), similarity.clone().into_inner());
+    let config_data = web::Data::new(config);
     let searcher_data = web::Data::new(searcher.clone());
 
     log::info!("Starting web server on port: {}", port);
@@ -217,15 +1207,85 @@ pub async fn start_web_server(
         App::new()
             .app_data(config_data.clone())
             .app_data(searcher_data.clone())
+            .app_data(regeneration_limit.clone())
+            .app_data(renderer.clone())
+            .app_data(trash.clone())
+            .app_data(jobs.clone())
+            .app_data(albums.clone())
+            .app_data(annotations.clone())
+            .app_data(analyzers.clone())
+            .app_data(similarity.clone())
             .service(actix_files::Files::new("/static", "./static").show_files_listing())
             .service(web::resource("/api/images").to(get_images))
             .service(web::resource("/api/status").to(get_status))
+            .service(web::resource("/api/stats").route(web::get().to(get_stats)))
+            .service(web::resource("/api/folders").route(web::get().to(get_folders)))
+            .service(web::resource("/api/browse").route(web::get().to(browse)))
+            .service(
+                web::resource("/api/albums")
+                    .route(web::get().to(list_albums))
+                    .route(web::post().to(create_album)),
+            )
+            .service(
+                web::resource("/api/albums/{id}")
+                    .route(web::get().to(get_album))
+                    .route(web::patch().to(rename_album))
+                    .route(web::delete().to(delete_album)),
+            )
+            .service(
+                web::resource("/api/albums/{id}/images")
+                    .route(web::get().to(get_album_images))
+                    .route(web::post().to(add_album_images))
+                    .route(web::delete().to(remove_album_images)),
+            )
+            .service(
+                web::resource("/api/thumbnails/gc")
+                    .route(web::post().to(collect_thumbnail_garbage)),
+            )
             .service(web::resource("/api/thumbnails/{hash}").to(get_thumbnail))
+            .service(web::resource("/api/images/by-path").route(web::get().to(get_image_by_path)))
+            .service(web::resource("/api/images/{hash}/metadata").route(web::get().to(get_image_metadata)))
+            .service(
+                web::resource("/api/images/{hash}/annotations")
+                    .route(web::get().to(get_annotations))
+                    .route(web::patch().to(update_annotations)),
+            )
+            .service(web::resource("/api/annotations").route(web::patch().to(bulk_update_annotations)))
+            .service(web::resource("/api/facets").route(web::get().to(get_facets)))
+            .service(web::resource("/api/places").route(web::get().to(get_places)))
+            .service(web::resource("/api/geo/clusters").route(web::get().to(get_geo_clusters)))
+            .service(web::resource("/api/images/{hash}/similar").route(web::get().to(get_similar_images)))
+            .service(web::resource("/api/images/{hash}/render").route(web::get().to(render_image)))
             .service(web::resource("/api/images/{hash}").to(get_full_image))
             .service(
                 web::resource("/api/images/{hash}/duplicates")
                     .route(web::delete().to(delete_duplicates)),
             )
+            .service(
+                web::resource("/api/images/{hash}/duplicates/link")
+                    .route(web::post().to(link_duplicates)),
+            )
+            .service(web::resource("/api/duplicates").route(web::get().to(list_duplicates)))
+            .service(
+                web::resource("/api/duplicates/resolve")
+                    .route(web::post().to(resolve_duplicates)),
+            )
+            .service(web::resource("/api/analyzers").route(web::get().to(list_analyzers)))
+            .service(web::resource("/api/analyzers/{name}/run").route(web::post().to(rerun_analyzer)))
+            .service(web::resource("/api/reprocess").route(web::post().to(reprocess_images)))
+            .service(web::resource("/api/groups/rebuild").route(web::post().to(regroup_bursts)))
+            .service(web::resource("/api/jobs").route(web::get().to(list_jobs)))
+            .service(web::resource("/api/jobs/{id}").route(web::get().to(get_job)))
+            .service(web::resource("/api/jobs/{id}/report").route(web::get().to(get_job_report)))
+            .service(
+                web::resource("/api/trash")
+                    .route(web::get().to(list_trash))
+                    .route(web::delete().to(empty_trash)),
+            )
+            .service(
+                web::resource("/api/trash/{id}/restore")
+                    .route(web::post().to(restore_from_trash)),
+            )
             .service(
                 web::resource("/api/indexer/start")
                     .route(web::post().to(start_indexing_handler)),
@@ -0,0 +1,767 @@
+use crate::annotations::Annotations;
+use crate::error::AppError;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use quick_xml::events::{BytesEnd, BytesPI, BytesStart, BytesText, Event};
+use quick_xml::name::ResolveResult;
+use quick_xml::reader::NsReader;
+use quick_xml::writer::Writer;
+
+const RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XML: &str = "http://www.w3.org/XML/1998/namespace";
+const DC: &str = "http://purl.org/dc/elements/1.1/";
+const XMP: &str = "http://ns.adobe.com/xap/1.0/";
+const PHOTOSHOP: &str = "http://ns.adobe.com/photoshop/1.0/";
+const MWG_RS: &str = "http://www.metadataworkinggroup.com/schemas/regions/";
+const ST_AREA: &str = "http://ns.adobe.com/xmp/sType/Area#";
+const X_META: &str = "adobe:ns:meta/";
+
+/// Embedded packets sit in the file header for every format we index, so only the start is scanned.
+const EMBEDDED_SCAN_BYTES: u64 = 4 * 1024 * 1024;
+
+/// Keywords, rating, caption and regions from an embedded XMP packet and/or a `.xmp` sidecar.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct XmpMetadata {
+    /// `dc:subject`
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// `xmp:Rating`, -1 (rejected) to 5.
+    #[serde(default)]
+    pub rating: Option<i8>,
+    /// `dc:description`
+    #[serde(default)]
+    pub description: Option<String>,
+    /// `photoshop:Headline`
+    #[serde(default)]
+    pub headline: Option<String>,
+    /// MWG face and object regions.
+    #[serde(default)]
+    pub regions: Vec<XmpRegion>,
+}
+
+/// A named area of the image, in coordinates normalized to 0..1 around its centre.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct XmpRegion {
+    pub name: Option<String>,
+    /// `Face`, `Pet`, `Focus`, `BarCode`, ...
+    pub kind: Option<String>,
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+}
+
+impl XmpMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == XmpMetadata::default()
+    }
+
+    /// Fills in whatever `other` has, letting it win where both have a value.
+    fn merge(&mut self, other: XmpMetadata) {
+        if !other.keywords.is_empty() {
+            self.keywords = other.keywords;
+        }
+        if other.rating.is_some() {
+            self.rating = other.rating;
+        }
+        if other.description.is_some() {
+            self.description = other.description;
+        }
+        if other.headline.is_some() {
+            self.headline = other.headline;
+        }
+        if !other.regions.is_empty() {
+            self.regions = other.regions;
+        }
+    }
+}
+
+/// Reads the embedded packet of `image` and then its sidecar, which takes precedence as editors
+/// like Lightroom and darktable write their changes there.
+pub fn read_for_image(image: &Path) -> XmpMetadata {
+    let mut metadata = XmpMetadata::default();
+    match read_embedded(image) {
+        Ok(Some(embedded)) => metadata.merge(embedded),
+        Ok(None) => {}
+        Err(e) => log::debug!("Could not read embedded XMP of {:?}: {}", image, e),
+    }
+    if let Some(sidecar) = existing_sidecar(image) {
+        match std::fs::read_to_string(&sidecar) {
+            Ok(xml) => metadata.merge(parse(&xml)),
+            Err(e) => log::warn!("Could not read XMP sidecar {:?}: {}", sidecar, e),
+        }
+    }
+    metadata
+}
+
+fn read_embedded(image: &Path) -> Result<Option<XmpMetadata>, AppError> {
+    let mut bytes = Vec::new();
+    File::open(image)?.take(EMBEDDED_SCAN_BYTES).read_to_end(&mut bytes)?;
+    let Some(start) = find(&bytes, b"<x:xmpmeta") else {
+        return Ok(None);
+    };
+    let end = find(&bytes[start..], b"</x:xmpmeta>")
+        .map(|end| start + end + b"</x:xmpmeta>".len())
+        .unwrap_or(bytes.len());
+    Ok(Some(parse(&String::from_utf8_lossy(&bytes[start..end]))))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// `photo.jpg.xmp` (darktable) or `photo.xmp` (Lightroom), whichever exists.
+fn existing_sidecar(image: &Path) -> Option<PathBuf> {
+    [appended_sidecar(image), image.with_extension("xmp")].into_iter().find(|p| p.is_file())
+}
+
+fn appended_sidecar(image: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.xmp", image.to_string_lossy()))
+}
+
+/// The sidecar annotations of `image` are written to. `photo.xmp` is only used when no other file
+/// shares it, so changing the JPEG of a RAW+JPEG pair never rewrites the RAW's sidecar.
+fn sidecar_for_write(image: &Path) -> PathBuf {
+    let appended = appended_sidecar(image);
+    if appended.is_file() || has_sibling(image) {
+        appended
+    } else {
+        image.with_extension("xmp")
+    }
+}
+
+/// Whether another file next to `image` has the same name with a different extension, like `photo.cr2`
+/// next to `photo.jpg`.
+fn has_sibling(image: &Path) -> bool {
+    let (Some(parent), Some(stem)) = (image.parent(), image.file_stem()) else {
+        return false;
+    };
+    let Ok(entries) = std::fs::read_dir(parent) else {
+        return false;
+    };
+    entries.filter_map(|e| e.ok()).map(|e| e.path()).any(|path| {
+        path.file_stem() == Some(stem)
+            && path.file_name() != image.file_name()
+            && !path.extension().is_some_and(|e| e.eq_ignore_ascii_case("xmp"))
+    })
+}
+
+/// Parses an XMP packet. Anything unexpected is skipped rather than treated as an error.
+pub fn parse(xml: &str) -> XmpMetadata {
+    let root = match Document::parse(xml) {
+        Some(root) => root,
+        None => return XmpMetadata::default(),
+    };
+    let descriptions = root.descriptions();
+    let property = |ns: &str, local: &str| descriptions.iter().find_map(|d| d.field(ns, local));
+
+    let keywords = property(DC, "subject")
+        .map(|p| p.items().into_iter().filter_map(|li| li.text()).collect())
+        .unwrap_or_default();
+    let rating = property(XMP, "Rating")
+        .and_then(|p| p.text())
+        .and_then(|r| r.parse::<f64>().ok())
+        .map(|r| r.clamp(-1.0, 5.0) as i8);
+    let description = property(DC, "description").and_then(|p| {
+        let items = p.items();
+        let default = items.iter().find(|li| li.attr(XML, "lang") == Some("x-default"));
+        default.or(items.first()).and_then(|li| li.text())
+    });
+    let headline = property(PHOTOSHOP, "Headline").and_then(|p| p.text());
+    let regions = property(MWG_RS, "Regions")
+        .and_then(|p| p.field(MWG_RS, "RegionList"))
+        .map(|list| list.items().into_iter().filter_map(region).collect())
+        .unwrap_or_default();
+
+    XmpMetadata {
+        keywords,
+        rating,
+        description,
+        headline,
+        regions,
+    }
+}
+
+fn region(item: Property) -> Option<XmpRegion> {
+    let area = item.field(MWG_RS, "Area")?;
+    let coordinate = |name: &str| area.field(ST_AREA, name)?.text()?.parse::<f64>().ok();
+    Some(XmpRegion {
+        name: item.field(MWG_RS, "Name").and_then(|p| p.text()),
+        kind: item.field(MWG_RS, "Type").and_then(|p| p.text()),
+        x: coordinate("x")?,
+        y: coordinate("y")?,
+        w: coordinate("w")?,
+        h: coordinate("h")?,
+    })
+}
+
+/// Mirrors an annotation change into the sidecar of `image`, creating one if there is none.
+/// Only the fields that differ between `before` and `after` are touched: added tags are merged into
+/// `dc:subject` and removed ones taken out of it, so keywords set by other tools stay. A sidecar that
+/// cannot be parsed is left alone rather than replaced.
+pub fn write_sidecar(image: &Path, before: &Annotations, after: &Annotations) -> Result<PathBuf, AppError> {
+    let sidecar = sidecar_for_write(image);
+    let rating_changed = before.rating != after.rating;
+    let label_changed = before.color_label != after.color_label;
+    let tags_changed = before.tags != after.tags;
+    if !rating_changed && !label_changed && !tags_changed {
+        return Ok(sidecar);
+    }
+    let mut root = match std::fs::read_to_string(&sidecar) {
+        Ok(xml) => Document::parse(&xml).ok_or_else(|| {
+            AppError::Generic(format!("XMP sidecar {:?} could not be parsed and was left untouched", sidecar))
+        })?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Document::empty_packet(),
+        Err(e) => return Err(AppError::Io(e)),
+    };
+
+    let xmp = root.prefix_for(XMP, "xmp");
+    let dc = root.prefix_for(DC, "dc");
+    let rdf = root.prefix_for(RDF, "rdf");
+
+    if rating_changed {
+        let description = root.description_mut(&rdf, XMP, "Rating");
+        description.remove_property(XMP, "Rating");
+        if after.rating > 0 {
+            description.declare(&xmp, XMP);
+            description.set_attr(&xmp, XMP, "Rating", &after.rating.to_string());
+        }
+    }
+    if label_changed {
+        let description = root.description_mut(&rdf, XMP, "Label");
+        description.remove_property(XMP, "Label");
+        if let Some(label) = after.color_label {
+            let label = label.as_str();
+            let label = format!("{}{}", label[..1].to_uppercase(), &label[1..]);
+            description.declare(&xmp, XMP);
+            description.set_attr(&xmp, XMP, "Label", &label);
+        }
+    }
+    if tags_changed {
+        let description = root.description_mut(&rdf, DC, "subject");
+        let mut keywords: Vec<String> = description
+            .property(DC, "subject")
+            .map(|p| p.items().into_iter().filter_map(|li| li.text()).collect())
+            .unwrap_or_default();
+        keywords.retain(|k| after.tags.contains(k) || !before.tags.contains(k));
+        for tag in &after.tags {
+            if !keywords.contains(tag) {
+                keywords.push(tag.clone());
+            }
+        }
+        description.remove_property(DC, "subject");
+        if !keywords.is_empty() {
+            let mut bag = Element::new(&rdf, RDF, "Bag");
+            for keyword in keywords {
+                let mut li = Element::new(&rdf, RDF, "li");
+                li.text = keyword;
+                bag.children.push(li);
+            }
+            let mut subject = Element::new(&dc, DC, "subject");
+            subject.children.push(bag);
+            description.declare(&dc, DC);
+            description.children.push(subject);
+        }
+    }
+
+    let tmp_path = sidecar.with_extension("xmp.tmp");
+    std::fs::write(&tmp_path, root.to_xml()?)?;
+    std::fs::rename(&tmp_path, &sidecar)?;
+    log::debug!("Wrote XMP sidecar {:?}", sidecar);
+    Ok(sidecar)
+}
+
+/// A namespace-resolved XML element. XMP only uses element content or text, never mixed content.
+#[derive(Debug, Clone)]
+struct Element {
+    qname: String,
+    ns: String,
+    local: String,
+    attrs: Vec<Attribute>,
+    children: Vec<Element>,
+    text: String,
+}
+
+#[derive(Debug, Clone)]
+struct Attribute {
+    qname: String,
+    ns: String,
+    local: String,
+    value: String,
+}
+
+/// A property value: a plain attribute or an element with text, an array or a struct below it.
+#[derive(Clone, Copy)]
+enum Property<'a> {
+    Attr(&'a str),
+    Elem(&'a Element),
+}
+
+impl<'a> Property<'a> {
+    fn text(&self) -> Option<String> {
+        let text = match self {
+            Property::Attr(value) => value.trim(),
+            Property::Elem(element) => element
+                .attr(RDF, "resource")
+                .unwrap_or(element.text.trim()),
+        };
+        (!text.is_empty()).then(|| text.to_string())
+    }
+
+    fn attr(&self, ns: &str, local: &str) -> Option<&'a str> {
+        match self {
+            Property::Attr(_) => None,
+            Property::Elem(element) => element.attr(ns, local),
+        }
+    }
+
+    /// The `rdf:li` entries of a Bag, Seq or Alt, or the value itself if it is not an array.
+    fn items(&self) -> Vec<Property<'a>> {
+        if let Property::Elem(element) = self {
+            let array = element
+                .children
+                .iter()
+                .find(|c| c.ns == RDF && matches!(c.local.as_str(), "Bag" | "Seq" | "Alt"));
+            if let Some(array) = array {
+                return array
+                    .children
+                    .iter()
+                    .filter(|c| c.is(RDF, "li"))
+                    .map(Property::Elem)
+                    .collect();
+            }
+        }
+        vec![*self]
+    }
+
+    /// A field of a struct value, in any of the shapes RDF allows for it.
+    fn field(&self, ns: &str, local: &str) -> Option<Property<'a>> {
+        match self {
+            Property::Attr(_) => None,
+            Property::Elem(element) => element.property(ns, local),
+        }
+    }
+}
+
+impl Element {
+    fn new(prefix: &str, ns: &str, local: &str) -> Self {
+        Element {
+            qname: format!("{}:{}", prefix, local),
+            ns: ns.to_string(),
+            local: local.to_string(),
+            attrs: Vec::new(),
+            children: Vec::new(),
+            text: String::new(),
+        }
+    }
+
+    /// Builds an element from a start tag, resolving its name and attributes in the reader's scope.
+    fn from_start(reader: &NsReader<&[u8]>, ns: String, start: &BytesStart) -> Option<Element> {
+        let qname = String::from_utf8(start.name().as_ref().to_vec()).ok()?;
+        let local = String::from_utf8(start.local_name().as_ref().to_vec()).ok()?;
+        let mut attrs = Vec::new();
+        for attr in start.attributes() {
+            let attr = attr.ok()?;
+            let qname = String::from_utf8(attr.key.as_ref().to_vec()).ok()?;
+            let (ns, local) = if attr.key.as_namespace_binding().is_some() {
+                (String::new(), String::new())
+            } else {
+                let (ns, local) = reader.resolve_attribute(attr.key);
+                (namespace_uri(ns), String::from_utf8(local.as_ref().to_vec()).ok()?)
+            };
+            let value = attr.unescape_value().ok()?.into_owned();
+            attrs.push(Attribute { qname, ns, local, value });
+        }
+        Some(Element {
+            qname,
+            ns,
+            local,
+            attrs,
+            children: Vec::new(),
+            text: String::new(),
+        })
+    }
+
+    fn is(&self, ns: &str, local: &str) -> bool {
+        self.ns == ns && self.local == local
+    }
+
+    fn attr(&self, ns: &str, local: &str) -> Option<&str> {
+        self.attrs
+            .iter()
+            .find(|a| a.ns == ns && a.local == local)
+            .map(|a| a.value.as_str())
+    }
+
+    /// Looks for `ns:local` as an attribute, a child element, or inside a nested `rdf:Description`.
+    fn property(&self, ns: &str, local: &str) -> Option<Property<'_>> {
+        if let Some(value) = self.attr(ns, local) {
+            return Some(Property::Attr(value));
+        }
+        if let Some(child) = self.children.iter().find(|c| c.is(ns, local)) {
+            return Some(Property::Elem(child));
+        }
+        self.children
+            .iter()
+            .filter(|c| c.is(RDF, "Description"))
+            .find_map(|d| d.property(ns, local))
+    }
+
+    fn declare(&mut self, prefix: &str, ns: &str) {
+        let qname = format!("xmlns:{}", prefix);
+        if !self.attrs.iter().any(|a| a.qname == qname) {
+            self.attrs.push(Attribute {
+                qname,
+                ns: String::new(),
+                local: String::new(),
+                value: ns.to_string(),
+            });
+        }
+    }
+
+    fn set_attr(&mut self, prefix: &str, ns: &str, local: &str, value: &str) {
+        self.attrs.push(Attribute {
+            qname: format!("{}:{}", prefix, local),
+            ns: ns.to_string(),
+            local: local.to_string(),
+            value: value.to_string(),
+        });
+    }
+
+    fn remove_property(&mut self, ns: &str, local: &str) {
+        self.attrs.retain(|a| !(a.ns == ns && a.local == local));
+        self.children.retain(|c| !c.is(ns, local));
+    }
+
+    fn write(&self, writer: &mut Writer<Vec<u8>>) -> std::io::Result<()> {
+        let mut start = BytesStart::new(self.qname.as_str());
+        for attr in &self.attrs {
+            start.push_attribute((attr.qname.as_str(), attr.value.as_str()));
+        }
+        if self.children.is_empty() && self.text.trim().is_empty() {
+            return writer.write_event(Event::Empty(start));
+        }
+        writer.write_event(Event::Start(start))?;
+        if self.children.is_empty() {
+            writer.write_event(Event::Text(BytesText::new(&self.text)))?;
+        }
+        for child in &self.children {
+            child.write(writer)?;
+        }
+        writer.write_event(Event::End(BytesEnd::new(self.qname.as_str())))
+    }
+}
+
+fn namespace_uri(ns: ResolveResult) -> String {
+    match ns {
+        ResolveResult::Bound(ns) => String::from_utf8_lossy(ns.as_ref()).to_string(),
+        ResolveResult::Unbound | ResolveResult::Unknown(_) => String::new(),
+    }
+}
+
+/// The root of a parsed packet, along with every namespace binding seen in it.
+struct Document {
+    root: Element,
+    namespaces: HashMap<String, String>,
+}
+
+impl Document {
+    /// Reads the first element of `xml` and everything below it, or `None` if it is not well-formed.
+    fn parse(xml: &str) -> Option<Document> {
+        let mut reader = NsReader::from_str(xml);
+        let mut namespaces = HashMap::from([("xml".to_string(), XML.to_string())]);
+        let mut open: Vec<Element> = Vec::new();
+        loop {
+            let (ns, event) = reader.read_resolved_event().ok()?;
+            let ns = namespace_uri(ns);
+            let finished = match event {
+                Event::Start(start) => {
+                    open.push(Element::from_start(&reader, ns, &start)?);
+                    None
+                }
+                Event::Empty(start) => Some(Element::from_start(&reader, ns, &start)?),
+                Event::End(_) => Some(open.pop()?),
+                Event::Text(text) => {
+                    if let Some(element) = open.last_mut() {
+                        element.text.push_str(&text.unescape().ok()?);
+                    }
+                    None
+                }
+                Event::CData(data) => {
+                    if let Some(element) = open.last_mut() {
+                        element.text.push_str(std::str::from_utf8(&data).ok()?);
+                    }
+                    None
+                }
+                Event::Eof => return None,
+                _ => None,
+            };
+            let Some(element) = finished else { continue };
+            for attr in element.attrs.iter().filter(|a| a.ns.is_empty() && a.local.is_empty()) {
+                let prefix = attr.qname.strip_prefix("xmlns:").unwrap_or_default();
+                namespaces.entry(prefix.to_string()).or_insert_with(|| attr.value.clone());
+            }
+            match open.last_mut() {
+                Some(parent) => parent.children.push(element),
+                None => return Some(Document { root: element, namespaces }),
+            }
+        }
+    }
+
+    fn empty_packet() -> Document {
+        let mut root = Element::new("x", X_META, "xmpmeta");
+        root.declare("x", X_META);
+        let mut rdf = Element::new("rdf", RDF, "RDF");
+        rdf.declare("rdf", RDF);
+        root.children.push(rdf);
+        Document {
+            root,
+            namespaces: HashMap::from([("x".to_string(), X_META.to_string()), ("rdf".to_string(), RDF.to_string())]),
+        }
+    }
+
+    /// The top-level `rdf:Description` elements, which hold the properties.
+    fn descriptions(&self) -> Vec<Property<'_>> {
+        let mut descriptions = Vec::new();
+        let mut stack = vec![&self.root];
+        while let Some(element) = stack.pop() {
+            if element.is(RDF, "RDF") {
+                descriptions.extend(
+                    element
+                        .children
+                        .iter()
+                        .filter(|c| c.is(RDF, "Description"))
+                        .map(Property::Elem),
+                );
+            } else {
+                stack.extend(element.children.iter());
+            }
+        }
+        descriptions
+    }
+
+    /// The prefix the document already uses for `ns`, or `preferred` when it has none.
+    fn prefix_for(&mut self, ns: &str, preferred: &str) -> String {
+        if let Some((prefix, _)) = self.namespaces.iter().find(|(_, uri)| uri.as_str() == ns) {
+            return prefix.clone();
+        }
+        let mut prefix = preferred.to_string();
+        while self.namespaces.contains_key(&prefix) {
+            prefix.push('1');
+        }
+        self.namespaces.insert(prefix.clone(), ns.to_string());
+        prefix
+    }
+
+    /// The top-level `rdf:Description` that holds `ns:local`, or else the first one, created if missing.
+    fn description_mut(&mut self, rdf_prefix: &str, ns: &str, local: &str) -> &mut Element {
+        if !self.root.is(RDF, "RDF") && !self.root.children.iter().any(|c| c.is(RDF, "RDF")) {
+            let mut rdf = Element::new(rdf_prefix, RDF, "RDF");
+            rdf.declare(rdf_prefix, RDF);
+            self.root.children.push(rdf);
+        }
+        let rdf = if self.root.is(RDF, "RDF") {
+            &mut self.root
+        } else {
+            self.root.children.iter_mut().find(|c| c.is(RDF, "RDF")).unwrap()
+        };
+        if !rdf.children.iter().any(|c| c.is(RDF, "Description")) {
+            let mut description = Element::new(rdf_prefix, RDF, "Description");
+            description.set_attr(rdf_prefix, RDF, "about", "");
+            rdf.children.push(description);
+        }
+        let holder = rdf
+            .children
+            .iter()
+            .position(|c| c.is(RDF, "Description") && (c.attr(ns, local).is_some() || c.children.iter().any(|p| p.is(ns, local))));
+        match holder {
+            Some(index) => &mut rdf.children[index],
+            None => rdf.children.iter_mut().find(|c| c.is(RDF, "Description")).unwrap(),
+        }
+    }
+
+    fn to_xml(&self) -> Result<String, AppError> {
+        let mut writer = Writer::new_with_indent(Vec::new(), b' ', 1);
+        writer.write_event(Event::PI(BytesPI::new("xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"")))?;
+        self.root.write(&mut writer)?;
+        writer.write_event(Event::PI(BytesPI::new("xpacket end=\"w\"")))?;
+        let mut xml = String::from_utf8(writer.into_inner()).map_err(|e| AppError::Generic(e.to_string()))?;
+        xml.push('\n');
+        Ok(xml)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::annotations::ColorLabel;
+
+    const LIGHTROOM: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="Adobe XMP Core 7.0-c000 1.000000, 0000/00/00-00:00:00        ">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+    xmlns:lr="http://ns.adobe.com/lightroom/1.0/"
+   xmp:Rating="4"
+   xmp:Label="Red"
+   crs:Version="15.0"
+   crs:Exposure2012="+0.35"
+   crs:WhiteBalance="As Shot">
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>beach</rdf:li>
+     <rdf:li>family &amp; friends</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+   <lr:hierarchicalSubject>
+    <rdf:Bag>
+     <rdf:li>places|beach</rdf:li>
+    </rdf:Bag>
+   </lr:hierarchicalSubject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    const DARKTABLE: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="XMP Core 4.4.0-Exiv2">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:exif="http://ns.adobe.com/exif/1.0/"
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:xmpMM="http://ns.adobe.com/xap/1.0/mm/"
+    xmlns:darktable="http://darktable.sf.net/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+   exif:DateTimeOriginal="2023:07:14 18:02:11"
+   xmp:Rating="1"
+   xmpMM:DerivedFrom="IMG_1234.CR2"
+   darktable:xmp_version="5"
+   darktable:history_end="2">
+   <darktable:history>
+    <rdf:Seq>
+     <rdf:li
+      darktable:num="0"
+      darktable:operation="exposure"
+      darktable:enabled="1"
+      darktable:params="0000000000000000"/>
+     <rdf:li
+      darktable:num="1"
+      darktable:operation="colorbalancergb"
+      darktable:enabled="1"
+      darktable:params="gz12eJxjYGBgYAFiCQY"/>
+    </rdf:Seq>
+   </darktable:history>
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>darktable|format|cr2</rdf:li>
+     <rdf:li>mountains</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+    /// Copies `sidecar` next to a fresh image path and returns the image path.
+    fn image_with_sidecar(name: &str, sidecar_name: &str, sidecar: Option<&str>) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xmp-test-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        if let Some(sidecar) = sidecar {
+            std::fs::write(dir.join(sidecar_name), sidecar).unwrap();
+        }
+        dir.join("photo.jpg")
+    }
+
+    fn annotations(tags: &[&str], rating: u8, color_label: Option<ColorLabel>) -> Annotations {
+        Annotations {
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+            rating,
+            color_label,
+            favorite: false,
+        }
+    }
+
+    #[test]
+    fn reads_lightroom_and_darktable_sidecars() {
+        let lightroom = parse(LIGHTROOM);
+        assert_eq!(lightroom.keywords, ["beach", "family & friends"]);
+        assert_eq!(lightroom.rating, Some(4));
+
+        let darktable = parse(DARKTABLE);
+        assert_eq!(darktable.keywords, ["darktable|format|cr2", "mountains"]);
+        assert_eq!(darktable.rating, Some(1));
+    }
+
+    #[test]
+    fn adding_a_tag_keeps_lightroom_keywords_and_settings() {
+        let image = image_with_sidecar("lightroom", "photo.xmp", Some(LIGHTROOM));
+        let before = annotations(&["beach"], 0, None);
+        let after = annotations(&["beach", "sunset"], 0, None);
+        let sidecar = write_sidecar(&image, &before, &after).unwrap();
+
+        let xml = std::fs::read_to_string(&sidecar).unwrap();
+        let written = parse(&xml);
+        assert_eq!(written.keywords, ["beach", "family & friends", "sunset"]);
+        // The rating did not change, so Lightroom's stays even though ours is unrated.
+        assert_eq!(written.rating, Some(4));
+        let document = Document::parse(&xml).unwrap();
+        let description = document.descriptions()[0];
+        assert_eq!(description.attr(XMP, "Label"), Some("Red"));
+        assert_eq!(description.attr("http://ns.adobe.com/camera-raw-settings/1.0/", "Exposure2012"), Some("+0.35"));
+        assert!(description.field("http://ns.adobe.com/lightroom/1.0/", "hierarchicalSubject").is_some());
+    }
+
+    #[test]
+    fn removing_a_tag_and_rating_keeps_darktable_history() {
+        let image = image_with_sidecar("darktable", "photo.jpg.xmp", Some(DARKTABLE));
+        let before = annotations(&["mountains"], 3, None);
+        let after = annotations(&[], 5, Some(ColorLabel::Green));
+        let sidecar = write_sidecar(&image, &before, &after).unwrap();
+        assert_eq!(sidecar, image.with_extension("jpg.xmp"));
+
+        let xml = std::fs::read_to_string(&sidecar).unwrap();
+        let written = parse(&xml);
+        assert_eq!(written.keywords, ["darktable|format|cr2"]);
+        assert_eq!(written.rating, Some(5));
+        let document = Document::parse(&xml).unwrap();
+        let description = document.descriptions()[0];
+        assert_eq!(description.attr(XMP, "Label"), Some("Green"));
+        assert_eq!(description.attr("http://ns.adobe.com/xap/1.0/mm/", "DerivedFrom"), Some("IMG_1234.CR2"));
+        let history = description.field("http://darktable.sf.net/", "history").unwrap().items();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].attr("http://darktable.sf.net/", "operation"), Some("colorbalancergb"));
+    }
+
+    #[test]
+    fn creates_a_sidecar_when_there_is_none() {
+        let image = image_with_sidecar("new", "photo.xmp", None);
+        let sidecar = write_sidecar(&image, &Annotations::default(), &annotations(&["a & b"], 2, None)).unwrap();
+        let written = parse(&std::fs::read_to_string(sidecar).unwrap());
+        assert_eq!(written.keywords, ["a & b"]);
+        assert_eq!(written.rating, Some(2));
+    }
+
+    #[test]
+    fn refuses_to_overwrite_a_sidecar_that_does_not_parse() {
+        let broken = "<x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF";
+        let image = image_with_sidecar("broken", "photo.xmp", Some(broken));
+        let result = write_sidecar(&image, &Annotations::default(), &annotations(&["tag"], 0, None));
+        assert!(result.is_err());
+        assert_eq!(std::fs::read_to_string(image.with_extension("xmp")).unwrap(), broken);
+    }
+
+    #[test]
+    fn leaves_the_sidecar_of_a_raw_sibling_alone() {
+        let image = image_with_sidecar("raw-pair", "photo.xmp", Some(LIGHTROOM));
+        std::fs::write(image.with_extension("cr2"), b"raw").unwrap();
+        let sidecar = write_sidecar(&image, &Annotations::default(), &annotations(&["jpeg only"], 0, None)).unwrap();
+        assert_eq!(sidecar, image.with_extension("jpg.xmp"));
+        assert_eq!(parse(&std::fs::read_to_string(&sidecar).unwrap()).keywords, ["jpeg only"]);
+        assert_eq!(std::fs::read_to_string(image.with_extension("xmp")).unwrap(), LIGHTROOM);
+    }
+}
@@ -15,6 +15,8 @@ document.addEventListener('DOMContentLoaded', () => {
     const settingsModal = document.getElementById('settingsModal');
     const closeSettingsModal = document.querySelector('#settingsModal .close-settings');
     const totalImagesSpan = document.getElementById('totalImages');
+    const placesButton = document.getElementById('places');
+    const allImagesButton = document.getElementById('allImages');
 
     let allImages = [];
     let currentlyDisplayedImages = [];
@@ -95,6 +97,8 @@ document.addEventListener('DOMContentLoaded', () => {
                 ${image.camera_model ? `<p><strong>Model:</strong> ${image.camera_model}</p>` : ''}
                 ${image.date_taken ? `<p><strong>Date:</strong> ${image.date_taken}</p>` : ''}
                 ${image.gps_latitude && image.gps_longitude ? `<p><strong>GPS:</strong> ${image.gps_latitude.toFixed(4)}, ${image.gps_longitude.toFixed(4)}</p>` : ''}
+                ${image.place ? `<p><strong>Place:</strong> ${image.place.city}, ${image.place.country}</p>` : ''}
+                ${image.colors ? `<p class="palette">${image.colors.palette.map(p => `<span class="swatch" style="background-color: ${p.color}" title="${p.color}"></span>`).join('')}</p>` : ''}
                 ${duplicatesHTML}
             `;
 
@@ -109,6 +113,65 @@ document.addEventListener('DOMContentLoaded', () => {
         });
     }
 
+    // --- Places ---
+    async function showPlaces() {
+        try {
+            imageGrid.innerHTML = '<p>Loading places...</p>';
+            const response = await fetch('/api/places');
+            if (!response.ok) {
+                throw new Error(`HTTP error! status: ${response.status}`);
+            }
+            const countries = await response.json();
+            if (countries.length === 0) {
+                imageGrid.innerHTML = '<p>No places found. Configure a gazetteer and reindex geotagged images.</p>';
+                return;
+            }
+            const list = document.createElement('ul');
+            list.classList.add('places');
+            countries.forEach(country => {
+                const countryItem = document.createElement('li');
+                countryItem.innerHTML = `<strong>${country.country}</strong> (${country.image_count})`;
+                const regions = document.createElement('ul');
+                country.regions.forEach(region => {
+                    region.cities.forEach(city => {
+                        const cityItem = document.createElement('li');
+                        const link = document.createElement('a');
+                        link.href = '#';
+                        link.textContent = region.region ? `${city.city}, ${region.region}` : city.city;
+                        link.addEventListener('click', (e) => {
+                            e.preventDefault();
+                            showPlace(country.country_code, city.city);
+                        });
+                        cityItem.appendChild(link);
+                        cityItem.append(` (${city.image_count})`);
+                        regions.appendChild(cityItem);
+                    });
+                });
+                countryItem.appendChild(regions);
+                list.appendChild(countryItem);
+            });
+            imageGrid.innerHTML = '';
+            imageGrid.appendChild(list);
+        } catch (error) {
+            console.error('Error fetching places:', error);
+            imageGrid.innerHTML = `<p>Error loading places: ${error.message}</p>`;
+        }
+    }
+
+    async function showPlace(countryCode, city) {
+        try {
+            const params = new URLSearchParams({ place_country: countryCode, place_city: city });
+            const response = await fetch(`/api/images?${params}`);
+            if (!response.ok) {
+                throw new Error(`HTTP error! status: ${response.status}`);
+            }
+            displayImages(await response.json());
+        } catch (error) {
+            console.error('Error fetching images of place:', error);
+            imageGrid.innerHTML = `<p>Error loading images: ${error.message}</p>`;
+        }
+    }
+
     // --- Delete Duplicates ---
     function handleDeleteClick(event) {
         event.stopPropagation(); // Prevent card click event
@@ -134,8 +197,8 @@ document.addEventListener('DOMContentLoaded', () => {
                 e.stopPropagation();
                 const mode = e.target.dataset.mode;
                 const message = mode === 'keep-one' 
-                    ? `Are you sure you want to delete ${count} duplicate(s)? One copy will be kept.`
-                    : `Are you sure you want to delete all ${count + 1} images (including the original)? This action cannot be undone.`;
+                    ? `Are you sure you want to move ${count} duplicate(s) to the trash? One copy will be kept.`
+                    : `Are you sure you want to move all ${count + 1} images (including the original) to the trash?`;
 
                 if (confirm(message)) {
                     deleteDuplicates(hash, mode);
@@ -165,7 +228,11 @@ document.addEventListener('DOMContentLoaded', () => {
             }
 
             const result = await response.json();
-            alert(result.message || 'Successfully deleted files.');
+            if (result.failed && result.failed.length > 0) {
+                alert(`Moved ${result.trashed.length} file(s) to the trash, ${result.failed.length} could not be moved.`);
+            } else {
+                alert(`Moved ${result.trashed.length} file(s) to the trash.`);
+            }
             fetchImages(searchInput.value); // Refresh the view
         } catch (error) {
             console.error('Error deleting duplicates:', error);
@@ -243,6 +310,12 @@ document.addEventListener('DOMContentLoaded', () => {
         }
     });
 
+    placesButton.addEventListener('click', showPlaces);
+    allImagesButton.addEventListener('click', () => {
+        searchInput.value = '';
+        performSearch();
+    });
+
     // Image Modal Listeners
     closeImageModal.addEventListener('click', closeImageModalFunction);
     prevButton.addEventListener('click', showPrevImage);